@@ -8,4 +8,7 @@ pub mod prelude {
     pub use crate::class::*;
 }
 
-lalrpop_mod!(grammar);
+lalrpop_mod!(
+    #[allow(clippy::all)]
+    grammar
+);
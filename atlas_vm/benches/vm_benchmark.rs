@@ -1,39 +1,40 @@
-use atlas_vm::{instruction::compiler::parser::Parser, runtime::VM};
+use atlas_vm::{
+    instruction::compiler::parser::{Parser, Program},
+    runtime::VM,
+};
 use criterion::{criterion_group, criterion_main, Criterion};
 
+fn compile(path: &'static str) -> Program {
+    let content = std::fs::read_to_string(path).expect("Can't read the file");
+    let mut lexer = atlas_vm::instruction::compiler::lexer::AtlasLexer::default();
+    lexer.set_path(path);
+    lexer.set_source(content);
+    lexer.add_system(atlas_vm::instruction::compiler::lexer::identifier_system);
+    lexer.add_system(atlas_vm::instruction::compiler::lexer::comment_system);
+    lexer.add_system(atlas_vm::instruction::compiler::lexer::negative_number_system);
+    lexer.add_system(atlas_vm::instruction::compiler::lexer::string_system);
+    let tokens = lexer.tokenize().expect("Can't tokenize the file");
+    Parser::parse(tokens, lexer.lines()).expect("Can't parse the file")
+}
+
 fn vm_test_benchmark(c: &mut Criterion) {
+    let code = compile("./examples/fib.txt");
     c.bench_function("vm_instruction", |b| {
         b.iter(|| {
-            if let Ok(content) = std::fs::read_to_string("./examples/fib.txt") {
-                let mut lexer = atlas_vm::instruction::compiler::lexer::AtlasLexer::default();
-                lexer.set_path("examples/fib.txt");
-                lexer.set_source(content);
-                lexer.add_system(atlas_vm::instruction::compiler::lexer::identifier_system);
-                lexer.add_system(atlas_vm::instruction::compiler::lexer::comment_system);
-                let res = lexer.tokenize();
-                match res {
-                    Ok(t) => {
-                        let parser = Parser::parse(t);
-                        match parser {
-                            Ok(code) => {
-                                let mut vm = VM::new(0, code.constants);
-                                vm.execute(code.ins.as_slice());
-                            }
-                            Err(e) => {
-                                panic!("{:?}", e);
-                            }
-                        };
-                    }
-                    Err(_e) => {
-                        println!("Error1");
-                    }
-                }
-            } else {
-                println!("Error2")
+            let mut vm = VM::new(1, code.constants.clone());
+            if let Err(e) = vm.execute(code.ins.as_slice()) {
+                panic!("{}", e);
             }
         })
     });
 }
 
-criterion_group!(benches, vm_test_benchmark);
+fn bytecode_load_benchmark(c: &mut Criterion) {
+    let bytes = compile("./examples/fib.txt").to_bytes();
+    c.bench_function("bytecode_load", |b| {
+        b.iter(|| Program::from_bytes(&bytes).expect("Invalid bytecode"))
+    });
+}
+
+criterion_group!(benches, vm_test_benchmark, bytecode_load_benchmark);
 criterion_main!(benches);
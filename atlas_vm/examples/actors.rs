@@ -0,0 +1,80 @@
+//! 4 producers & a consumer, each one a VM, exchanging messages over channels:
+//! `cargo run --example actors`
+
+use atlas_vm::prelude::*;
+
+const PRODUCERS: usize = 4;
+
+fn main() {
+    let numbers = Channel::new();
+    let total = Channel::new();
+    let mut workers = Workers::new(2);
+
+    let Some(mut consumer) = load("consumer.txt") else {
+        return;
+    };
+    consumer.add_channel(numbers.clone());
+    consumer.add_channel(total.clone());
+    workers.add(consumer);
+    for _ in 0..PRODUCERS {
+        let Some(mut producer) = load("producer.txt") else {
+            return;
+        };
+        producer.add_channel(numbers.clone());
+        workers.add(producer);
+    }
+
+    for (i, (_, outcome)) in workers.run().into_iter().enumerate() {
+        match outcome {
+            Ok(outcome) => println!("vm {}: {:?}", i, outcome),
+            Err(e) => println!("vm {}: {}", i, e),
+        }
+    }
+    // Messages are read through a VM, as they may hold objects
+    let mut host = VM::new(16, vec![]);
+    if let Some(message) = total.try_recv() {
+        match message.paste(&mut host.object_map) {
+            Ok(val) => println!("total: {}", val),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// A VM with the example `name` loaded
+fn load(name: &str) -> Option<VM> {
+    let path: &'static str =
+        Box::leak(format!("{}/examples/{}", env!("CARGO_MANIFEST_DIR"), name).into_boxed_str());
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("can't read {}: {}", path, e);
+            return None;
+        }
+    };
+    let mut lexer = AtlasLexer::default();
+    lexer.set_path(path);
+    lexer.set_source(content);
+    lexer.add_system(identifier_system);
+    lexer.add_system(comment_system);
+    lexer.add_system(negative_number_system);
+    lexer.add_system(string_system);
+    let Ok(tokens) = lexer.tokenize() else {
+        println!("can't tokenize {}", path);
+        return None;
+    };
+    let Ok(code) = Parser::parse(tokens, lexer.lines()) else {
+        println!("can't parse {}", path);
+        return None;
+    };
+    let mut vm = VM::new(16, code.constants.clone());
+    match vm.link(&code) {
+        Ok(ins) => {
+            vm.load(ins);
+            Some(vm)
+        }
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
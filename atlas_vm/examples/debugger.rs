@@ -0,0 +1,174 @@
+//! A tiny command line debugger: `cargo run --example debugger -- <file.txt>`
+//! then type `help` to list the commands.
+
+use std::io::{BufRead, Write};
+
+use atlas_vm::prelude::*;
+
+const HELP: &str = "\
+break <pc|label>  stop before an instruction (b)
+delete <pc>       remove a breakpoint (d)
+step              run the next instruction (s)
+next              same as step, but run calls until they return (n)
+out               run until the current call returns (o)
+continue          run until a breakpoint or the end (c)
+stack             print the stack
+bt                print the call stack
+consts            print the constants
+obj <index>       print an object of the object map
+reset             start over
+quit              exit (q)";
+
+fn main() {
+    let path: &'static str = match std::env::args().nth(1) {
+        Some(path) => Box::leak(path.into_boxed_str()),
+        None => {
+            println!("usage: debugger <file.txt>");
+            return;
+        }
+    };
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("can't read {}: {}", path, e);
+            return;
+        }
+    };
+    let mut lexer = AtlasLexer::default();
+    lexer.set_path(path);
+    lexer.set_source(content);
+    lexer.add_system(identifier_system);
+    lexer.add_system(comment_system);
+    lexer.add_system(negative_number_system);
+    lexer.add_system(string_system);
+    let Ok(tokens) = lexer.tokenize() else {
+        println!("can't tokenize {}", path);
+        return;
+    };
+    let Ok(code) = Parser::parse(tokens, lexer.lines()) else {
+        println!("can't parse {}", path);
+        return;
+    };
+    let mut vm = VM::new(16, code.constants.clone());
+    vm.add_extern_fn("fib", &[VMData::TAG_I64], fib_extern);
+    let ins = match vm.link(&code) {
+        Ok(ins) => ins,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut debugger = Debugger::new(&mut vm, &ins);
+    where_am_i(&debugger);
+    let stdin = std::io::stdin();
+    loop {
+        print!("(atlas) ");
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let arg = words.next();
+        let outcome = match command {
+            "break" | "b" => {
+                let pc = match arg.map(|a| a.parse::<usize>()) {
+                    Some(Ok(pc)) => debugger.add_breakpoint(pc).then_some(pc),
+                    Some(Err(_)) => debugger.add_breakpoint_at(arg.unwrap()),
+                    None => None,
+                };
+                match pc {
+                    Some(pc) => println!("breakpoint at [{}]", pc),
+                    None => println!("no such instruction"),
+                }
+                continue;
+            }
+            "delete" | "d" => {
+                match arg.and_then(|a| a.parse().ok()) {
+                    Some(pc) if debugger.remove_breakpoint(pc) => println!("removed"),
+                    _ => println!("no such breakpoint"),
+                }
+                continue;
+            }
+            "step" | "s" => debugger.step(),
+            "next" | "n" => debugger.step_over(),
+            "out" | "o" => debugger.step_out(),
+            "continue" | "c" => debugger.resume(),
+            "stack" => {
+                let stack: Vec<String> =
+                    debugger.vm().stack.iter().map(|v| v.to_string()).collect();
+                println!("[{}]", stack.join(", "));
+                continue;
+            }
+            "bt" => {
+                for frame in debugger.vm().backtrace() {
+                    println!("    {}", frame);
+                }
+                continue;
+            }
+            "consts" => {
+                for (i, c) in debugger.vm().constants().iter().enumerate() {
+                    println!("    #{} = {}", i, c);
+                }
+                continue;
+            }
+            "obj" => {
+                let object = arg
+                    .and_then(|a| a.parse().ok())
+                    .and_then(|i| debugger.vm().object_map.object(ObjectIndex::new(i)));
+                match object {
+                    Some(object) => println!("{}", object),
+                    None => println!("no such object"),
+                }
+                continue;
+            }
+            "reset" => {
+                debugger.reset();
+                where_am_i(&debugger);
+                continue;
+            }
+            "quit" | "q" => break,
+            _ => {
+                println!("{}", HELP);
+                continue;
+            }
+        };
+        match outcome {
+            Ok(Stop::Finished) => println!("program finished"),
+            Ok(Stop::Breakpoint(pc)) => {
+                println!("breakpoint at [{}]", pc);
+                where_am_i(&debugger);
+            }
+            Ok(Stop::Step) => where_am_i(&debugger),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Print the next instruction & where it comes from
+fn where_am_i(debugger: &Debugger) {
+    let Some(ins) = debugger.current() else {
+        println!("program finished");
+        return;
+    };
+    match debugger.vm().backtrace().first() {
+        Some(frame) => println!("{} {:?}", frame, ins),
+        None => println!("[{}] {:?}", debugger.vm().pc(), ins),
+    }
+}
+
+pub fn fib_extern(vm_state: VMState) -> Result<VMData, VMErrorKind> {
+    fn fib(n: i64) -> i64 {
+        if n < 2 {
+            n
+        } else {
+            fib(n - 1) + fib(n - 2)
+        }
+    }
+    let res = fib(vm_state.stack.pop()?.try_i64()?);
+    Ok(VMData::new_i64(res))
+}
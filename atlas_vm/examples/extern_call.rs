@@ -1,27 +1,40 @@
 use atlas_vm::instruction::compiler::parser::Parser;
 
+use atlas_vm::memory::vm_data::VMData;
+use atlas_vm::runtime::error::VMErrorKind;
+use atlas_vm::runtime::vm_state::VMState;
+
 use atlas_vm::runtime::VM;
 
 fn main() {
     let tmp = std::time::Instant::now();
-    if let Ok(content) = std::fs::read_to_string("./vm/examples/extern_call.txt") {
+    if let Ok(content) = std::fs::read_to_string("./atlas_vm/examples/extern_call.txt") {
         let mut lexer = atlas_vm::instruction::compiler::lexer::AtlasLexer::default();
         lexer.set_path("examples/extern_call.txt");
         lexer.set_source(content);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::identifier_system);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::comment_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::negative_number_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::string_system);
         let res = lexer.tokenize();
         match res {
             Ok(t) => {
                 println!("Ok Lexer: {:?}", tmp.elapsed());
                 let tmp = std::time::Instant::now();
-                let parser = Parser::parse(t);
+                let parser = Parser::parse(t, lexer.lines());
                 match parser {
                     Ok(code) => {
                         println!("Ok Parser: {:?}", tmp.elapsed());
                         let tmp = std::time::Instant::now();
-                        let mut vm = VM::new(0, code.constants);
-                        vm.execute(code.ins.as_slice());
+                        let mut vm = VM::new(16, code.constants.clone());
+                        vm.add_extern_fn("fib", &[VMData::TAG_I64], fib_extern);
+                        let ins = match vm.link(&code) {
+                            Ok(ins) => ins,
+                            Err(e) => panic!("{}", e),
+                        };
+                        if let Err(e) = vm.execute(ins.as_slice()) {
+                            panic!("{}", e);
+                        }
                         println!("Ok Excution: {:?}", tmp.elapsed())
                     }
                     Err(e) => {
@@ -37,3 +50,15 @@ fn main() {
         println!("Error2")
     }
 }
+
+pub fn fib_extern(vm_state: VMState) -> Result<VMData, VMErrorKind> {
+    fn fib(n: i64) -> i64 {
+        if n < 2 {
+            n
+        } else {
+            fib(n - 1) + fib(n - 2)
+        }
+    }
+    let res = fib(vm_state.stack.pop()?.try_i64()?);
+    Ok(VMData::new_i64(res))
+}
@@ -10,18 +10,22 @@ fn main() {
         lexer.set_source(content);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::identifier_system);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::comment_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::negative_number_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::string_system);
         let res = lexer.tokenize();
         match res {
             Ok(t) => {
                 println!("Ok Lexer: {:?}", tmp.elapsed());
                 let tmp = std::time::Instant::now();
-                let parser = Parser::parse(t);
+                let parser = Parser::parse(t, lexer.lines());
                 match parser {
                     Ok(code) => {
                         println!("Ok Parser: {:?}", tmp.elapsed());
                         let tmp = std::time::Instant::now();
                         let mut vm = VM::new(0, code.constants);
-                        vm.execute(code.ins.as_slice());
+                        if let Err(e) = vm.execute(code.ins.as_slice()) {
+                            panic!("{}", e);
+                        }
                         println!("Ok Excution: {:?}", tmp.elapsed())
                     }
                     Err(e) => {
@@ -0,0 +1,853 @@
+//! Binary container for a [`Program`], so it doesn't have to be lexed & parsed on every run.
+//!
+//! Layout (every number is little endian):
+//! ```text
+//! magic        "ATLB"
+//! version      u16
+//! flags        u16              (bit 0: debug info present)
+//! constants    u32 count, then (name: str, tag: u64, payload: u64) per constant
+//! strings      u32 count, then (constant: u64, contents: str) per string literal
+//! globals      u32 count, then (name: str, tag: u64, payload: u64) per global
+//! classes      u32 count, then per class:
+//!              name: str, parent: u64 (0: none, else its position + 1),
+//!              u32 count then name: str per field, u32 count then (name: str, address: u64) per method
+//! externs      u32 count, then name: str per extern
+//! instructions u32 count, then (opcode: u8, operands...) per instruction
+//! fn_name      u32 count, then (name: str, offset: u64) per symbol
+//! debug info   path: str, u32 count, then (start: u64, end: u64) per instruction,
+//!              u32 count, then start: u64 per source line
+//! ```
+//! A `str` is a u32 byte length followed by the UTF-8 bytes.
+//! An address operand is a u8 (0: resolved, 1: to define) followed by a u64 or a `str`.
+
+use std::fmt::Display;
+
+use internment::Intern;
+
+use crate::instruction::compiler::parser::{ClassDecl, DebugInfo, Program};
+use crate::instruction::{Address, Instruction, Overflow};
+use crate::memory::vm_data::VMData;
+
+pub const MAGIC: &[u8; 4] = b"ATLB";
+pub const VERSION: u16 = 16;
+
+const FLAG_DEBUG: u16 = 1;
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    Io(std::io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    /// The file ended while `offset` bytes were expected
+    UnexpectedEof {
+        offset: usize,
+    },
+    InvalidOpcode {
+        opcode: u8,
+        offset: usize,
+    },
+    InvalidConstant {
+        tag: u64,
+        offset: usize,
+    },
+    InvalidAddress {
+        offset: usize,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    /// A class whose parent isn't declared before it
+    InvalidClass {
+        offset: usize,
+    },
+    /// The debug info doesn't describe the same number of instructions
+    InvalidDebugInfo,
+    TrailingBytes {
+        offset: usize,
+    },
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::Io(e) => write!(f, "{}", e),
+            BytecodeError::InvalidMagic => write!(f, "not an atlas bytecode file"),
+            BytecodeError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported bytecode version {} (expected {})",
+                v, VERSION
+            ),
+            BytecodeError::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of file at byte {}", offset)
+            }
+            BytecodeError::InvalidOpcode { opcode, offset } => {
+                write!(f, "invalid opcode {:#04x} at byte {}", opcode, offset)
+            }
+            BytecodeError::InvalidConstant { tag, offset } => {
+                write!(f, "invalid constant with tag {} at byte {}", tag, offset)
+            }
+            BytecodeError::InvalidAddress { offset } => {
+                write!(f, "invalid address at byte {}", offset)
+            }
+            BytecodeError::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 string at byte {}", offset)
+            }
+            BytecodeError::InvalidClass { offset } => {
+                write!(f, "invalid class parent at byte {}", offset)
+            }
+            BytecodeError::InvalidDebugInfo => {
+                write!(f, "debug info doesn't match the instructions")
+            }
+            BytecodeError::TrailingBytes { offset } => {
+                write!(
+                    f,
+                    "unexpected data after the end of the program at byte {}",
+                    offset
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl From<std::io::Error> for BytecodeError {
+    fn from(value: std::io::Error) -> Self {
+        BytecodeError::Io(value)
+    }
+}
+
+impl Program {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u16(if self.debug.is_some() { FLAG_DEBUG } else { 0 });
+
+        w.u32(self.constants.len() as u32);
+        for (i, c) in self.constants.iter().enumerate() {
+            w.str(self.const_name.get(i).map_or("", |n| n.as_str()));
+            w.u64(c.tag);
+            w.u64(c.to_bits());
+        }
+
+        w.u32(self.strings.len() as u32);
+        for (c, s) in &self.strings {
+            w.u64(*c as u64);
+            w.str(s);
+        }
+
+        w.u32(self.globals.len() as u32);
+        for (i, g) in self.globals.iter().enumerate() {
+            w.str(self.global_name.get(i).map_or("", |n| n.as_str()));
+            w.u64(g.tag);
+            w.u64(g.to_bits());
+        }
+
+        w.u32(self.classes.len() as u32);
+        for class in &self.classes {
+            w.str(&class.name);
+            w.u64(class.parent.map_or(0, |p| p as u64 + 1));
+            w.u32(class.fields.len() as u32);
+            for field in &class.fields {
+                w.str(field);
+            }
+            w.u32(class.methods.len() as u32);
+            for (name, address) in &class.methods {
+                w.str(name);
+                w.u64(*address as u64);
+            }
+        }
+
+        w.u32(self.extern_name.len() as u32);
+        for name in &self.extern_name {
+            w.str(name);
+        }
+
+        w.u32(self.ins.len() as u32);
+        for ins in &self.ins {
+            w.instruction(ins);
+        }
+
+        w.u32(self.fn_name.len() as u32);
+        for (name, offset) in &self.fn_name {
+            w.str(name);
+            w.u64(*offset as u64);
+        }
+
+        if let Some(debug) = &self.debug {
+            w.str(&debug.path);
+            w.u32(debug.spans.len() as u32);
+            for (start, end) in &debug.spans {
+                w.u64(*start as u64);
+                w.u64(*end as u64);
+            }
+            w.u32(debug.lines.len() as u32);
+            for start in &debug.lines {
+                w.u64(*start as u64);
+            }
+        }
+        w.out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, BytecodeError> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(BytecodeError::InvalidMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        let flags = r.u16()?;
+
+        let len = r.u32()?;
+        let mut constants = Vec::with_capacity(r.capacity(len, 20));
+        let mut const_name = Vec::with_capacity(r.capacity(len, 20));
+        for _ in 0..len {
+            const_name.push(r.str()?);
+            let offset = r.pos;
+            let tag = r.u64()?;
+            let bits = r.u64()?;
+            constants.push(
+                VMData::from_bits(tag, bits)
+                    .ok_or(BytecodeError::InvalidConstant { tag, offset })?,
+            );
+        }
+
+        let len = r.u32()?;
+        let mut strings = Vec::with_capacity(r.capacity(len, 12));
+        for _ in 0..len {
+            let offset = r.pos;
+            let c = r.usize()?;
+            if c >= constants.len() {
+                return Err(BytecodeError::InvalidConstant {
+                    tag: VMData::TAG_STR,
+                    offset,
+                });
+            }
+            strings.push((c, r.str()?));
+        }
+
+        let len = r.u32()?;
+        let mut globals = Vec::with_capacity(r.capacity(len, 20));
+        let mut global_name = Vec::with_capacity(r.capacity(len, 20));
+        for _ in 0..len {
+            global_name.push(r.str()?);
+            let offset = r.pos;
+            let tag = r.u64()?;
+            let bits = r.u64()?;
+            globals.push(
+                VMData::from_bits(tag, bits)
+                    .ok_or(BytecodeError::InvalidConstant { tag, offset })?,
+            );
+        }
+
+        let len = r.u32()?;
+        let mut classes = Vec::with_capacity(r.capacity(len, 20));
+        for _ in 0..len {
+            let name = r.str()?;
+            let offset = r.pos;
+            let parent = match r.usize()? {
+                0 => None,
+                p if p <= classes.len() => Some(p - 1),
+                _ => return Err(BytecodeError::InvalidClass { offset }),
+            };
+            let count = r.u32()?;
+            let mut fields = Vec::with_capacity(r.capacity(count, 4));
+            for _ in 0..count {
+                fields.push(r.str()?);
+            }
+            let count = r.u32()?;
+            let mut methods = Vec::with_capacity(r.capacity(count, 12));
+            for _ in 0..count {
+                let name = r.str()?;
+                methods.push((name, r.usize()?));
+            }
+            classes.push(ClassDecl {
+                name,
+                parent,
+                fields,
+                methods,
+            });
+        }
+
+        let len = r.u32()?;
+        let mut extern_name = Vec::with_capacity(r.capacity(len, 4));
+        for _ in 0..len {
+            extern_name.push(r.str()?);
+        }
+
+        let len = r.u32()?;
+        let mut ins = Vec::with_capacity(r.capacity(len, 1));
+        for _ in 0..len {
+            ins.push(r.instruction()?);
+        }
+
+        let len = r.u32()?;
+        let mut fn_name = Vec::with_capacity(r.capacity(len, 12));
+        for _ in 0..len {
+            let name = r.str()?;
+            fn_name.push((name, r.u64()? as usize));
+        }
+
+        let debug = if flags & FLAG_DEBUG != 0 {
+            let path = r.str()?;
+            let len = r.u32()?;
+            if len as usize != ins.len() {
+                return Err(BytecodeError::InvalidDebugInfo);
+            }
+            let mut spans = Vec::with_capacity(r.capacity(len, 16));
+            for _ in 0..len {
+                spans.push((r.u64()? as usize, r.u64()? as usize));
+            }
+            let len = r.u32()?;
+            let mut lines = Vec::with_capacity(r.capacity(len, 8));
+            for _ in 0..len {
+                lines.push(r.u64()? as usize);
+            }
+            Some(DebugInfo { path, spans, lines })
+        } else {
+            None
+        };
+
+        if r.pos != bytes.len() {
+            return Err(BytecodeError::TrailingBytes { offset: r.pos });
+        }
+        Ok(Program {
+            ins,
+            constants,
+            const_name,
+            extern_name,
+            globals,
+            global_name,
+            strings,
+            classes,
+            fn_name,
+            debug,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), BytecodeError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Program, BytecodeError> {
+        Program::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, b: &[u8]) {
+        self.out.extend_from_slice(b);
+    }
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+    fn address(&mut self, a: &Address) {
+        match a {
+            Address::Val(v) => {
+                self.u8(0);
+                self.u64(*v as u64);
+            }
+            Address::ToDefine(label) => {
+                self.u8(1);
+                self.str(label);
+            }
+        }
+    }
+
+    fn op(&mut self, opcode: u8) {
+        self.u8(opcode);
+    }
+    fn op_u64(&mut self, opcode: u8, v: u64) {
+        self.u8(opcode);
+        self.u64(v);
+    }
+    fn op_overflow(&mut self, opcode: u8, mode: &Overflow) {
+        self.u8(opcode);
+        self.u8(match mode {
+            Overflow::Checked => 0,
+            Overflow::Wrapping => 1,
+            Overflow::Saturating => 2,
+        });
+    }
+    fn op_address(&mut self, opcode: u8, a: &Address) {
+        self.u8(opcode);
+        self.address(a);
+    }
+
+    fn instruction(&mut self, ins: &Instruction) {
+        use Instruction::*;
+        match ins {
+            PushI(i) => self.op_u64(0x00, *i as u64),
+            PushU(u) => self.op_u64(0x01, *u),
+            PushF(f) => self.op_u64(0x02, f.to_bits()),
+            LoadConst(u) => self.op_u64(0x03, *u as u64),
+            Pop => self.op(0x04),
+            AddI => self.op(0x05),
+            AddU => self.op(0x06),
+            AddF => self.op(0x07),
+            SubI => self.op(0x08),
+            SubU => self.op(0x09),
+            SubF => self.op(0x0A),
+            MulI => self.op(0x0B),
+            MulU => self.op(0x0C),
+            MulF => self.op(0x0D),
+            DivI => self.op(0x0E),
+            DivU => self.op(0x0F),
+            DivF => self.op(0x10),
+            Dup => self.op(0x11),
+            Swap => self.op(0x12),
+            Rot => self.op(0x13),
+            Jmp(a) => self.op_address(0x14, a),
+            JmpNZ(a) => self.op_address(0x15, a),
+            JmpZ(a) => self.op_address(0x16, a),
+            ExternCall(u) => self.op_u64(0x17, *u as u64),
+            Call(a) => self.op_address(0x18, a),
+            Ret => self.op(0x19),
+            Print => self.op(0x1A),
+            PrintChar => self.op(0x1B),
+            Read => self.op(0x1C),
+            ReadI => self.op(0x1D),
+            SetStruct(u) => self.op_u64(0x1E, *u as u64),
+            GetStruct(u) => self.op_u64(0x1F, *u as u64),
+            CreateStruct(u) => self.op_u64(0x20, *u as u64),
+            CreateString => self.op(0x21),
+            StrLen => self.op(0x22),
+            WriteCharToString => self.op(0x23),
+            ReadCharFromString => self.op(0x24),
+            Eq => self.op(0x25),
+            Neq => self.op(0x26),
+            Lt => self.op(0x27),
+            Gt => self.op(0x28),
+            Lte => self.op(0x29),
+            Gte => self.op(0x2A),
+            And => self.op(0x2B),
+            Or => self.op(0x2C),
+            Not => self.op(0x2D),
+            CastToI => self.op(0x2E),
+            CastToF => self.op(0x2F),
+            CastToU => self.op(0x30),
+            CastToChar => self.op(0x31),
+            CastToBool => self.op(0x32),
+            CastToPtr => self.op(0x33),
+            HLT => self.op(0x34),
+            Nop => self.op(0x35),
+            Enter(args, locals) => {
+                self.op_u64(0x36, *args as u64);
+                self.u64(*locals as u64);
+            }
+            LoadArg(u) => self.op_u64(0x37, *u as u64),
+            LoadLocal(u) => self.op_u64(0x38, *u as u64),
+            StoreLocal(u) => self.op_u64(0x39, *u as u64),
+            LoadGlobal(u) => self.op_u64(0x3A, *u as u64),
+            StoreGlobal(u) => self.op_u64(0x3B, *u as u64),
+            CreateVec(tag) => self.op_u64(0x3C, *tag),
+            VecPush => self.op(0x3D),
+            VecPop => self.op(0x3E),
+            VecGet => self.op(0x3F),
+            VecSet => self.op(0x40),
+            VecLen => self.op(0x41),
+            VecInsert => self.op(0x42),
+            VecRemove => self.op(0x43),
+            NewClass(u) => self.op_u64(0x44, *u as u64),
+            GetField(class, field) => {
+                self.op_u64(0x45, *class as u64);
+                self.u64(*field as u64);
+            }
+            SetField(class, field) => {
+                self.op_u64(0x46, *class as u64);
+                self.u64(*field as u64);
+            }
+            CallMethod(name) => {
+                self.op(0x47);
+                self.str(name);
+            }
+            StrConcat => self.op(0x48),
+            StrSlice => self.op(0x49),
+            StrFind => self.op(0x4A),
+            StrUpper => self.op(0x4B),
+            StrLower => self.op(0x4C),
+            ParseInt => self.op(0x4D),
+            ParseFloat => self.op(0x4E),
+            ToStr => self.op(0x4F),
+            CreateMap => self.op(0x50),
+            MapGet => self.op(0x51),
+            MapSet => self.op(0x52),
+            MapHas => self.op(0x53),
+            MapRemove => self.op(0x54),
+            MapLen => self.op(0x55),
+            MapKeyAt => self.op(0x56),
+            ModI => self.op(0x57),
+            ModU => self.op(0x58),
+            NegI => self.op(0x59),
+            AbsI => self.op(0x5A),
+            MinI => self.op(0x5B),
+            MaxI => self.op(0x5C),
+            MinU => self.op(0x5D),
+            MaxU => self.op(0x5E),
+            BAnd => self.op(0x5F),
+            BOr => self.op(0x60),
+            BXor => self.op(0x61),
+            BNot => self.op(0x62),
+            Shl => self.op(0x63),
+            Shr => self.op(0x64),
+            Sar => self.op(0x65),
+            AddIWith(mode) => self.op_overflow(0x66, mode),
+            SubIWith(mode) => self.op_overflow(0x67, mode),
+            MulIWith(mode) => self.op_overflow(0x68, mode),
+            AddUWith(mode) => self.op_overflow(0x69, mode),
+            SubUWith(mode) => self.op_overflow(0x6A, mode),
+            MulUWith(mode) => self.op_overflow(0x6B, mode),
+            TryBegin(a) => self.op_address(0x6C, a),
+            TryEnd => self.op(0x6D),
+            Throw => self.op(0x6E),
+            CoroCreate(a) => self.op_address(0x6F, a),
+            CoroResume => self.op(0x70),
+            Yield => self.op(0x71),
+            CoroStatus => self.op(0x72),
+            Send(u) => self.op_u64(0x73, *u as u64),
+            Recv(u) => self.op_u64(0x74, *u as u64),
+            DivIWith(mode) => self.op_overflow(0x75, mode),
+            NegIWith(mode) => self.op_overflow(0x76, mode),
+            AbsIWith(mode) => self.op_overflow(0x77, mode),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEof { offset: self.pos })?;
+        let b = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(b)
+    }
+    /// Don't trust the count of a corrupted file to preallocate memory
+    fn capacity(&self, count: u32, min_size: usize) -> usize {
+        (count as usize).min((self.bytes.len() - self.pos) / min_size)
+    }
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        Ok(self.u64()? as usize)
+    }
+    fn str(&mut self) -> Result<String, BytecodeError> {
+        let offset = self.pos;
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| BytecodeError::InvalidUtf8 { offset })
+    }
+    fn address(&mut self) -> Result<Address, BytecodeError> {
+        let offset = self.pos;
+        match self.u8()? {
+            0 => Ok(Address::Val(self.usize()?)),
+            1 => Ok(Address::ToDefine(Intern::new(self.str()?))),
+            _ => Err(BytecodeError::InvalidAddress { offset }),
+        }
+    }
+
+    fn overflow(&mut self) -> Result<Overflow, BytecodeError> {
+        let offset = self.pos;
+        match self.u8()? {
+            0 => Ok(Overflow::Checked),
+            1 => Ok(Overflow::Wrapping),
+            2 => Ok(Overflow::Saturating),
+            opcode => Err(BytecodeError::InvalidOpcode { opcode, offset }),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, BytecodeError> {
+        use Instruction::*;
+        let offset = self.pos;
+        let ins = match self.u8()? {
+            0x00 => PushI(self.u64()? as i64),
+            0x01 => PushU(self.u64()?),
+            0x02 => PushF(f64::from_bits(self.u64()?)),
+            0x03 => LoadConst(self.usize()?),
+            0x04 => Pop,
+            0x05 => AddI,
+            0x06 => AddU,
+            0x07 => AddF,
+            0x08 => SubI,
+            0x09 => SubU,
+            0x0A => SubF,
+            0x0B => MulI,
+            0x0C => MulU,
+            0x0D => MulF,
+            0x0E => DivI,
+            0x0F => DivU,
+            0x10 => DivF,
+            0x11 => Dup,
+            0x12 => Swap,
+            0x13 => Rot,
+            0x14 => Jmp(self.address()?),
+            0x15 => JmpNZ(self.address()?),
+            0x16 => JmpZ(self.address()?),
+            0x17 => ExternCall(self.usize()?),
+            0x18 => Call(self.address()?),
+            0x19 => Ret,
+            0x1A => Print,
+            0x1B => PrintChar,
+            0x1C => Read,
+            0x1D => ReadI,
+            0x1E => SetStruct(self.usize()?),
+            0x1F => GetStruct(self.usize()?),
+            0x20 => CreateStruct(self.usize()?),
+            0x21 => CreateString,
+            0x22 => StrLen,
+            0x23 => WriteCharToString,
+            0x24 => ReadCharFromString,
+            0x25 => Eq,
+            0x26 => Neq,
+            0x27 => Lt,
+            0x28 => Gt,
+            0x29 => Lte,
+            0x2A => Gte,
+            0x2B => And,
+            0x2C => Or,
+            0x2D => Not,
+            0x2E => CastToI,
+            0x2F => CastToF,
+            0x30 => CastToU,
+            0x31 => CastToChar,
+            0x32 => CastToBool,
+            0x33 => CastToPtr,
+            0x34 => HLT,
+            0x35 => Nop,
+            0x36 => Enter(self.usize()?, self.usize()?),
+            0x37 => LoadArg(self.usize()?),
+            0x38 => LoadLocal(self.usize()?),
+            0x39 => StoreLocal(self.usize()?),
+            0x3A => LoadGlobal(self.usize()?),
+            0x3B => StoreGlobal(self.usize()?),
+            0x3C => CreateVec(self.u64()?),
+            0x3D => VecPush,
+            0x3E => VecPop,
+            0x3F => VecGet,
+            0x40 => VecSet,
+            0x41 => VecLen,
+            0x42 => VecInsert,
+            0x43 => VecRemove,
+            0x44 => NewClass(self.usize()?),
+            0x45 => GetField(self.usize()?, self.usize()?),
+            0x46 => SetField(self.usize()?, self.usize()?),
+            0x47 => CallMethod(Intern::new(self.str()?)),
+            0x48 => StrConcat,
+            0x49 => StrSlice,
+            0x4A => StrFind,
+            0x4B => StrUpper,
+            0x4C => StrLower,
+            0x4D => ParseInt,
+            0x4E => ParseFloat,
+            0x4F => ToStr,
+            0x50 => CreateMap,
+            0x51 => MapGet,
+            0x52 => MapSet,
+            0x53 => MapHas,
+            0x54 => MapRemove,
+            0x55 => MapLen,
+            0x56 => MapKeyAt,
+            0x57 => ModI,
+            0x58 => ModU,
+            0x59 => NegI,
+            0x5A => AbsI,
+            0x5B => MinI,
+            0x5C => MaxI,
+            0x5D => MinU,
+            0x5E => MaxU,
+            0x5F => BAnd,
+            0x60 => BOr,
+            0x61 => BXor,
+            0x62 => BNot,
+            0x63 => Shl,
+            0x64 => Shr,
+            0x65 => Sar,
+            0x66 => AddIWith(self.overflow()?),
+            0x67 => SubIWith(self.overflow()?),
+            0x68 => MulIWith(self.overflow()?),
+            0x69 => AddUWith(self.overflow()?),
+            0x6A => SubUWith(self.overflow()?),
+            0x6B => MulUWith(self.overflow()?),
+            0x6C => TryBegin(self.address()?),
+            0x6D => TryEnd,
+            0x6E => Throw,
+            0x6F => CoroCreate(self.address()?),
+            0x70 => CoroResume,
+            0x71 => Yield,
+            0x72 => CoroStatus,
+            0x73 => Send(self.usize()?),
+            0x74 => Recv(self.usize()?),
+            0x75 => DivIWith(self.overflow()?),
+            0x76 => NegIWith(self.overflow()?),
+            0x77 => AbsIWith(self.overflow()?),
+            opcode => return Err(BytecodeError::InvalidOpcode { opcode, offset }),
+        };
+        Ok(ins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::{assemble, EXAMPLES};
+
+    /// Every kind of section entry, an explicit overflow mode & an address left to define
+    const EVERYTHING: &str = "
+.section
+    @int answer 42
+    @float half 0.5
+    @string greeting \"Hello\"
+    @global int counter 0
+    @class Base
+        field x
+        method get &base_get
+    @class Derived extends Base
+        field y
+.code
+main:
+    load_const #answer
+    load_const #half
+    load_const #greeting
+    load_global #counter
+    add_i_wrapping
+    div_i_saturating
+    new_class #Derived
+    call_method #get
+    extern_call @print_int
+    jmp &main
+base_get:
+    .args $1
+    get_field #Base.x
+    ret
+";
+
+    fn programs() -> Vec<Program> {
+        let mut programs: Vec<_> = EXAMPLES.iter().map(|(_, src)| assemble(src)).collect();
+        let mut everything = assemble(EVERYTHING);
+        programs.push(everything.clone());
+        everything.debug = None;
+        programs.push(everything);
+        programs
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        for program in programs() {
+            assert_eq!(Program::from_bytes(&program.to_bytes()).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn every_truncation_fails() {
+        for program in programs() {
+            let bytes = program.to_bytes();
+            for len in 0..bytes.len() {
+                match Program::from_bytes(&bytes[..len]) {
+                    Err(BytecodeError::InvalidMagic) => assert!(len < MAGIC.len()),
+                    Err(BytecodeError::UnexpectedEof { offset }) => assert!(offset <= len),
+                    other => panic!("{} bytes gave {:?}", len, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_magic_or_version_fails() {
+        let mut bytes = assemble(EVERYTHING).to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Program::from_bytes(&bytes),
+            Err(BytecodeError::InvalidMagic)
+        ));
+        let mut bytes = assemble(EVERYTHING).to_bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Program::from_bytes(&bytes),
+            Err(BytecodeError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn huge_count_doesnt_preallocate() {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u16(0);
+        w.u32(u32::MAX);
+        w.str("c");
+        let r = Reader {
+            bytes: &w.out,
+            pos: 12,
+        };
+        // Only the 5 bytes left are trusted, not the count
+        assert_eq!(r.capacity(u32::MAX, 1), 5);
+        assert_eq!(r.capacity(u32::MAX, 20), 0);
+        assert_eq!(r.capacity(3, 1), 3);
+        assert!(matches!(
+            Program::from_bytes(&w.out),
+            Err(BytecodeError::UnexpectedEof { offset: 17 })
+        ));
+
+        // Same for the length of a string
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u16(0);
+        w.u32(1);
+        w.u32(u32::MAX);
+        assert!(matches!(
+            Program::from_bytes(&w.out),
+            Err(BytecodeError::UnexpectedEof { offset: 16 })
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes_fail() {
+        let mut bytes = assemble(EVERYTHING).to_bytes();
+        let len = bytes.len();
+        bytes.push(0);
+        assert!(matches!(
+            Program::from_bytes(&bytes),
+            Err(BytecodeError::TrailingBytes { offset }) if offset == len
+        ));
+    }
+}
@@ -0,0 +1,478 @@
+//! Turn a [`Program`] back into assembly that the parser accepts.
+//!
+//! Assembling the output gives back the same instructions, constants & labels,
+//! only the debug info points to the new text. Immediates the lexer can't read exactly
+//! (non finite floats, integers bigger than 2^53 & unit constants) don't round-trip.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::instruction::compiler::parser::{ClassDecl, Program};
+use crate::instruction::{Address, Instruction};
+use crate::memory::vm_data::{VMData, TAG};
+
+/// Names used by the operands of the instructions
+struct Names<'a> {
+    consts: Vec<String>,
+    globals: Vec<String>,
+    externs: &'a [String],
+    classes: &'a [ClassDecl],
+    /// Every label that should be written before the instruction at that position
+    labels: BTreeMap<usize, Vec<String>>,
+}
+
+pub fn disassemble(program: &Program) -> String {
+    let const_names = names_or_generated(&program.const_name, program.constants.len(), "const");
+    let global_names = names_or_generated(&program.global_name, program.globals.len(), "global");
+
+    let mut labels: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (name, pos) in &program.fn_name {
+        labels.entry(*pos).or_default().push(name.clone());
+    }
+    if !program.ins.is_empty() && !labels.contains_key(&0) {
+        labels.insert(0, vec![generated_name("label", 0)]);
+    }
+    let methods = program
+        .classes
+        .iter()
+        .flat_map(|c| c.methods.iter().map(|(_, address)| address));
+    let targets = program.ins.iter().filter_map(|ins| match target(ins) {
+        Some(Address::Val(pos)) => Some(pos),
+        _ => None,
+    });
+    for pos in targets.chain(methods) {
+        if !labels.contains_key(pos) {
+            labels.insert(*pos, vec![generated_name("label", *pos)]);
+        }
+    }
+
+    let mut out = String::from(".section\n");
+    for (i, (c, name)) in program.constants.iter().zip(&const_names).enumerate() {
+        match program.strings.iter().find(|(s, _)| *s == i) {
+            Some((_, s)) => writeln!(out, "    @string {} {}", name, string_literal(s)).unwrap(),
+            None => writeln!(out, "    @{} {} {}", const_type(c), name, const_value(c)).unwrap(),
+        }
+    }
+    for (g, name) in program.globals.iter().zip(&global_names) {
+        writeln!(
+            out,
+            "    @global {} {} {}",
+            const_type(g),
+            name,
+            const_value(g)
+        )
+        .unwrap();
+    }
+    for class in &program.classes {
+        write!(out, "    @class {}", class.name).unwrap();
+        if let Some(parent) = class.parent {
+            write!(out, " extends {}", program.classes[parent].name).unwrap();
+        }
+        out.push('\n');
+        for field in &class.fields {
+            writeln!(out, "        field {}", field).unwrap();
+        }
+        for (name, address) in &class.methods {
+            writeln!(out, "        method {} &{}", name, labels[address][0]).unwrap();
+        }
+    }
+    let names = Names {
+        consts: const_names,
+        globals: global_names,
+        externs: &program.extern_name,
+        classes: &program.classes,
+        labels,
+    };
+    out.push_str(".code\n");
+    for (pc, ins) in program.ins.iter().enumerate() {
+        for label in names.labels.get(&pc).into_iter().flatten() {
+            writeln!(out, "{}:", label).unwrap();
+        }
+        out.push_str("    ");
+        write_instruction(&mut out, ins, &names);
+        out.push('\n');
+    }
+    for (_, labels) in names.labels.range(program.ins.len()..) {
+        for label in labels {
+            writeln!(out, "{}:", label).unwrap();
+        }
+    }
+    out
+}
+
+fn target(ins: &Instruction) -> Option<&Address> {
+    match ins {
+        Instruction::Jmp(a)
+        | Instruction::JmpNZ(a)
+        | Instruction::JmpZ(a)
+        | Instruction::Call(a)
+        | Instruction::TryBegin(a)
+        | Instruction::CoroCreate(a) => Some(a),
+        _ => None,
+    }
+}
+
+/// `#Class.field` operand of `get_field` & `set_field`
+fn field_name(names: &Names, class: usize, field: usize) -> String {
+    match names.classes.get(class) {
+        Some(c) => match c.fields.get(field) {
+            Some(f) => format!("#{}.{}", c.name, f),
+            None => format!("#{}.{}", c.name, generated_name("field", field)),
+        },
+        None => format!(
+            "#{}.{}",
+            generated_name("class", class),
+            generated_name("field", field)
+        ),
+    }
+}
+
+fn names_or_generated(names: &[String], len: usize, prefix: &str) -> Vec<String> {
+    (0..len)
+        .map(|i| match names.get(i) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => generated_name(prefix, i),
+        })
+        .collect()
+}
+
+/// Identifiers can't contain digits, so the index is written with letters (0 => a, 26 => ba)
+fn generated_name(prefix: &str, mut i: usize) -> String {
+    let mut letters = vec![];
+    loop {
+        letters.push((b'a' + (i % 26) as u8) as char);
+        i /= 26;
+        if i == 0 {
+            break;
+        }
+    }
+    format!(
+        "{}_{}",
+        prefix,
+        letters.into_iter().rev().collect::<String>()
+    )
+}
+
+fn const_type(c: &VMData) -> &'static str {
+    tag_type(c.tag)
+}
+
+/// Type keyword of a tag
+fn tag_type(tag: TAG) -> &'static str {
+    match tag {
+        VMData::TAG_I64 => "int",
+        VMData::TAG_U64 => "u_int",
+        VMData::TAG_FLOAT => "float",
+        VMData::TAG_CHAR => "char",
+        VMData::TAG_BOOL => "bool",
+        VMData::TAG_STR => "string",
+        _ => "object",
+    }
+}
+
+/// `s` quoted & escaped the way the lexer reads it back
+fn string_literal(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Value of a constant as written in the `.section`
+fn const_value(c: &VMData) -> String {
+    match c.tag {
+        VMData::TAG_I64 => c.as_i64().to_string(),
+        VMData::TAG_FLOAT => c.as_f64().to_string(),
+        _ => c.to_bits().to_string(),
+    }
+}
+
+fn write_instruction(out: &mut String, ins: &Instruction, names: &Names) {
+    use Instruction::*;
+    let label = |a: &Address| match a {
+        Address::Val(pos) => names.labels[pos][0].clone(),
+        Address::ToDefine(name) => name.to_string(),
+    };
+    let res = match ins {
+        PushI(i) => write!(out, "push_i ${}", i),
+        PushU(u) => write!(out, "push_u ${}", u),
+        PushF(f) => write!(out, "push_f ${}", f),
+        LoadConst(u) => match names.consts.get(*u) {
+            Some(name) => write!(out, "load_const #{}", name),
+            None => write!(out, "load_const #{}", generated_name("const", *u)),
+        },
+        LoadGlobal(u) => match names.globals.get(*u) {
+            Some(name) => write!(out, "load_global #{}", name),
+            None => write!(out, "load_global #{}", generated_name("global", *u)),
+        },
+        StoreGlobal(u) => match names.globals.get(*u) {
+            Some(name) => write!(out, "store_global #{}", name),
+            None => write!(out, "store_global #{}", generated_name("global", *u)),
+        },
+        Pop => write!(out, "pop"),
+        AddI => write!(out, "add_i"),
+        AddU => write!(out, "add_u"),
+        AddF => write!(out, "add_f"),
+        SubI => write!(out, "sub_i"),
+        SubU => write!(out, "sub_u"),
+        SubF => write!(out, "sub_f"),
+        MulI => write!(out, "mul_i"),
+        MulU => write!(out, "mul_u"),
+        MulF => write!(out, "mul_f"),
+        DivI => write!(out, "div_i"),
+        DivU => write!(out, "div_u"),
+        DivF => write!(out, "div_f"),
+        AddIWith(mode) => write!(out, "add_i_{}", mode),
+        SubIWith(mode) => write!(out, "sub_i_{}", mode),
+        MulIWith(mode) => write!(out, "mul_i_{}", mode),
+        AddUWith(mode) => write!(out, "add_u_{}", mode),
+        SubUWith(mode) => write!(out, "sub_u_{}", mode),
+        MulUWith(mode) => write!(out, "mul_u_{}", mode),
+        DivIWith(mode) => write!(out, "div_i_{}", mode),
+        NegIWith(mode) => write!(out, "neg_i_{}", mode),
+        AbsIWith(mode) => write!(out, "abs_i_{}", mode),
+        ModI => write!(out, "mod_i"),
+        ModU => write!(out, "mod_u"),
+        NegI => write!(out, "neg_i"),
+        AbsI => write!(out, "abs_i"),
+        MinI => write!(out, "min_i"),
+        MaxI => write!(out, "max_i"),
+        MinU => write!(out, "min_u"),
+        MaxU => write!(out, "max_u"),
+        BAnd => write!(out, "band"),
+        BOr => write!(out, "bor"),
+        BXor => write!(out, "bxor"),
+        BNot => write!(out, "bnot"),
+        Shl => write!(out, "shl"),
+        Shr => write!(out, "shr"),
+        Sar => write!(out, "sar"),
+        Dup => write!(out, "dup"),
+        Swap => write!(out, "swap"),
+        Rot => write!(out, "rot"),
+        Jmp(a) => write!(out, "jmp &{}", label(a)),
+        JmpNZ(a) => write!(out, "jmp_nz &{}", label(a)),
+        JmpZ(a) => write!(out, "jmp_z &{}", label(a)),
+        ExternCall(u) => match names.externs.get(*u) {
+            Some(name) => write!(out, "extern_call @{}", name),
+            None => write!(out, "extern_call @{}", generated_name("extern", *u)),
+        },
+        Call(a) => write!(out, "call &{}", label(a)),
+        Ret => write!(out, "ret"),
+        Enter(args, locals) => write!(out, ".args ${}\n    .locals ${}", args, locals),
+        LoadArg(u) => write!(out, "load_arg ${}", u),
+        LoadLocal(u) => write!(out, "load_local ${}", u),
+        StoreLocal(u) => write!(out, "store_local ${}", u),
+        TryBegin(a) => write!(out, "try_begin &{}", label(a)),
+        TryEnd => write!(out, "try_end"),
+        Throw => write!(out, "throw"),
+        CoroCreate(a) => write!(out, "coro_create &{}", label(a)),
+        CoroResume => write!(out, "coro_resume"),
+        Yield => write!(out, "yield"),
+        CoroStatus => write!(out, "coro_status"),
+        Send(u) => write!(out, "send ${}", u),
+        Recv(u) => write!(out, "recv ${}", u),
+        Print => write!(out, "print"),
+        PrintChar => write!(out, "print_char"),
+        Read => write!(out, "read"),
+        ReadI => write!(out, "read_i"),
+        SetStruct(u) => write!(out, "set_struct ${}", u),
+        GetStruct(u) => write!(out, "get_struct ${}", u),
+        CreateStruct(u) => write!(out, "create_struct ${}", u),
+        CreateString => write!(out, "create_string"),
+        StrLen => write!(out, "str_len"),
+        WriteCharToString => write!(out, "write_char"),
+        ReadCharFromString => write!(out, "read_char"),
+        StrConcat => write!(out, "str_concat"),
+        StrSlice => write!(out, "str_slice"),
+        StrFind => write!(out, "str_find"),
+        StrUpper => write!(out, "str_upper"),
+        StrLower => write!(out, "str_lower"),
+        ParseInt => write!(out, "parse_int"),
+        ParseFloat => write!(out, "parse_float"),
+        ToStr => write!(out, "to_str"),
+        CreateVec(tag) => write!(out, "create_vec {}", tag_type(*tag)),
+        VecPush => write!(out, "vec_push"),
+        VecPop => write!(out, "vec_pop"),
+        VecGet => write!(out, "vec_get"),
+        VecSet => write!(out, "vec_set"),
+        VecLen => write!(out, "vec_len"),
+        VecInsert => write!(out, "vec_insert"),
+        VecRemove => write!(out, "vec_remove"),
+        CreateMap => write!(out, "create_map"),
+        MapGet => write!(out, "map_get"),
+        MapSet => write!(out, "map_set"),
+        MapHas => write!(out, "map_has"),
+        MapRemove => write!(out, "map_remove"),
+        MapLen => write!(out, "map_len"),
+        MapKeyAt => write!(out, "map_key_at"),
+        NewClass(u) => match names.classes.get(*u) {
+            Some(class) => write!(out, "new_class #{}", class.name),
+            None => write!(out, "new_class #{}", generated_name("class", *u)),
+        },
+        GetField(class, field) => write!(out, "get_field {}", field_name(names, *class, *field)),
+        SetField(class, field) => write!(out, "set_field {}", field_name(names, *class, *field)),
+        CallMethod(name) => write!(out, "call_method #{}", name),
+        Instruction::Eq => write!(out, "eq"),
+        Neq => write!(out, "neq"),
+        Lt => write!(out, "lt"),
+        Gt => write!(out, "gt"),
+        Lte => write!(out, "lte"),
+        Gte => write!(out, "gte"),
+        And => write!(out, "and"),
+        Or => write!(out, "or"),
+        Not => write!(out, "not"),
+        CastToI => write!(out, "cast_to_int"),
+        CastToF => write!(out, "cast_to_float"),
+        CastToU => write!(out, "cast_to_uint"),
+        CastToChar => write!(out, "cast_to_char"),
+        CastToBool => write!(out, "cast_to_bool"),
+        CastToPtr => write!(out, "cast_to_ptr"),
+        HLT => write!(out, "hlt"),
+        Nop => write!(out, "nop"),
+    };
+    res.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::{assemble, EXAMPLES};
+    use crate::memory::object_map::ObjectIndex;
+
+    /// Assemble the disassembly of `program`, which should give back everything but the debug info
+    fn reassemble(program: &Program) -> Program {
+        let text = disassemble(program);
+        let again = assemble(&text);
+        assert_eq!(again.ins, program.ins);
+        assert_eq!(again.constants, program.constants);
+        assert_eq!(again.strings, program.strings);
+        assert_eq!(again.globals, program.globals);
+        assert_eq!(again.classes, program.classes);
+        assert_eq!(again.extern_name, program.extern_name);
+        // The output of the reassembled program is the same text
+        assert_eq!(disassemble(&again), text);
+        again
+    }
+
+    #[test]
+    fn examples_round_trip() {
+        for (name, source) in EXAMPLES {
+            let program = assemble(source);
+            let again = reassemble(&program);
+            assert_eq!(again.const_name, program.const_name, "{}", name);
+            assert_eq!(again.global_name, program.global_name, "{}", name);
+            assert_eq!(again.fn_name, program.fn_name, "{}", name);
+        }
+    }
+
+    #[test]
+    fn labels_classes_strings_globals_round_trip() {
+        let program = assemble(
+            "
+.section
+    @string quoted \"say \\\"hi\\\"\\n\\tbye\"
+    @char letter 97
+    @bool yes 1
+    @u_int big 4000000000
+    @global float ratio -2.5
+    @global int count 0
+    @class Shape
+        field sides
+        method area &shape_area
+    @class Square extends Shape
+        field side
+        method area &square_area
+.code
+main:
+    load_const #quoted
+    load_global #ratio
+    store_global #count
+    new_class #Square
+    call_method #area
+    get_field #Square.side
+    try_begin &handler
+    jmp &main
+handler:
+    coro_create &main
+    hlt
+shape_area:
+square_area:
+    .args $1
+    ret
+",
+        );
+        assert_eq!(program.strings[0].1, "say \"hi\"\n\tbye");
+        reassemble(&program);
+    }
+
+    #[test]
+    fn missing_names_are_generated() {
+        let mut program = assemble(
+            "
+.section
+    @int a 1
+    @global int b 2
+.code
+main:
+    load_const #a
+    store_global #b
+    jmp_z &end
+    call &main
+end:
+    hlt
+",
+        );
+        program.const_name.clear();
+        program.global_name.clear();
+        program.fn_name.clear();
+        let text = disassemble(&program);
+        assert!(text.contains("@int const_a 1"));
+        assert!(text.contains("@global int global_a 2"));
+        assert!(text.contains("label_a:\n    load_const #const_a"));
+        assert!(text.contains("jmp_z &label_e"));
+        let again = reassemble(&program);
+        assert_eq!(
+            again.fn_name,
+            vec![("label_a".to_owned(), 0), ("label_e".to_owned(), 4)]
+        );
+    }
+
+    /// Values the documentation says don't round-trip
+    #[test]
+    fn unreadable_immediates_change() {
+        let mut program = assemble(".section\n.code\nmain:\n    hlt\n");
+        let constants = [
+            VMData::new_f64(f64::INFINITY),
+            VMData::new_f64(f64::NAN),
+            VMData::new_i64((1 << 53) + 1),
+            VMData::new_unit(),
+            // These are exact
+            VMData::new_f64(-0.0),
+            VMData::new_i64(i64::MIN),
+        ];
+        program.constants = constants.to_vec();
+        let text = disassemble(&program);
+        // Not number literals, the parser rejects them
+        assert!(text.contains("@float const_a inf\n"));
+        assert!(text.contains("@float const_b NaN\n"));
+
+        program.constants.drain(..2);
+        let again = assemble(&disassemble(&program)).constants;
+        // Integers are read as floats
+        assert_eq!(again[0].as_i64(), 1 << 53);
+        // Unit is written as the object at 0
+        assert_eq!(again[1].tag, 257);
+        assert_eq!(again[1].as_object(), ObjectIndex::new(0));
+        assert_eq!(again[2..], constants[4..]);
+    }
+}
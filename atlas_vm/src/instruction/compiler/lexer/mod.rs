@@ -20,6 +20,8 @@ keywords!(
     "push_u",
     "push_f",
     "load_const",
+    "load_global",
+    "store_global",
     "pop",
     "add_i",
     "add_u",
@@ -33,6 +35,48 @@ keywords!(
     "div_i",
     "div_u",
     "div_f",
+    "add_i_checked",
+    "add_i_wrapping",
+    "add_i_saturating",
+    "sub_i_checked",
+    "sub_i_wrapping",
+    "sub_i_saturating",
+    "mul_i_checked",
+    "mul_i_wrapping",
+    "mul_i_saturating",
+    "add_u_checked",
+    "add_u_wrapping",
+    "add_u_saturating",
+    "sub_u_checked",
+    "sub_u_wrapping",
+    "sub_u_saturating",
+    "mul_u_checked",
+    "mul_u_wrapping",
+    "mul_u_saturating",
+    "div_i_checked",
+    "div_i_wrapping",
+    "div_i_saturating",
+    "neg_i_checked",
+    "neg_i_wrapping",
+    "neg_i_saturating",
+    "abs_i_checked",
+    "abs_i_wrapping",
+    "abs_i_saturating",
+    "mod_i",
+    "mod_u",
+    "neg_i",
+    "abs_i",
+    "min_i",
+    "max_i",
+    "min_u",
+    "max_u",
+    "band",
+    "bor",
+    "bxor",
+    "bnot",
+    "shl",
+    "shr",
+    "sar",
     "dup",
     "swap",
     "rot",
@@ -42,6 +86,20 @@ keywords!(
     "extern_call",
     "call",
     "ret",
+    "args",
+    "locals",
+    "load_arg",
+    "load_local",
+    "store_local",
+    "try_begin",
+    "try_end",
+    "throw",
+    "coro_create",
+    "coro_resume",
+    "yield",
+    "coro_status",
+    "send",
+    "recv",
     "print_char",
     "print",
     "read",
@@ -53,6 +111,29 @@ keywords!(
     "str_len",
     "write_char",
     "read_char",
+    "str_concat",
+    "str_slice",
+    "str_find",
+    "str_upper",
+    "str_lower",
+    "parse_int",
+    "parse_float",
+    "to_str",
+    "create_vec",
+    "vec_push",
+    "vec_pop",
+    "vec_get",
+    "vec_set",
+    "vec_len",
+    "vec_insert",
+    "vec_remove",
+    "create_map",
+    "map_get",
+    "map_set",
+    "map_has",
+    "map_remove",
+    "map_len",
+    "map_key_at",
     "eq",
     "neq",
     "lt",
@@ -70,6 +151,15 @@ keywords!(
     "cast_to_char",
     "cast_to_bool",
     "cast_to_ptr",
+    "global",
+    "class",
+    "extends",
+    "field",
+    "method",
+    "new_class",
+    "get_field",
+    "set_field",
+    "call_method",
     "int",
     "u_int",
     "float",
@@ -79,6 +169,22 @@ keywords!(
     "bool"
 );
 
+impl AtlasLexer {
+    /// Position of the start of each line of the source, counted in chars like the spans of
+    /// the tokens, so they can be given a line & column
+    pub fn lines(&self) -> Vec<usize> {
+        std::iter::once(0)
+            .chain(
+                self.source
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '\n')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect()
+    }
+}
+
 pub fn comment_system(c: char, state: &mut LexerState) -> Option<Token> {
     if c == ';' {
         let start = state.current_pos;
@@ -127,3 +233,121 @@ pub fn identifier_system(c: char, state: &mut LexerState) -> Option<Token> {
     }
     None
 }
+
+/// `"..."` string literals, the only escapes are `\n`, `\t`, `\"` & `\\`.
+/// Nothing is consumed if the literal is unterminated or has another escape.
+pub fn string_system(c: char, state: &mut LexerState) -> Option<Token> {
+    if c == '"' {
+        let start = state.current_pos;
+        let mut ahead = state.clone();
+        ahead.next();
+        let mut s = String::new();
+        loop {
+            match ahead.next()? {
+                '"' => break,
+                '\\' => match ahead.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    c @ ('"' | '\\') => s.push(c),
+                    _ => return None,
+                },
+                c => s.push(c),
+            }
+        }
+        *state = ahead;
+        return Some(Token::new(
+            Span {
+                start,
+                end: state.current_pos,
+                path: state.path,
+            },
+            TokenKind::Literal(Literal::StringLiteral(Intern::new(s))),
+        ));
+    }
+    None
+}
+
+/// The default number system doesn't handle negative numbers, so they're lexed here.
+/// Nothing is consumed if the `-` isn't followed by a digit.
+pub fn negative_number_system(c: char, state: &mut LexerState) -> Option<Token> {
+    if c == '-' {
+        let mut ahead = state.clone();
+        ahead.next();
+        if !ahead.peek().is_some_and(|c| c.is_numeric()) {
+            return None;
+        }
+        let start = state.current_pos;
+        *state = ahead;
+        let mut n = String::from(c);
+        let mut dot = false;
+        while let Some(c) = state.peek() {
+            if c.is_numeric() || (*c == '.' && !dot) {
+                dot |= *c == '.';
+                n.push(*c);
+                state.next();
+            } else {
+                break;
+            }
+        }
+        return Some(Token::new(
+            Span {
+                start,
+                end: state.current_pos,
+                path: state.path,
+            },
+            TokenKind::Literal(Literal::Float(n.parse::<f64>().ok()?)),
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `system` on the start of `source`, giving back the token & the position after it
+    fn lex(
+        system: fn(char, &mut LexerState) -> Option<Token>,
+        source: &str,
+    ) -> (Option<TokenKind>, usize) {
+        let mut state = LexerState::new(BytePos::from(0), source, "<test>");
+        let c = source.chars().next().unwrap();
+        let token = system(c, &mut state);
+        (token.map(|t| t.kind()), usize::from(state.current_pos))
+    }
+
+    fn string(s: &str) -> Option<TokenKind> {
+        Some(TokenKind::Literal(Literal::StringLiteral(Intern::new(
+            s.to_owned(),
+        ))))
+    }
+
+    #[test]
+    fn string_with_escapes() {
+        assert_eq!(
+            lex(string_system, r#""a\n\t\"\\b" rest"#),
+            (string("a\n\t\"\\b"), 12)
+        );
+    }
+
+    #[test]
+    fn malformed_string_is_not_consumed() {
+        assert_eq!(lex(string_system, r#""abc"#), (None, 0));
+        assert_eq!(lex(string_system, r#""a\qb""#), (None, 0));
+        assert_eq!(lex(string_system, r#""a\"#), (None, 0));
+    }
+
+    #[test]
+    fn negative_number() {
+        let float = |f| Some(TokenKind::Literal(Literal::Float(f)));
+        assert_eq!(lex(negative_number_system, "-12 "), (float(-12.0), 3));
+        assert_eq!(lex(negative_number_system, "-1.5.2"), (float(-1.5), 4));
+    }
+
+    #[test]
+    fn lone_minus_is_not_consumed() {
+        assert_eq!(lex(negative_number_system, "-"), (None, 0));
+        assert_eq!(lex(negative_number_system, "-x"), (None, 0));
+        assert_eq!(lex(negative_number_system, "-.5"), (None, 0));
+    }
+}
@@ -1,2 +1,42 @@
+pub mod bytecode;
+pub mod disassembler;
 pub mod lexer;
 pub mod parser;
+
+/// Assemble `source` with the same lexer systems as the examples
+#[cfg(test)]
+pub(crate) fn assemble(source: &str) -> parser::Program {
+    let mut lexer = lexer::AtlasLexer::default();
+    lexer.set_path("<test>");
+    lexer.set_source(source.to_owned());
+    lexer.add_system(lexer::identifier_system);
+    lexer.add_system(lexer::comment_system);
+    lexer.add_system(lexer::negative_number_system);
+    lexer.add_system(lexer::string_system);
+    let tokens = lexer.tokenize().expect("the source can't be tokenized");
+    parser::Parser::parse(tokens, lexer.lines()).expect("the source can't be parsed")
+}
+
+/// (name, source) of every example program
+#[cfg(test)]
+pub(crate) const EXAMPLES: &[(&str, &str)] = &[
+    ("brainfuck", include_str!("../../../examples/brainfuck.txt")),
+    ("class", include_str!("../../../examples/class.txt")),
+    ("consumer", include_str!("../../../examples/consumer.txt")),
+    ("coroutine", include_str!("../../../examples/coroutine.txt")),
+    ("exception", include_str!("../../../examples/exception.txt")),
+    (
+        "extern_call",
+        include_str!("../../../examples/extern_call.txt"),
+    ),
+    ("fib", include_str!("../../../examples/fib.txt")),
+    (
+        "fib_locals",
+        include_str!("../../../examples/fib_locals.txt"),
+    ),
+    ("map", include_str!("../../../examples/map.txt")),
+    ("mem_test", include_str!("../../../examples/mem_test.txt")),
+    ("producer", include_str!("../../../examples/producer.txt")),
+    ("string", include_str!("../../../examples/string.txt")),
+    ("vector", include_str!("../../../examples/vector.txt")),
+];
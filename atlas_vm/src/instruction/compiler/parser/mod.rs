@@ -1,9 +1,9 @@
 use std::{iter::Peekable, vec::IntoIter};
 
 use crate::instruction::compiler::lexer::{Literal, Token, TokenKind};
-use crate::instruction::{Address, Instruction};
+use crate::instruction::{Address, Instruction, Overflow};
 use crate::memory::object_map::ObjectIndex;
-use crate::memory::vm_data::VMData;
+use crate::memory::vm_data::{VMData, TAG};
 use atlas_core::prelude::Spanned;
 use internment::Intern;
 
@@ -22,6 +22,8 @@ pub enum Type {
 pub struct Block {
     pub id: Intern<String>,
     pub ins: Vec<Instruction>,
+    ///Source span (start, end) of each instruction
+    pub spans: Vec<(usize, usize)>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -30,26 +32,109 @@ pub struct Constant {
     pub value: VMData,
 }
 
+/// A class declared with `@class` in the `.section`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDecl {
+    pub name: String,
+    ///Position of the parent in `Program::classes`, it's always declared before its children
+    pub parent: Option<usize>,
+    ///Fields declared by the class itself, an instance also has the ones of its parents
+    pub fields: Vec<String>,
+    ///(name, address) of the methods declared by the class, overriding the ones of its parents
+    pub methods: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub ins: Vec<Instruction>,
     pub constants: Vec<VMData>,
+    ///Name of each constant, in the same order as `constants`
+    pub const_name: Vec<String>,
+    ///Name of each extern the program calls, `ExternCall(i)` refers to `extern_name[i]`
+    ///until the program is linked by [`crate::runtime::VM::link`]
+    pub extern_name: Vec<String>,
+    ///Initial value of each global, declared with `@global` in the `.section`
+    pub globals: Vec<VMData>,
+    ///Name of each global, in the same order as `globals`
+    pub global_name: Vec<String>,
+    ///(constant, contents) of each string literal, the constant itself is unit until the
+    ///program is linked by [`crate::runtime::VM::link`]
+    pub strings: Vec<(usize, String)>,
+    ///Classes declared with `@class` in the `.section`, `NewClass(i)` refers to `classes[i]`
+    pub classes: Vec<ClassDecl>,
+    ///(label, position) of each block, it names the functions in backtraces
     pub fn_name: Vec<(String, usize)>,
+    pub debug: Option<DebugInfo>,
+}
+
+/// Where each instruction comes from in the assembly source
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+    pub path: String,
+    ///Span (start, end) of each instruction in the source file, counted in chars
+    pub spans: Vec<(usize, usize)>,
+    ///Position of the start of each line of the source file, counted in chars like the spans,
+    ///empty if it's unknown
+    pub lines: Vec<usize>,
+}
+
+impl DebugInfo {
+    /// Line & column of the instruction at `pc`, both starting at 1
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        let (start, _) = *self.spans.get(pc)?;
+        let line = self.lines.partition_point(|l| *l <= start);
+        let line_start = *self.lines.get(line.checked_sub(1)?)?;
+        Some(Location {
+            path: self.path.clone(),
+            line,
+            col: start - line_start + 1,
+        })
+    }
+}
+
+/// A position in an assembly source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.path, self.line, self.col)
+    }
 }
 
 pub struct Parser {
     tokens: Peekable<IntoIter<Token>>,
     blocks: Vec<Block>,
     constants: Vec<Constant>,
+    globals: Vec<Constant>,
+    externs: Vec<Intern<String>>,
+    classes: Vec<ClassDecl>,
+    strings: Vec<(usize, String)>,
+    ///(class, method, label) of each method, resolved once every block is known
+    methods: Vec<(usize, String, Intern<String>)>,
     pos: usize,
 }
 
 impl Parser {
-    pub fn parse(tokens: Vec<Token>) -> Result<Program, ()> {
-        let toks = tokens.into_iter().peekable();
+    /// `lines` are the starts of the lines of the source, to locate the instructions in the
+    /// debug info, see [`crate::instruction::compiler::lexer::AtlasLexer::lines`]
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(tokens: Vec<Token>, lines: Vec<usize>) -> Result<Program, ()> {
+        let path = tokens.first().map(|t| t.span().path).unwrap_or_default();
+        let toks = contextual_keywords(tokens).into_iter().peekable();
         let mut parser = Parser {
             tokens: toks,
             blocks: Vec::new(),
             constants: vec![],
+            globals: vec![],
+            externs: vec![],
+            classes: vec![],
+            strings: vec![],
+            methods: vec![],
             pos: 0,
         };
         if let TokenKind::SoI = parser.tokens.next().unwrap().kind() {
@@ -58,23 +143,47 @@ impl Parser {
                     parser.constants = constants;
                     let ins = parser.parse_code()?.clone();
                     let mut consts = vec![];
-                    parser
-                        .constants
-                        .into_iter()
-                        .for_each(|c| consts.push(c.value));
+                    let mut const_name = vec![];
+                    parser.constants.into_iter().for_each(|c| {
+                        consts.push(c.value);
+                        const_name.push(c.id.as_str().to_owned());
+                    });
+                    let debug = DebugInfo {
+                        path: path.to_owned(),
+                        spans: parser
+                            .blocks
+                            .iter()
+                            .flat_map(|b| b.spans.iter().copied())
+                            .collect(),
+                        lines,
+                    };
                     Ok(Program {
                         ins,
                         constants: consts,
+                        const_name,
+                        extern_name: parser
+                            .externs
+                            .iter()
+                            .map(|e| e.as_str().to_owned())
+                            .collect(),
+                        globals: parser.globals.iter().map(|g| g.value).collect(),
+                        global_name: parser
+                            .globals
+                            .iter()
+                            .map(|g| g.id.as_str().to_owned())
+                            .collect(),
+                        strings: parser.strings,
+                        classes: parser.classes,
                         fn_name: {
                             let mut names = vec![];
                             let mut current_pos = 0;
-                            // need to change this to get the correct address (call trace)
                             parser.blocks.into_iter().for_each(|b| {
                                 names.push((b.id.as_str().to_owned(), current_pos));
                                 current_pos += b.ins.len();
                             });
                             names
                         },
+                        debug: Some(debug),
                     })
                 }
                 Err(_e) => Err(()),
@@ -83,6 +192,154 @@ impl Parser {
             Err(())
         }
     }
+    /// Position of `name` in the externs called by the program, adding it if it's new
+    fn extern_index(&mut self, name: Intern<String>) -> usize {
+        match self.externs.iter().position(|e| *e == name) {
+            Some(pos) => pos,
+            None => {
+                self.externs.push(name);
+                self.externs.len() - 1
+            }
+        }
+    }
+    /// Parse the `$n` operand of `name`
+    fn parse_operand(&mut self, name: &str) -> usize {
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::DollarSign) => {}
+            _ => panic!("There should be a dollar sign ($) after \"{}\"", name),
+        }
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Float(f))) if f >= 0.0 => f as usize,
+            _ => panic!("There should be a positive number after \"{} $\"", name),
+        }
+    }
+    /// Parse the `&label` operand of `ins`, it's resolved once every block is parsed
+    fn parse_label(&mut self, ins: &str) -> Address {
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Ampersand) => {}
+            _ => panic!("There should be an ampersand (&) after \"{}\"", ins),
+        }
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Identifier(i))) => Address::ToDefine(i),
+            _ => panic!("There should be a label after \"{} &\"", ins),
+        }
+    }
+    /// Parse the `#name` operand of `ins` and give back the position of the global
+    fn parse_global(&mut self, ins: &str) -> usize {
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::HashTag) => {}
+            _ => panic!("There should be an hashtag (#) after \"{}\"", ins),
+        }
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Identifier(i))) => self
+                .globals
+                .iter()
+                .position(|g| g.id == i)
+                .unwrap_or_else(|| panic!("The global \"{}\" isn't declared", i)),
+            _ => panic!("There should be a global name after \"{} #\"", ins),
+        }
+    }
+    /// Parse `@class Name extends Parent` (the parent being optional), followed by
+    /// its `field name` & `method name &label`
+    fn parse_class(&mut self) {
+        let name = match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Identifier(i))) => i,
+            _ => panic!("There should be a class name after \"@class\""),
+        };
+        if self.classes.iter().any(|c| c.name == name.as_str()) {
+            panic!("The class \"{}\" is declared twice", name)
+        }
+        let mut class = ClassDecl {
+            name: name.as_str().to_owned(),
+            parent: None,
+            fields: vec![],
+            methods: vec![],
+        };
+        if let Some(TokenKind::Keyword(k)) = self.tokens.peek().map(|t| t.kind()) {
+            if k.as_str() == "extends" {
+                self.tokens.next();
+                class.parent = match self.tokens.next().map(|t| t.kind()) {
+                    Some(TokenKind::Literal(Literal::Identifier(i))) => Some(self.class_index(i)),
+                    _ => panic!("There should be a class name after \"extends\""),
+                };
+            }
+        }
+        loop {
+            let member = match self.tokens.peek().map(|t| t.kind()) {
+                Some(TokenKind::Keyword(k)) if k.as_str() == "field" || k.as_str() == "method" => k,
+                _ => break,
+            };
+            self.tokens.next();
+            let member_name = match self.tokens.next().map(|t| t.kind()) {
+                Some(TokenKind::Literal(Literal::Identifier(i))) => i,
+                _ => panic!(
+                    "There should be a name after \"{}\" in \"{}\"",
+                    member, name
+                ),
+            };
+            if member.as_str() == "field" {
+                class.fields.push(member_name.as_str().to_owned());
+                continue;
+            }
+            match self.tokens.next().map(|t| t.kind()) {
+                Some(TokenKind::Ampersand) => {}
+                _ => panic!(
+                    "There should be an ampersand (&) after \"method {}\"",
+                    member_name
+                ),
+            }
+            match self.tokens.next().map(|t| t.kind()) {
+                Some(TokenKind::Literal(Literal::Identifier(label))) => {
+                    self.methods
+                        .push((self.classes.len(), member_name.as_str().to_owned(), label))
+                }
+                _ => panic!("There should be a label after \"method {} &\"", member_name),
+            }
+        }
+        self.classes.push(class);
+    }
+    fn class_index(&self, name: Intern<String>) -> usize {
+        self.classes
+            .iter()
+            .position(|c| c.name == name.as_str())
+            .unwrap_or_else(|| panic!("The class \"{}\" isn't declared", name))
+    }
+    /// Parse the `#Class` operand of `ins` and give back the position of the class
+    fn parse_class_operand(&mut self, ins: &str) -> usize {
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::HashTag) => {}
+            _ => panic!("There should be an hashtag (#) after \"{}\"", ins),
+        }
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Identifier(i))) => self.class_index(i),
+            _ => panic!("There should be a class name after \"{} #\"", ins),
+        }
+    }
+    /// Parse the `#Class.field` operand of `ins` into (class, field),
+    /// the class being the one declaring the field, which may be a parent of `Class`
+    fn parse_field(&mut self, ins: &str) -> (usize, usize) {
+        let mut class = self.parse_class_operand(ins);
+        match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Dot) => {}
+            _ => panic!("There should be a dot (.) after \"{} #class\"", ins),
+        }
+        let field = match self.tokens.next().map(|t| t.kind()) {
+            Some(TokenKind::Literal(Literal::Identifier(i))) => i,
+            _ => panic!("There should be a field name after \"{} #class.\"", ins),
+        };
+        loop {
+            let c = &self.classes[class];
+            if let Some(pos) = c.fields.iter().position(|f| *f == field.as_str()) {
+                return (class, pos);
+            }
+            class = c.parent.unwrap_or_else(|| {
+                panic!(
+                    "The class \"{}\" has no field \"{}\"",
+                    self.classes[class].name, field
+                )
+            });
+        }
+    }
     fn is(tok: Option<Token>, t: TokenKind) -> bool {
         if let Some(tok) = tok {
             tok.kind() == t
@@ -112,7 +369,18 @@ impl Parser {
                         if self.tokens.peek().is_none() {
                             panic!("There should be a constant definition after an \"@\"")
                         }
-                        let t = match self.tokens.next().unwrap().kind() {
+                        let mut kind = self.tokens.next().unwrap().kind();
+                        if kind == TokenKind::Keyword(Intern::new(String::from("class"))) {
+                            self.parse_class();
+                            continue;
+                        }
+                        // `@global type name value` declares a mutable global instead
+                        let global =
+                            kind == TokenKind::Keyword(Intern::new(String::from("global")));
+                        if global {
+                            kind = self.tokens.next().unwrap().kind();
+                        }
+                        let t = match kind {
                             TokenKind::Keyword(k) => match k.as_str() {
                                 "int" => Type::I64,
                                 "float" => Type::F64,
@@ -142,13 +410,30 @@ impl Parser {
                                 (Type::String, Literal::Float(f)) => {
                                     VMData::new_string(ObjectIndex::new(f as u64))
                                 }
+                                (Type::String, Literal::StringLiteral(s)) if !global => {
+                                    self.strings.push((constants.len(), s.as_str().to_owned()));
+                                    VMData::new_unit()
+                                }
+                                (Type::String, Literal::StringLiteral(_)) => panic!(
+                                    "The global \"{}\" can't be initialized with a string literal",
+                                    name
+                                ),
+                                (Type::Char, Literal::Float(f)) => VMData::new_char(
+                                    char::from_u32(f as u32)
+                                        .unwrap_or_else(|| panic!("{} isn't a valid char", f)),
+                                ),
+                                (Type::Bool, Literal::Float(f)) => VMData::new_bool(f != 0.0),
                                 _ => {
                                     unreachable!("need a correct value based on the type")
                                 }
                             },
                             _ => unreachable!("Need to reach it"),
                         };
-                        constants.push(Constant { id: name, value })
+                        if global {
+                            self.globals.push(Constant { id: name, value })
+                        } else {
+                            constants.push(Constant { id: name, value })
+                        }
                     }
                     _ => return Ok(constants),
                 }
@@ -202,74 +487,27 @@ impl Parser {
                 }
             }
             self.blocks = blocks.clone();
+            for (class, method, label) in std::mem::take(&mut self.methods) {
+                match resolve(&blocks, &Address::ToDefine(label)) {
+                    Address::Val(v) => self.classes[class].methods.push((method, v)),
+                    Address::ToDefine(_) => panic!(
+                        "The method \"{}\" of \"{}\" points to \"{}\" which isn't defined",
+                        method, self.classes[class].name, label
+                    ),
+                }
+            }
             let mut ins: Vec<Instruction> = vec![];
             for b in &blocks {
                 for i in &b.ins {
-                    match i {
-                        Instruction::Call(a) => {
-                            let mut position = 0;
-                            blocks.iter().for_each(|b| {
-                                if b.id
-                                    == match a {
-                                        Address::ToDefine(i) => *i,
-                                        Address::Val(_v) => panic!("blabla"),
-                                    }
-                                {
-                                    ins.push(Instruction::Call(Address::Val(position)));
-                                    return;
-                                }
-                                position += b.ins.len();
-                            })
-                        }
-                        Instruction::Jmp(a) => {
-                            let mut position = 0;
-                            blocks.iter().for_each(|b| {
-                                if b.id
-                                    == match a {
-                                        Address::ToDefine(i) => *i,
-                                        Address::Val(_v) => panic!("blabla"),
-                                    }
-                                {
-                                    ins.push(Instruction::Jmp(Address::Val(position)));
-                                    return;
-                                }
-                                position += b.ins.len();
-                            })
-                        }
-                        Instruction::JmpNZ(a) => {
-                            let mut position = 0;
-                            blocks.iter().for_each(|b| {
-                                if b.id
-                                    == match a {
-                                        Address::ToDefine(i) => *i,
-                                        Address::Val(_v) => panic!("blabla"),
-                                    }
-                                {
-                                    ins.push(Instruction::JmpNZ(Address::Val(position)));
-                                    return;
-                                }
-                                position += b.ins.len();
-                            })
-                        }
-                        Instruction::JmpZ(a) => {
-                            let mut position = 0;
-                            blocks.iter().for_each(|b| {
-                                if b.id
-                                    == match a {
-                                        Address::ToDefine(i) => *i,
-                                        Address::Val(_v) => panic!("blabla"),
-                                    }
-                                {
-                                    ins.push(Instruction::JmpZ(Address::Val(position)));
-                                    return;
-                                }
-                                position += b.ins.len();
-                            })
-                        }
-                        _ => {
-                            ins.push(*i);
-                        }
-                    }
+                    ins.push(match i {
+                        Instruction::Call(a) => Instruction::Call(resolve(&blocks, a)),
+                        Instruction::Jmp(a) => Instruction::Jmp(resolve(&blocks, a)),
+                        Instruction::JmpNZ(a) => Instruction::JmpNZ(resolve(&blocks, a)),
+                        Instruction::JmpZ(a) => Instruction::JmpZ(resolve(&blocks, a)),
+                        Instruction::TryBegin(a) => Instruction::TryBegin(resolve(&blocks, a)),
+                        Instruction::CoroCreate(a) => Instruction::CoroCreate(resolve(&blocks, a)),
+                        _ => *i,
+                    });
                 }
             }
             Ok(ins)
@@ -281,6 +519,7 @@ impl Parser {
         let mut block = Block {
             id: tag,
             ins: vec![],
+            spans: vec![],
         };
         loop {
             if let Some(t) = self.tokens.peek() {
@@ -288,6 +527,35 @@ impl Parser {
                 let end = t.end();
                 let k = self.tokens.peek().unwrap().kind();
                 match k {
+                    // `.args $n` & `.locals $n` declare the frame of the block
+                    TokenKind::Dot => {
+                        self.tokens.next();
+                        let directive = match self.tokens.next().map(|t| t.kind()) {
+                            Some(TokenKind::Keyword(k)) => k,
+                            _ => panic!(
+                                "There should be a directive after \".\" [{}:{}]",
+                                start, end
+                            ),
+                        };
+                        let n = self.parse_operand(directive.as_str());
+                        let (mut args, mut locals) = match block.ins.as_slice() {
+                            [] => (0, 0),
+                            [Instruction::Enter(args, locals)] => (*args, *locals),
+                            _ => panic!(
+                                "\".{}\" should be at the start of \"{}\" [{}:{}]",
+                                directive, block.id, start, end
+                            ),
+                        };
+                        match directive.as_str() {
+                            "args" => args = n,
+                            "locals" => locals = n,
+                            _ => panic!("Unknown directive \".{}\" [{}:{}]", directive, start, end),
+                        }
+                        if block.ins.is_empty() {
+                            block.spans.push((start, end));
+                        }
+                        block.ins = vec![Instruction::Enter(args, locals)];
+                    }
                     TokenKind::Keyword(k) => {
                         self.tokens.next();
                         match k.as_str() {
@@ -355,8 +623,9 @@ impl Parser {
                                     let end = t.end();
                                     if t.kind() == TokenKind::DollarSign {
                                         if let Some(t) = self.tokens.next() {
-                                            if let TokenKind::Literal(Literal::Int(i)) = t.kind() {
-                                                block.ins.push(Instruction::PushU(i as u64))
+                                            if let TokenKind::Literal(Literal::Float(f)) = t.kind()
+                                            {
+                                                block.ins.push(Instruction::PushU(f as u64))
                                             }
                                         } else {
                                             panic!(
@@ -417,6 +686,14 @@ impl Parser {
                                     )
                                 }
                             }
+                            "load_global" => {
+                                let g = self.parse_global("load_global");
+                                block.ins.push(Instruction::LoadGlobal(g))
+                            }
+                            "store_global" => {
+                                let g = self.parse_global("store_global");
+                                block.ins.push(Instruction::StoreGlobal(g))
+                            }
                             "pop" => block.ins.push(Instruction::Pop),
                             "add_i" => block.ins.push(Instruction::AddI),
                             "add_u" => block.ins.push(Instruction::AddU),
@@ -430,6 +707,102 @@ impl Parser {
                             "div_i" => block.ins.push(Instruction::DivI),
                             "div_u" => block.ins.push(Instruction::DivU),
                             "div_f" => block.ins.push(Instruction::DivF),
+                            "add_i_checked" => {
+                                block.ins.push(Instruction::AddIWith(Overflow::Checked))
+                            }
+                            "add_i_wrapping" => {
+                                block.ins.push(Instruction::AddIWith(Overflow::Wrapping))
+                            }
+                            "add_i_saturating" => {
+                                block.ins.push(Instruction::AddIWith(Overflow::Saturating))
+                            }
+                            "sub_i_checked" => {
+                                block.ins.push(Instruction::SubIWith(Overflow::Checked))
+                            }
+                            "sub_i_wrapping" => {
+                                block.ins.push(Instruction::SubIWith(Overflow::Wrapping))
+                            }
+                            "sub_i_saturating" => {
+                                block.ins.push(Instruction::SubIWith(Overflow::Saturating))
+                            }
+                            "mul_i_checked" => {
+                                block.ins.push(Instruction::MulIWith(Overflow::Checked))
+                            }
+                            "mul_i_wrapping" => {
+                                block.ins.push(Instruction::MulIWith(Overflow::Wrapping))
+                            }
+                            "mul_i_saturating" => {
+                                block.ins.push(Instruction::MulIWith(Overflow::Saturating))
+                            }
+                            "add_u_checked" => {
+                                block.ins.push(Instruction::AddUWith(Overflow::Checked))
+                            }
+                            "add_u_wrapping" => {
+                                block.ins.push(Instruction::AddUWith(Overflow::Wrapping))
+                            }
+                            "add_u_saturating" => {
+                                block.ins.push(Instruction::AddUWith(Overflow::Saturating))
+                            }
+                            "sub_u_checked" => {
+                                block.ins.push(Instruction::SubUWith(Overflow::Checked))
+                            }
+                            "sub_u_wrapping" => {
+                                block.ins.push(Instruction::SubUWith(Overflow::Wrapping))
+                            }
+                            "sub_u_saturating" => {
+                                block.ins.push(Instruction::SubUWith(Overflow::Saturating))
+                            }
+                            "mul_u_checked" => {
+                                block.ins.push(Instruction::MulUWith(Overflow::Checked))
+                            }
+                            "mul_u_wrapping" => {
+                                block.ins.push(Instruction::MulUWith(Overflow::Wrapping))
+                            }
+                            "mul_u_saturating" => {
+                                block.ins.push(Instruction::MulUWith(Overflow::Saturating))
+                            }
+                            "div_i_checked" => {
+                                block.ins.push(Instruction::DivIWith(Overflow::Checked))
+                            }
+                            "div_i_wrapping" => {
+                                block.ins.push(Instruction::DivIWith(Overflow::Wrapping))
+                            }
+                            "div_i_saturating" => {
+                                block.ins.push(Instruction::DivIWith(Overflow::Saturating))
+                            }
+                            "neg_i_checked" => {
+                                block.ins.push(Instruction::NegIWith(Overflow::Checked))
+                            }
+                            "neg_i_wrapping" => {
+                                block.ins.push(Instruction::NegIWith(Overflow::Wrapping))
+                            }
+                            "neg_i_saturating" => {
+                                block.ins.push(Instruction::NegIWith(Overflow::Saturating))
+                            }
+                            "abs_i_checked" => {
+                                block.ins.push(Instruction::AbsIWith(Overflow::Checked))
+                            }
+                            "abs_i_wrapping" => {
+                                block.ins.push(Instruction::AbsIWith(Overflow::Wrapping))
+                            }
+                            "abs_i_saturating" => {
+                                block.ins.push(Instruction::AbsIWith(Overflow::Saturating))
+                            }
+                            "mod_i" => block.ins.push(Instruction::ModI),
+                            "mod_u" => block.ins.push(Instruction::ModU),
+                            "neg_i" => block.ins.push(Instruction::NegI),
+                            "abs_i" => block.ins.push(Instruction::AbsI),
+                            "min_i" => block.ins.push(Instruction::MinI),
+                            "max_i" => block.ins.push(Instruction::MaxI),
+                            "min_u" => block.ins.push(Instruction::MinU),
+                            "max_u" => block.ins.push(Instruction::MaxU),
+                            "band" => block.ins.push(Instruction::BAnd),
+                            "bor" => block.ins.push(Instruction::BOr),
+                            "bxor" => block.ins.push(Instruction::BXor),
+                            "bnot" => block.ins.push(Instruction::BNot),
+                            "shl" => block.ins.push(Instruction::Shl),
+                            "shr" => block.ins.push(Instruction::Shr),
+                            "sar" => block.ins.push(Instruction::Sar),
                             "dup" => block.ins.push(Instruction::Dup),
                             "swap" => block.ins.push(Instruction::Swap),
                             "rot" => block.ins.push(Instruction::Rot),
@@ -564,15 +937,21 @@ impl Parser {
                             }
                             "extern_call" => {
                                 if let Some(t) = self.tokens.next() {
-                                    if t.kind() == TokenKind::DollarSign {
+                                    if t.kind() == TokenKind::AtSign {
                                         if let Some(t) = self.tokens.next() {
-                                            if let TokenKind::Literal(Literal::Float(f)) = t.kind()
+                                            if let TokenKind::Literal(Literal::Identifier(i)) =
+                                                t.kind()
                                             {
-                                                block.ins.push(Instruction::ExternCall(f as usize))
+                                                let pos = self.extern_index(i);
+                                                block.ins.push(Instruction::ExternCall(pos))
                                             } else {
-                                                panic!("there should be a number here")
+                                                panic!("there should be an extern name here")
                                             }
                                         }
+                                    } else {
+                                        panic!(
+                                            "There should be an at sign (@) after \"extern_call\""
+                                        )
                                     }
                                 }
                             }
@@ -620,6 +999,39 @@ impl Parser {
                                 }
                             }
                             "ret" => block.ins.push(Instruction::Ret),
+                            "load_arg" => {
+                                let n = self.parse_operand("load_arg");
+                                block.ins.push(Instruction::LoadArg(n))
+                            }
+                            "load_local" => {
+                                let n = self.parse_operand("load_local");
+                                block.ins.push(Instruction::LoadLocal(n))
+                            }
+                            "store_local" => {
+                                let n = self.parse_operand("store_local");
+                                block.ins.push(Instruction::StoreLocal(n))
+                            }
+                            "try_begin" => {
+                                let label = self.parse_label("try_begin");
+                                block.ins.push(Instruction::TryBegin(label))
+                            }
+                            "try_end" => block.ins.push(Instruction::TryEnd),
+                            "throw" => block.ins.push(Instruction::Throw),
+                            "coro_create" => {
+                                let label = self.parse_label("coro_create");
+                                block.ins.push(Instruction::CoroCreate(label))
+                            }
+                            "coro_resume" => block.ins.push(Instruction::CoroResume),
+                            "yield" => block.ins.push(Instruction::Yield),
+                            "coro_status" => block.ins.push(Instruction::CoroStatus),
+                            "send" => {
+                                let n = self.parse_operand("send");
+                                block.ins.push(Instruction::Send(n))
+                            }
+                            "recv" => {
+                                let n = self.parse_operand("recv");
+                                block.ins.push(Instruction::Recv(n))
+                            }
                             "print" => block.ins.push(Instruction::Print),
                             "print_char" => block.ins.push(Instruction::PrintChar),
                             "read" => block.ins.push(Instruction::Read),
@@ -751,6 +1163,71 @@ impl Parser {
                             "str_len" => block.ins.push(Instruction::StrLen),
                             "write_char" => block.ins.push(Instruction::WriteCharToString),
                             "read_char" => block.ins.push(Instruction::ReadCharFromString),
+                            "str_concat" => block.ins.push(Instruction::StrConcat),
+                            "str_slice" => block.ins.push(Instruction::StrSlice),
+                            "str_find" => block.ins.push(Instruction::StrFind),
+                            "str_upper" => block.ins.push(Instruction::StrUpper),
+                            "str_lower" => block.ins.push(Instruction::StrLower),
+                            "parse_int" => block.ins.push(Instruction::ParseInt),
+                            "parse_float" => block.ins.push(Instruction::ParseFloat),
+                            "to_str" => block.ins.push(Instruction::ToStr),
+                            "create_vec" => {
+                                let tag = match self.tokens.next().map(|t| t.kind()) {
+                                    Some(TokenKind::Keyword(k)) => type_tag(k.as_str()),
+                                    _ => None,
+                                };
+                                match tag {
+                                    Some(tag) => block.ins.push(Instruction::CreateVec(tag)),
+                                    None => panic!(
+                                        "There should be a type after \"create_vec\" [{}:{}]",
+                                        start, end
+                                    ),
+                                }
+                            }
+                            "vec_push" => block.ins.push(Instruction::VecPush),
+                            "vec_pop" => block.ins.push(Instruction::VecPop),
+                            "vec_get" => block.ins.push(Instruction::VecGet),
+                            "vec_set" => block.ins.push(Instruction::VecSet),
+                            "vec_len" => block.ins.push(Instruction::VecLen),
+                            "vec_insert" => block.ins.push(Instruction::VecInsert),
+                            "vec_remove" => block.ins.push(Instruction::VecRemove),
+                            "create_map" => block.ins.push(Instruction::CreateMap),
+                            "map_get" => block.ins.push(Instruction::MapGet),
+                            "map_set" => block.ins.push(Instruction::MapSet),
+                            "map_has" => block.ins.push(Instruction::MapHas),
+                            "map_remove" => block.ins.push(Instruction::MapRemove),
+                            "map_len" => block.ins.push(Instruction::MapLen),
+                            "map_key_at" => block.ins.push(Instruction::MapKeyAt),
+                            "new_class" => {
+                                let class = self.parse_class_operand("new_class");
+                                block.ins.push(Instruction::NewClass(class))
+                            }
+                            "get_field" => {
+                                let (class, field) = self.parse_field("get_field");
+                                block.ins.push(Instruction::GetField(class, field))
+                            }
+                            "set_field" => {
+                                let (class, field) = self.parse_field("set_field");
+                                block.ins.push(Instruction::SetField(class, field))
+                            }
+                            "call_method" => {
+                                match self.tokens.next().map(|t| t.kind()) {
+                                    Some(TokenKind::HashTag) => {}
+                                    _ => panic!(
+                                        "There should be an hashtag (#) after \"call_method\" [{}:{}]",
+                                        start, end
+                                    ),
+                                }
+                                match self.tokens.next().map(|t| t.kind()) {
+                                    Some(TokenKind::Literal(Literal::Identifier(i))) => {
+                                        block.ins.push(Instruction::CallMethod(i))
+                                    }
+                                    _ => panic!(
+                                        "There should be a method name after \"call_method #\" [{}:{}]",
+                                        start, end
+                                    ),
+                                }
+                            }
                             "eq" => block.ins.push(Instruction::Eq),
                             "neq" => block.ins.push(Instruction::Neq),
                             "lt" => block.ins.push(Instruction::Lt),
@@ -764,6 +1241,9 @@ impl Parser {
                             "nop" => block.ins.push(Instruction::Nop),
                             _ => break,
                         }
+                        if block.spans.len() < block.ins.len() {
+                            block.spans.push((start, end));
+                        }
                     }
                     _ => return Ok(block),
                 }
@@ -773,3 +1253,146 @@ impl Parser {
         Ok(block)
     }
 }
+
+/// Turn the keywords used as names into identifiers, so e.g. `yield:` or `load_const #args`
+/// still work with the keywords added over time. A name is:
+/// - a label, followed by `:` or after `&`
+/// - after `#`, or the field in `#Class.field`
+/// - the extern in `extern_call @name`
+/// - the constant or global in `@type name` & `@global type name`
+/// - the class & its parent in `@class Name extends Parent`, or a member after `field` & `method`
+fn contextual_keywords(mut tokens: Vec<Token>) -> Vec<Token> {
+    let keyword = |t: Option<&Token>, words: &[&str]| matches!(t.map(|t| t.kind()), Some(TokenKind::Keyword(k)) if words.contains(&k.as_str()));
+    let kind = |t: Option<&Token>| t.map(|t| t.kind());
+    const TYPES: &[&str] = &["int", "u_int", "float", "char", "bool", "object", "string"];
+    for i in 0..tokens.len() {
+        let TokenKind::Keyword(k) = tokens[i].kind() else {
+            continue;
+        };
+        // Looking back at the tokens already turned into identifiers
+        let back = |n: usize| i.checked_sub(n).map(|j| &tokens[j]);
+        let is_name = kind(tokens.get(i + 1)) == Some(TokenKind::Colon)
+            || matches!(
+                kind(back(1)),
+                Some(TokenKind::Ampersand | TokenKind::HashTag)
+            )
+            || (kind(back(1)) == Some(TokenKind::AtSign) && keyword(back(2), &["extern_call"]))
+            || (kind(back(1)) == Some(TokenKind::Dot)
+                && matches!(
+                    kind(back(2)),
+                    Some(TokenKind::Literal(Literal::Identifier(_)))
+                )
+                && kind(back(3)) == Some(TokenKind::HashTag))
+            || (keyword(back(1), TYPES)
+                && (kind(back(2)) == Some(TokenKind::AtSign) || keyword(back(2), &["global"])))
+            || (keyword(back(1), &["class"]) && kind(back(2)) == Some(TokenKind::AtSign))
+            || keyword(back(1), &["extends", "field", "method"]);
+        if is_name {
+            tokens[i] = Token::new(tokens[i].span(), TokenKind::Literal(Literal::Identifier(k)));
+        }
+    }
+    tokens
+}
+
+/// Tag of the values of a type keyword (`int`, `float`...)
+fn type_tag(keyword: &str) -> Option<TAG> {
+    match keyword {
+        "int" => Some(VMData::TAG_I64),
+        "u_int" => Some(VMData::TAG_U64),
+        "float" => Some(VMData::TAG_FLOAT),
+        "char" => Some(VMData::TAG_CHAR),
+        "bool" => Some(VMData::TAG_BOOL),
+        "string" => Some(VMData::TAG_STR),
+        "object" => Some(257),
+        _ => None,
+    }
+}
+
+/// Turn a label into the position of its block.
+/// Unknown labels stay [`Address::ToDefine`] so the verifier can report them.
+fn resolve(blocks: &[Block], address: &Address) -> Address {
+    match address {
+        Address::ToDefine(label) => {
+            let mut position = 0;
+            for b in blocks {
+                if b.id == *label {
+                    return Address::Val(position);
+                }
+                position += b.ins.len();
+            }
+            *address
+        }
+        Address::Val(_) => *address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruction::compiler::assemble;
+    use crate::instruction::{Address, Instruction};
+
+    #[test]
+    fn instructions_are_located_in_the_source() {
+        let program = assemble(
+            ".section
+.code
+main:
+    push_i $1
+  ; a comment
+\tpush_i $2
+    add_i
+    hlt",
+        );
+        let debug = program.debug.unwrap();
+        let location = |pc| {
+            let l = debug.location(pc).unwrap();
+            (l.path, l.line, l.col)
+        };
+        assert_eq!(location(0), ("<test>".to_owned(), 4, 5));
+        assert_eq!(location(1), ("<test>".to_owned(), 6, 2));
+        assert_eq!(location(2), ("<test>".to_owned(), 7, 5));
+        assert_eq!(debug.location(4), None);
+    }
+
+    #[test]
+    fn keywords_can_be_names() {
+        let program = assemble(
+            ".section
+    @int args 2
+    @global int send 3
+    @class class
+    @class field extends class
+        field method
+        field yield
+        method recv &throw
+.code
+main:
+    load_const #args
+    load_global #send
+    store_global #send
+    new_class #field
+    get_field #field.yield
+    call_method #recv
+    extern_call @locals
+    jmp &yield
+yield:
+    call &throw
+    hlt
+throw:
+    .args $1
+    ret",
+        );
+        assert_eq!(program.const_name, ["args"]);
+        assert_eq!(program.global_name, ["send"]);
+        assert_eq!(program.extern_name, ["locals"]);
+        let names: Vec<&str> = program.classes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["class", "field"]);
+        assert_eq!(program.classes[1].parent, Some(0));
+        assert_eq!(program.classes[1].fields, ["method", "yield"]);
+        assert_eq!(program.classes[1].methods, [("recv".to_owned(), 10)]);
+        let labels: Vec<&str> = program.fn_name.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(labels, ["main", "yield", "throw"]);
+        assert_eq!(program.ins[7], Instruction::Jmp(Address::Val(8)));
+        assert_eq!(program.ins[8], Instruction::Call(Address::Val(10)));
+    }
+}
@@ -3,14 +3,19 @@
 
 use internment::Intern;
 
+use crate::runtime::error::VMErrorKind;
+
 pub mod compiler;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     PushI(i64),
     PushU(u64),
     PushF(f64),
     LoadConst(usize),
+    LoadGlobal(usize),
+    //Pop the top of the stack into a global
+    StoreGlobal(usize),
     Pop,
 
     AddI,
@@ -25,6 +30,37 @@ pub enum Instruction {
     DivI,
     DivU,
     DivF,
+    //Same as the instructions above, whatever the overflow mode of the VM is
+    AddIWith(Overflow),
+    SubIWith(Overflow),
+    MulIWith(Overflow),
+    AddUWith(Overflow),
+    SubUWith(Overflow),
+    MulUWith(Overflow),
+    DivIWith(Overflow),
+    //Remainder of the truncated division, it has the sign of the dividend
+    ModI,
+    ModU,
+    //`i64::MIN` has no positive counterpart, so `neg_i` & `abs_i` overflow on it
+    NegI,
+    AbsI,
+    NegIWith(Overflow),
+    AbsIWith(Overflow),
+    MinI,
+    MaxI,
+    MinU,
+    MaxU,
+
+    //Bitwise operations work on both i64 & u64, the 2 operands must have the same type
+    BAnd,
+    BOr,
+    BXor,
+    BNot,
+    //Shift by the amount on top of the stack, an int or an uint which can't be negative.
+    //Shifting by 64 or more gives 0, except for `sar` which fills with the sign of an i64
+    Shl,
+    Shr,
+    Sar,
 
     //Duplicate the top value of the stack
     Dup,
@@ -39,6 +75,33 @@ pub enum Instruction {
     ExternCall(usize),
     Call(Address), //Should it uses the top of the stack value as adress if the Address is `ToDefine`? Or maybe adding another way?
     Ret,
+    //Set up the frame of the current call as `Enter(args, locals)`, it's generated by the
+    //`.args` & `.locals` directives at the start of a block
+    Enter(usize, usize),
+    LoadArg(usize),
+    LoadLocal(usize),
+    //Pop the top of the stack into a local
+    StoreLocal(usize),
+    //Until the matching `TryEnd`, a thrown value jumps to the address with the stack
+    //back to its height at `TryBegin` & the value on top of it
+    TryBegin(Address),
+    TryEnd,
+    //Throw the top of the stack to the innermost `TryBegin`, even across calls
+    Throw,
+    //Create a suspended coroutine running the function at the address, with its own stack
+    CoroCreate(Address),
+    //Pop a value & a coroutine, then run the coroutine with the value on top of its stack
+    //until it yields or returns, giving back the yielded or returned value
+    CoroResume,
+    //Give back the top of the stack to the resumer of the current coroutine & suspend it.
+    //Once resumed, the value given to `CoroResume` is on top of the stack
+    Yield,
+    //Replace a coroutine by its status as an int, see `CoroutineStatus`
+    CoroStatus,
+    //Pop a value & send a copy of it, with every object it references, on a channel of the VM
+    Send(usize),
+    //Push the next message of a channel of the VM, the program is blocked until there's one
+    Recv(usize),
 
     Print,
     PrintChar,
@@ -53,6 +116,47 @@ pub enum Instruction {
     StrLen,
     WriteCharToString,
     ReadCharFromString,
+    //Every string operation below gives back a new string
+    StrConcat,
+    //Chars in [start, end) of a string, the end being on top of the stack
+    StrSlice,
+    //Char index of the first occurrence of the top string in the one below, -1 if there's none
+    StrFind,
+    StrUpper,
+    StrLower,
+    ParseInt,
+    ParseFloat,
+    //Format a number, a char or a bool into a new string
+    ToStr,
+
+    //Create an empty vector holding values of the given tag
+    CreateVec(u64),
+    VecPush,
+    VecPop,
+    VecGet,
+    VecSet,
+    VecLen,
+    VecInsert,
+    VecRemove,
+
+    //Maps are keyed by unit, numbers, chars, bools & string contents
+    CreateMap,
+    MapGet,
+    MapSet,
+    MapHas,
+    //Remove a key & give back its value
+    MapRemove,
+    MapLen,
+    //Key of the i-th entry, to iterate from 0 to `map_len`
+    MapKeyAt,
+
+    //Create an instance of a class declared in the .section
+    NewClass(usize),
+    //`GetField(class, field)`, the field being declared by `class` itself
+    GetField(usize, usize),
+    SetField(usize, usize),
+    //Call a method of the object on top of the stack, which stays there as its last argument
+    CallMethod(Intern<String>),
 
     Eq,
     Neq,
@@ -76,18 +180,41 @@ pub enum Instruction {
     Nop,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// What integer arithmetic does when the result doesn't fit in its type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Fail with [`VMErrorKind::IntegerOverflow`]
+    Checked,
+    /// Wrap around in two's complement, like the release build used to do
+    #[default]
+    Wrapping,
+    /// Clamp to the bounds of the type
+    Saturating,
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Overflow::Checked => write!(f, "checked"),
+            Overflow::Wrapping => write!(f, "wrapping"),
+            Overflow::Saturating => write!(f, "saturating"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Address {
     ToDefine(Intern<String>),
     Val(usize),
 }
 
-impl From<&Address> for usize {
+impl TryFrom<&Address> for usize {
+    type Error = VMErrorKind;
     #[inline(always)]
-    fn from(value: &Address) -> Self {
+    fn try_from(value: &Address) -> Result<Self, Self::Error> {
         match value {
-            Address::ToDefine(_i) => panic!(),
-            Address::Val(addr) => *addr,
+            Address::ToDefine(i) => Err(VMErrorKind::UnresolvedAddress(*i)),
+            Address::Val(addr) => Ok(*addr),
         }
     }
 }
@@ -6,11 +6,22 @@ pub mod runtime;
 pub mod prelude {
     pub use crate::{
         instruction::{
-            compiler::{lexer::*, parser::*},
-            Address, Instruction,
+            compiler::{bytecode::BytecodeError, disassembler::disassemble, lexer::*, parser::*},
+            Address, Instruction, Overflow,
         },
         memory::{object_map::*, stack::*, vm_data::VMData},
-        runtime::{vm_state::VMState, CallBack, VM},
+        runtime::{
+            channel::{Channel, Message},
+            debugger::{Debugger, Stop},
+            error::{TraceFrame, VMError, VMErrorKind},
+            observer::{JsonTracer, Observer},
+            profiler::{FunctionProfile, Profiler},
+            verifier::{Diagnostic, DiagnosticKind, Verified},
+            vm_state::VMState,
+            workers::Workers,
+            CallBack, CallFrame, ClassDescriptor, CoroutineId, ExecutionOutcome, ExternFn, Handler,
+            VM,
+        },
     };
     pub use internment::Intern;
 }
@@ -1,6 +1,7 @@
 use atlas_vm::instruction::compiler::parser::Parser;
 
 use atlas_vm::memory::vm_data::VMData;
+use atlas_vm::runtime::error::VMErrorKind;
 use atlas_vm::runtime::vm_state::VMState;
 use atlas_vm::runtime::VM;
 
@@ -12,20 +13,36 @@ let tmp = std::time::Instant::now();
         lexer.set_source(content);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::identifier_system);
         lexer.add_system(atlas_vm::instruction::compiler::lexer::comment_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::negative_number_system);
+        lexer.add_system(atlas_vm::instruction::compiler::lexer::string_system);
         let res = lexer.tokenize();
         match res {
             Ok(t) => {
                 println!("Ok Lexer: {:?}", tmp.elapsed());
                 let tmp = std::time::Instant::now();
                 //t.clone().into_iter().for_each(|ins| println!("{:?}, ", ins.kind()));
-                let parser = Parser::parse(t);
+                let parser = Parser::parse(t, lexer.lines());
                 match parser {
                     Ok(code) => {
                         println!("Ok Parser: {:?}", tmp.elapsed());
                         let tmp = std::time::Instant::now();
                         //code.clone().into_iter().for_each(|ins| println!("{:?}", ins));
-                        let mut vm = VM::new(1, code.constants);
-                        vm.add_extern_call(fib_extern).execute(code.ins.as_slice());
+                        let mut vm = VM::new(1, code.constants.clone());
+                        vm.add_extern_fn("fib", &[VMData::TAG_I64], fib_extern);
+                        let ins = match vm.link(&code) {
+                            Ok(ins) => ins,
+                            Err(e) => panic!("{}", e),
+                        };
+                        let verified = match vm.verify(ins.as_slice()) {
+                            Ok(verified) => verified,
+                            Err(diagnostics) => {
+                                diagnostics.iter().for_each(|d| println!("{}", d));
+                                panic!("{} error(s) found", diagnostics.len());
+                            }
+                        };
+                        if let Err(e) = vm.execute_verified(verified) {
+                            panic!("{}", e);
+                        }
                         println!("{}", vm.object_map);
                         println!("Ok Excution: {:?}", tmp.elapsed())
                     }
@@ -43,7 +60,7 @@ let tmp = std::time::Instant::now();
     }
 }
 
-pub fn fib_extern(vm_state: VMState) -> Result<VMData, ()> {
+pub fn fib_extern(vm_state: VMState) -> Result<VMData, VMErrorKind> {
     fn fib(n: i64) -> i64 {
         if n < 2 {
             n
@@ -51,6 +68,6 @@ pub fn fib_extern(vm_state: VMState) -> Result<VMData, ()> {
             fib(n - 1) + fib(n - 2)
         }
     }
-    let res = fib(vm_state.stack.pop().expect("Stack Underflow").as_i64());
+    let res = fib(vm_state.stack.pop()?.try_i64()?);
     Ok(VMData::new_i64(res))
 }
@@ -1,16 +1,24 @@
-use crate::memory::vm_data::VMData;
+use std::collections::HashMap;
 
-use super::{stack::Stack, vm_data::TAG};
+use crate::memory::{stack::Stack, vm_data::VMData};
+
+use super::vm_data::TAG;
+use crate::runtime::{error::VMErrorKind, CallFrame, Handler};
+
+/// Number of live objects before the first garbage collection is triggered
+const GC_INITIAL_THRESHOLD: usize = 1024;
 
 #[derive(Debug)]
 pub struct Memory {
     mem: Vec<Object>,
     pub(crate) free: ObjectIndex,
     memory_pressure: usize,
+    /// Once `memory_pressure` reaches it, the VM should run a collection
+    gc_threshold: usize,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct ObjectIndex {
     pub(crate) idx: u64,
 }
@@ -53,6 +61,7 @@ impl Memory {
                 })
                 .collect(),
             memory_pressure: usize::default(),
+            gc_threshold: GC_INITIAL_THRESHOLD,
         }
     }
 
@@ -109,6 +118,29 @@ impl Memory {
         &mut self.mem[index.idx as usize]
     }
 
+    /// The object at `index`, if it's allocated
+    pub fn object(&self, index: ObjectIndex) -> Option<&Object> {
+        self.try_get(index).ok()
+    }
+
+    /// Same as `get` but fails if the index is out of bound or points to a free block
+    #[inline(always)]
+    pub(crate) fn try_get(&self, index: ObjectIndex) -> Result<&Object, VMErrorKind> {
+        match self.mem.get(index.idx as usize) {
+            Some(Object::Free { .. }) | None => Err(VMErrorKind::InvalidObject(index)),
+            Some(obj) => Ok(obj),
+        }
+    }
+
+    /// Same as `get_mut` but fails if the index is out of bound or points to a free block
+    #[inline(always)]
+    pub(crate) fn try_get_mut(&mut self, index: ObjectIndex) -> Result<&mut Object, VMErrorKind> {
+        match self.mem.get_mut(index.idx as usize) {
+            Some(Object::Free { .. }) | None => Err(VMErrorKind::InvalidObject(index)),
+            Some(obj) => Ok(obj),
+        }
+    }
+
     #[inline(always)]
     pub(crate) fn raw(&self) -> &[Object] {
         &self.mem
@@ -121,7 +153,11 @@ impl Memory {
 
     #[inline(always)]
     /// Return None if the ptr points to a free block and return the pointed object if not
-    pub(crate) fn free_obj(&mut self, index: ObjectIndex, stack: &mut Stack) -> Option<Object> {
+    pub(crate) fn free_obj<'a>(
+        &mut self,
+        index: ObjectIndex,
+        roots: impl IntoIterator<Item = &'a mut VMData>,
+    ) -> Option<Object> {
         if let &Object::Free { next: _ } = self.get(index) {
             None
         } else {
@@ -130,14 +166,134 @@ impl Memory {
             let repl = std::mem::replace(v, new_free);
             self.free = index;
             self.memory_pressure -= 1;
-            if self.memory_pressure < self.mem.len() / 2 {
-                self.shrink(stack)
-            }
+            self.shrink(roots);
             Some(repl)
         }
     }
-    //Should be doing something, but for now it's too hard for my little brain.
-    fn shrink(&mut self, _stack: &mut Stack) {}
+
+    /// Compact the memory if less than half of it is used
+    pub fn shrink<'a>(&mut self, roots: impl IntoIterator<Item = &'a mut VMData>) {
+        if self.should_shrink() {
+            self.compact(roots)
+        }
+    }
+
+    #[inline(always)]
+    pub fn should_shrink(&self) -> bool {
+        self.memory_pressure < self.mem.len() / 2
+    }
+
+    /// Move every live object to the front of the memory and give the unused space back.
+    ///
+    /// Every pointer held by an object or by one of the `roots` is rewritten to the new location,
+    /// so anything holding an `ObjectIndex` outside of them is invalidated.
+    pub fn compact<'a>(&mut self, roots: impl IntoIterator<Item = &'a mut VMData>) {
+        let mut forward: Vec<Option<ObjectIndex>> = vec![None; self.mem.len()];
+        let mut live = 0;
+        for (i, obj) in self.mem.iter().enumerate() {
+            if !matches!(obj, Object::Free { .. }) {
+                forward[i] = Some(ObjectIndex::new(live));
+                live += 1;
+            }
+        }
+        let live = live as usize;
+
+        let forward = |ptr: ObjectIndex| forward.get(ptr.idx as usize).copied().flatten();
+
+        let old = std::mem::take(&mut self.mem);
+        let new_size = live + (live / 10) + 1;
+        self.mem.reserve_exact(new_size);
+        for mut obj in old {
+            if !matches!(obj, Object::Free { .. }) {
+                obj.relocate(&forward);
+                self.mem.push(obj);
+            }
+        }
+        self.mem.resize(
+            new_size,
+            Object::Free {
+                next: ObjectIndex::new(0),
+            },
+        );
+        for root in roots {
+            relocate(root, &forward);
+        }
+        self.rebuild_free_list();
+    }
+
+    /// Number of objects currently alive in the memory
+    #[inline(always)]
+    pub fn memory_pressure(&self) -> usize {
+        self.memory_pressure
+    }
+
+    #[inline(always)]
+    pub fn should_collect(&self) -> bool {
+        self.memory_pressure >= self.gc_threshold
+    }
+
+    /// Set the number of live objects needed to trigger the next collection
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.gc_threshold = threshold;
+    }
+
+    /// Mark & sweep collection. Everything that can't be reached from `roots`
+    /// (through structures, classes, vectors & maps) is given back to the free list.
+    ///
+    /// Return the number of freed objects
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = VMData>) -> usize {
+        let mut marked = vec![false; self.mem.len()];
+        let mut gray: Vec<ObjectIndex> = roots
+            .into_iter()
+            .filter(|d| d.is_object())
+            .map(|d| d.as_object())
+            .collect();
+        while let Some(index) = gray.pop() {
+            let i = index.idx as usize;
+            match self.mem.get(i) {
+                // Pointers can be forged with `cast_to_ptr`, so they may not point to anything
+                Some(Object::Free { .. }) | None => {}
+                Some(obj) => {
+                    if !marked[i] {
+                        marked[i] = true;
+                        obj.trace(&mut gray);
+                    }
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for (obj, marked) in self.mem.iter_mut().zip(marked) {
+            if !marked && !matches!(obj, Object::Free { .. }) {
+                *obj = Object::Free {
+                    next: ObjectIndex::new(0),
+                };
+                freed += 1;
+            }
+        }
+        self.rebuild_free_list();
+        self.gc_threshold = GC_INITIAL_THRESHOLD.max(self.memory_pressure * 2);
+        freed
+    }
+
+    /// Link every free block together (in the same circular way as `Memory::new()`)
+    /// and recompute the memory pressure.
+    fn rebuild_free_list(&mut self) {
+        let free: Vec<usize> = self
+            .mem
+            .iter()
+            .enumerate()
+            .filter(|(_, obj)| matches!(obj, Object::Free { .. }))
+            .map(|(i, _)| i)
+            .collect();
+        for (n, i) in free.iter().enumerate() {
+            self.mem[*i] = Object::Free {
+                next: ObjectIndex::new(free[(n + 1) % free.len()] as u64),
+            };
+        }
+        self.free = ObjectIndex::new(free.first().copied().unwrap_or_default() as u64);
+        self.memory_pressure = self.mem.len() - free.len();
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -146,6 +302,8 @@ pub enum Object {
     Structure(Structure),
     Class(Class),
     Vector(Vector),
+    Map(Map),
+    Coroutine(Coroutine),
     Free { next: ObjectIndex },
 }
 
@@ -179,6 +337,18 @@ impl std::fmt::Display for Object {
                         str_
                     })
                 }
+                Object::Map(m) => {
+                    format!("Map {{ {} }}", {
+                        let mut str_ = String::new();
+                        m.entries
+                            .iter()
+                            .for_each(|(k, _, v)| str_.push_str(&format!("{}: {}, ", k, v)));
+                        str_
+                    })
+                }
+                Object::Coroutine(c) => {
+                    format!("Coroutine {{ {}, pc: {} }}", c.status, c.pc)
+                }
                 Object::Free { next } => {
                     format!("Free: {}", next)
                 }
@@ -187,11 +357,99 @@ impl std::fmt::Display for Object {
     }
 }
 
+/// Rewrite `data` if it points to an object that has been moved, `forward` giving the new
+/// location of a moved object
+#[inline(always)]
+fn relocate(data: &mut VMData, forward: &impl Fn(ObjectIndex) -> Option<ObjectIndex>) {
+    if data.is_object() {
+        // forged pointers to nothing are left untouched
+        if let Some(new) = forward(data.as_object()) {
+            *data = data.with_object(new);
+        }
+    }
+}
+
 impl Object {
     pub fn new(data: impl Into<Object>) -> Self {
         data.into()
     }
 
+    /// Rewrite every pointer held by this object, see `Memory::compact()`
+    pub(crate) fn relocate(&mut self, forward: &impl Fn(ObjectIndex) -> Option<ObjectIndex>) {
+        let fields: &mut [VMData] = match self {
+            Object::Structure(s) => &mut s.fields,
+            Object::Class(c) => {
+                let mut proto = c.prototype.as_deref_mut();
+                while let Some(p) = proto {
+                    p.fields.iter_mut().for_each(|d| relocate(d, forward));
+                    proto = p.prototype.as_deref_mut();
+                }
+                &mut c.fields
+            }
+            Object::Vector(v) => &mut v.vec,
+            Object::Map(m) => {
+                m.entries.iter_mut().for_each(|(_, k, v)| {
+                    relocate(k, forward);
+                    relocate(v, forward);
+                });
+                &mut []
+            }
+            Object::Coroutine(c) => {
+                c.stack.iter_mut().for_each(|d| relocate(d, forward));
+                &mut []
+            }
+            Object::String(_) | Object::Free { .. } => &mut [],
+        };
+        fields.iter_mut().for_each(|d| relocate(d, forward));
+    }
+
+    /// Push every object referenced by this one
+    pub(crate) fn trace(&self, gray: &mut Vec<ObjectIndex>) {
+        let fields: &[VMData] = match self {
+            Object::Structure(s) => &s.fields,
+            Object::Class(c) => {
+                let mut proto = c.prototype.as_deref();
+                while let Some(p) = proto {
+                    gray.extend(
+                        p.fields
+                            .iter()
+                            .filter(|d| d.is_object())
+                            .map(|d| d.as_object()),
+                    );
+                    proto = p.prototype.as_deref();
+                }
+                &c.fields
+            }
+            Object::Vector(v) => &v.vec,
+            Object::Map(m) => {
+                gray.extend(
+                    m.entries
+                        .iter()
+                        .flat_map(|(_, k, v)| [k, v])
+                        .filter(|d| d.is_object())
+                        .map(|d| d.as_object()),
+                );
+                &[]
+            }
+            Object::Coroutine(c) => {
+                gray.extend(
+                    c.stack
+                        .iter()
+                        .filter(|d| d.is_object())
+                        .map(|d| d.as_object()),
+                );
+                &[]
+            }
+            Object::String(_) | Object::Free { .. } => &[],
+        };
+        gray.extend(
+            fields
+                .iter()
+                .filter(|d| d.is_object())
+                .map(|d| d.as_object()),
+        );
+    }
+
     pub fn string(&self) -> &String {
         match &self {
             Object::String(s) => s,
@@ -247,6 +505,34 @@ impl Object {
             _ => unreachable!(),
         }
     }
+
+    pub fn map(&self) -> &Map {
+        match self {
+            Object::Map(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn map_mut(&mut self) -> &mut Map {
+        match self {
+            Object::Map(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn coroutine(&self) -> &Coroutine {
+        match self {
+            Object::Coroutine(c) => c,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn coroutine_mut(&mut self) -> &mut Coroutine {
+        match self {
+            Object::Coroutine(c) => c,
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl From<Structure> for Object {
@@ -273,21 +559,467 @@ impl From<Vector> for Object {
     }
 }
 
+impl From<Map> for Object {
+    fn from(value: Map) -> Self {
+        Object::Map(value)
+    }
+}
+
+impl From<Coroutine> for Object {
+    fn from(value: Coroutine) -> Self {
+        Object::Coroutine(value)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Structure {
     pub fields: Vec<VMData>,
 }
 
 #[derive(Clone, Debug)]
-///This should have a VTable
+///An instance of a class, its methods are in the vtable of `VM::classes[class]`
 pub struct Class {
+    ///Index of the class in the VM
+    pub class: usize,
+    ///The part of the instance declared by the parent class
     pub prototype: Option<Box<Class>>,
+    ///Fields declared by `class` itself
     pub fields: Vec<VMData>,
 }
 
+impl Class {
+    /// The part of this instance declared by `class`, following the prototype chain
+    pub fn part_mut(&mut self, class: usize) -> Option<&mut Class> {
+        let mut part = Some(self);
+        while let Some(p) = part {
+            if p.class == class {
+                return Some(p);
+            }
+            part = p.prototype.as_deref_mut();
+        }
+        None
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Vector {
     pub vec: Vec<VMData>,
     ///To know what's the type of the value in the Vec and ensure everything works correctly
     pub tag: TAG,
 }
+
+impl Vector {
+    pub fn new(tag: TAG) -> Self {
+        Self { vec: vec![], tag }
+    }
+
+    /// Whether `val` can be stored in this vector, any object fits in a vector of objects
+    pub fn accepts(&self, val: &VMData) -> bool {
+        val.tag == self.tag || (self.tag > 256 && val.is_object())
+    }
+}
+
+/// Key of a [`Map`], strings are compared by their contents & floats by their bits
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Unit,
+    I64(i64),
+    U64(u64),
+    Float(u64),
+    Bool(bool),
+    Char(char),
+    String(String),
+}
+
+impl std::fmt::Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Unit => write!(f, "()"),
+            MapKey::I64(i) => write!(f, "{}", i),
+            MapKey::U64(u) => write!(f, "{}", u),
+            MapKey::Float(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            MapKey::Bool(b) => write!(f, "{}", b),
+            MapKey::Char(c) => write!(f, "{:?}", c),
+            MapKey::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Entries are kept in insertion order, removing one moves the last entry in its place
+#[derive(Clone, Debug, Default)]
+pub struct Map {
+    ///(key, value given as key, value)
+    pub(crate) entries: Vec<(MapKey, VMData, VMData)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<VMData> {
+        self.index.get(key).map(|i| self.entries[*i].2)
+    }
+
+    /// Insert `value` under `key`, `data` being the value used as key.
+    /// Return the previous value if there was one
+    pub fn insert(&mut self, key: MapKey, data: VMData, value: VMData) -> Option<VMData> {
+        match self.index.get(&key) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[*i].2, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, data, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<VMData> {
+        let i = self.index.remove(key)?;
+        let (_, _, value) = self.entries.swap_remove(i);
+        if let Some((moved, _, _)) = self.entries.get(i) {
+            self.index.insert(moved.clone(), i);
+        }
+        Some(value)
+    }
+
+    /// The value used as key by the `i`th entry
+    pub fn key_at(&self, i: usize) -> Option<VMData> {
+        self.entries.get(i).map(|(_, data, _)| *data)
+    }
+}
+
+/// Pushed by `coro_status`, as an int
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Created or yielded, waiting for `coro_resume`
+    Suspended = 0,
+    Running = 1,
+    /// Waiting for a coroutine it resumed to yield
+    Normal = 2,
+    /// Returned or failed, it can't be resumed anymore
+    Dead = 3,
+}
+
+impl std::fmt::Display for CoroutineStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoroutineStatus::Suspended => write!(f, "suspended"),
+            CoroutineStatus::Running => write!(f, "running"),
+            CoroutineStatus::Normal => write!(f, "normal"),
+            CoroutineStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+/// A function running on its own stack, that can be suspended & resumed.
+///
+/// While it's suspended, it holds its own stack, calls & pc. Once resumed, they're swapped
+/// with the ones of the VM, so it holds the ones of its resumer until it yields back.
+#[derive(Clone, Debug)]
+pub struct Coroutine {
+    pub status: CoroutineStatus,
+    pub stack: Box<Stack>,
+    pub frames: Vec<CallFrame>,
+    pub handlers: Vec<Handler>,
+    pub pc: usize,
+}
+
+impl Coroutine {
+    /// A suspended coroutine starting at `entry`
+    pub fn new(entry: usize) -> Self {
+        Self {
+            status: CoroutineStatus::Suspended,
+            stack: Box::default(),
+            frames: vec![CallFrame {
+                entry,
+                ..Default::default()
+            }],
+            handlers: vec![],
+            pc: entry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(mem: &mut Memory, s: &str) -> VMData {
+        VMData::new_string(mem.put(s.to_owned().into()).unwrap())
+    }
+
+    fn object(mem: &mut Memory, obj: impl Into<Object>) -> VMData {
+        VMData::new_object(257, mem.put(obj.into()).unwrap())
+    }
+
+    fn is_string(mem: &Memory, data: VMData, s: &str) -> bool {
+        matches!(mem.object(data.as_object()), Some(Object::String(o)) if o == s)
+    }
+
+    #[test]
+    fn collect_frees_unreachable_objects() {
+        let mut mem = Memory::new(4);
+        let kept = string(&mut mem, "kept");
+        let lost = string(&mut mem, "lost");
+        string(&mut mem, "lost too");
+        assert_eq!(mem.collect([kept, VMData::new_i64(1)]), 2);
+        assert_eq!(mem.memory_pressure(), 1);
+        assert!(is_string(&mem, kept, "kept"));
+        assert!(mem.object(lost.as_object()).is_none());
+        // Nothing is left to free
+        assert_eq!(mem.collect([kept]), 0);
+    }
+
+    #[test]
+    fn freed_blocks_are_reused() {
+        let mut mem = Memory::new(4);
+        let kept = string(&mut mem, "kept");
+        for _ in 0..3 {
+            string(&mut mem, "lost");
+        }
+        mem.collect([kept]);
+        for _ in 0..3 {
+            string(&mut mem, "new");
+        }
+        assert_eq!(mem.raw().len(), 4);
+        assert!(is_string(&mem, kept, "kept"));
+    }
+
+    #[test]
+    fn collect_traces_through_objects() {
+        let mut mem = Memory::new(4);
+        let in_vector = string(&mut mem, "in vector");
+        let vector = object(
+            &mut mem,
+            Vector {
+                vec: vec![in_vector],
+                tag: VMData::TAG_STR,
+            },
+        );
+        let key = string(&mut mem, "key");
+        let value = string(&mut mem, "value");
+        let mut map = Map::new();
+        map.insert(MapKey::String("key".to_owned()), key, value);
+        let map = object(&mut mem, map);
+        let inherited = string(&mut mem, "inherited");
+        let instance = object(
+            &mut mem,
+            Class {
+                class: 1,
+                prototype: Some(Box::new(Class {
+                    class: 0,
+                    prototype: None,
+                    fields: vec![inherited],
+                })),
+                fields: vec![map],
+            },
+        );
+        let on_stack = string(&mut mem, "on stack");
+        let mut coroutine = Coroutine::new(0);
+        coroutine.stack.push(on_stack).unwrap();
+        let coroutine = object(&mut mem, coroutine);
+        let root = object(
+            &mut mem,
+            Structure {
+                fields: vec![vector, instance, coroutine],
+            },
+        );
+        // A cycle back to the root
+        let root_ptr = root.as_object();
+        if let Object::Structure(s) = mem.get_mut(root_ptr) {
+            s.fields.push(root);
+        }
+        let lost = string(&mut mem, "lost");
+        let live = mem.memory_pressure() - 1;
+
+        // A forged pointer to nothing is ignored
+        let forged = VMData::new_object(257, ObjectIndex::new(1000));
+        assert_eq!(mem.collect([root, forged]), 1);
+        assert_eq!(mem.memory_pressure(), live);
+        assert!(mem.object(lost.as_object()).is_none());
+        for (data, s) in [
+            (in_vector, "in vector"),
+            (key, "key"),
+            (value, "value"),
+            (inherited, "inherited"),
+            (on_stack, "on stack"),
+        ] {
+            assert!(is_string(&mem, data, s), "{} was freed", s);
+        }
+    }
+
+    #[test]
+    fn compact_relocates_every_pointer() {
+        let mut mem = Memory::new(4);
+        for _ in 0..8 {
+            string(&mut mem, "lost");
+        }
+        let s = string(&mut mem, "s");
+        let vector = object(
+            &mut mem,
+            Vector {
+                vec: vec![s],
+                tag: VMData::TAG_STR,
+            },
+        );
+        let mut map = Map::new();
+        map.insert(MapKey::String("s".to_owned()), s, vector);
+        let map = object(&mut mem, map);
+        let mut coroutine = Coroutine::new(0);
+        coroutine.stack.push(map).unwrap();
+        let coroutine = object(&mut mem, coroutine);
+        let mut roots = [
+            object(
+                &mut mem,
+                Structure {
+                    fields: vec![s, coroutine],
+                },
+            ),
+            VMData::new_object(257, ObjectIndex::new(1000)),
+            VMData::new_i64(7),
+        ];
+        let old = roots[0];
+
+        mem.collect(roots);
+        assert!(mem.should_shrink());
+        mem.shrink(roots.iter_mut());
+        // 5 objects are left at the front, with 10% of free space
+        assert_eq!(mem.raw().len(), 6);
+        assert_ne!(roots[0], old);
+        assert_eq!(roots[1].as_object(), ObjectIndex::new(1000));
+        assert_eq!(roots[2].as_i64(), 7);
+
+        let Object::Structure(root) = mem.get(roots[0].as_object()).clone() else {
+            panic!("the root isn't a structure anymore")
+        };
+        assert!(is_string(&mem, root.fields[0], "s"));
+        let Object::Coroutine(c) = mem.get(root.fields[1].as_object()).clone() else {
+            panic!("the coroutine was lost")
+        };
+        let Object::Map(m) = mem.get(c.stack.last().unwrap().as_object()).clone() else {
+            panic!("the map was lost")
+        };
+        assert!(is_string(&mem, m.key_at(0).unwrap(), "s"));
+        let v = m.get(&MapKey::String("s".to_owned())).unwrap();
+        assert!(is_string(&mem, mem.get(v.as_object()).vector().vec[0], "s"));
+
+        // The free list is rebuilt after the live objects
+        let new = string(&mut mem, "new");
+        assert_eq!(new.as_object(), ObjectIndex::new(5));
+        string(&mut mem, "grown");
+        assert!(mem.raw().len() > 6);
+        assert_eq!(mem.memory_pressure(), 7);
+    }
+
+    #[test]
+    fn dense_memory_isnt_compacted() {
+        let mut mem = Memory::new(4);
+        let mut roots: Vec<_> = (0..3).map(|_| string(&mut mem, "kept")).collect();
+        string(&mut mem, "lost");
+        let before = roots.clone();
+        mem.collect(roots.iter().copied());
+        assert!(!mem.should_shrink());
+        mem.shrink(roots.iter_mut());
+        assert_eq!(roots, before);
+        assert_eq!(mem.raw().len(), 4);
+    }
+
+    fn int_map(keys: &[i64]) -> Map {
+        let mut map = Map::new();
+        for k in keys {
+            map.insert(
+                MapKey::I64(*k),
+                VMData::new_i64(*k),
+                VMData::new_i64(k * 10),
+            );
+        }
+        map
+    }
+
+    /// Every entry, in order, checked against the index
+    fn entries(map: &Map) -> Vec<i64> {
+        (0..map.len())
+            .map(|i| {
+                let key = map.key_at(i).unwrap().as_i64();
+                assert_eq!(map.get(&MapKey::I64(key)).unwrap().as_i64(), key * 10);
+                key
+            })
+            .collect()
+    }
+
+    #[test]
+    fn map_keys_compare_strings_by_contents_and_floats_by_bits() {
+        let mut map = Map::new();
+        let unit = VMData::new_unit();
+        map.insert(MapKey::String("x".to_owned()), unit, VMData::new_i64(1));
+        let old = map.insert(MapKey::String("x".to_owned()), unit, VMData::new_i64(2));
+        assert_eq!(old.unwrap().as_i64(), 1);
+        assert_eq!(map.len(), 1);
+
+        let float = |f: f64| MapKey::Float(f.to_bits());
+        map.insert(float(0.0), unit, VMData::new_i64(3));
+        map.insert(float(f64::NAN), unit, VMData::new_i64(4));
+        // -0.0 == 0.0 but they're different keys, while NaN can be found again
+        assert!(map.get(&float(-0.0)).is_none());
+        assert_eq!(map.get(&float(0.0)).unwrap().as_i64(), 3);
+        assert_eq!(map.get(&float(f64::NAN)).unwrap().as_i64(), 4);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn map_remove_keeps_the_entries_indexed() {
+        let mut map = int_map(&[1, 2, 3, 4]);
+        // The last entry takes the place of the removed one
+        assert_eq!(map.remove(&MapKey::I64(2)).unwrap().as_i64(), 20);
+        assert_eq!(entries(&map), [1, 4, 3]);
+        assert!(map.remove(&MapKey::I64(2)).is_none());
+        // Removing the last entry moves nothing
+        assert_eq!(map.remove(&MapKey::I64(3)).unwrap().as_i64(), 30);
+        assert_eq!(entries(&map), [1, 4]);
+        map.insert(MapKey::I64(5), VMData::new_i64(5), VMData::new_i64(50));
+        assert_eq!(entries(&map), [1, 4, 5]);
+        for k in [1, 5, 4] {
+            map.remove(&MapKey::I64(k)).unwrap();
+        }
+        assert!(map.is_empty());
+        assert!(map.key_at(0).is_none());
+    }
+
+    #[test]
+    fn map_key_at_is_bounded() {
+        let map = int_map(&[7, 8]);
+        assert_eq!(map.key_at(1).unwrap().as_i64(), 8);
+        assert!(map.key_at(2).is_none());
+        assert!(map.key_at(usize::MAX).is_none());
+    }
+
+    #[test]
+    fn vectors_only_accept_their_tag() {
+        let mut mem = Memory::new(4);
+        let s = string(&mut mem, "s");
+        let structure = object(&mut mem, Structure { fields: vec![] });
+        let ints = Vector::new(VMData::TAG_I64);
+        assert!(ints.accepts(&VMData::new_i64(1)));
+        assert!(!ints.accepts(&VMData::new_u64(1)));
+        assert!(!ints.accepts(&VMData::new_f64(1.0)));
+        assert!(!ints.accepts(&s));
+        let strings = Vector::new(VMData::TAG_STR);
+        assert!(strings.accepts(&s));
+        assert!(!strings.accepts(&structure));
+        // Strings are objects too
+        let objects = Vector::new(257);
+        assert!(objects.accepts(&structure));
+        assert!(objects.accepts(&s));
+        assert!(!objects.accepts(&VMData::new_unit()));
+    }
+}
@@ -1,9 +1,9 @@
 use std::fmt::Display;
 
-use crate::memory::vm_data::VMData;
+use crate::{memory::vm_data::VMData, runtime::error::VMErrorKind};
 
 const STACK_SIZE: usize = 16 * 1024 / size_of::<VMData>();
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stack {
     values: [VMData; STACK_SIZE],
     pub top: usize,
@@ -23,31 +23,74 @@ impl Stack {
         }
     }
 
-    pub fn push(&mut self, val: VMData) {
+    pub fn push(&mut self, val: VMData) -> Result<(), VMErrorKind> {
         if self.top < STACK_SIZE {
             self.values[self.top] = val;
             self.top += 1;
+            Ok(())
         } else {
-            panic!("Stack full");
+            Err(VMErrorKind::StackOverflow)
         }
     }
 
-    pub fn pop(&mut self) -> Option<VMData> {
-        if self.top != 0 {
+    pub fn pop(&mut self) -> Result<VMData, VMErrorKind> {
+        // The first slot is never used, popping it would be an underflow
+        if self.top > 1 {
             self.top -= 1;
             let r = self.values[self.top];
-            Some(r)
+            Ok(r)
         } else {
-            None
+            Err(VMErrorKind::StackUnderflow)
         }
     }
 
+    /// Iterate over the values currently in the stack, from the bottom to the top
     #[inline(always)]
-    pub fn last(&self) -> Option<&VMData> {
-        if self.top != 0 {
-            Some(&self.values[self.top - 1])
+    pub fn iter(&self) -> impl Iterator<Item = &VMData> {
+        self.values[..self.top].iter()
+    }
+
+    #[inline(always)]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut VMData> {
+        self.values[..self.top].iter_mut()
+    }
+
+    /// Value at `index` from the bottom of the stack
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> Result<VMData, VMErrorKind> {
+        if index < self.top {
+            Ok(self.values[index])
+        } else {
+            Err(VMErrorKind::StackUnderflow)
+        }
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, index: usize, val: VMData) -> Result<(), VMErrorKind> {
+        if index < self.top {
+            self.values[index] = val;
+            Ok(())
+        } else {
+            Err(VMErrorKind::StackUnderflow)
+        }
+    }
+
+    /// The last `n` values, the top of the stack being the last one
+    #[inline(always)]
+    pub fn last_n(&self, n: usize) -> Result<&[VMData], VMErrorKind> {
+        if n < self.top {
+            Ok(&self.values[self.top - n..self.top])
+        } else {
+            Err(VMErrorKind::StackUnderflow)
+        }
+    }
+
+    #[inline(always)]
+    pub fn last(&self) -> Result<&VMData, VMErrorKind> {
+        if self.top > 1 {
+            Ok(&self.values[self.top - 1])
         } else {
-            None
+            Err(VMErrorKind::StackUnderflow)
         }
     }
 }
@@ -69,3 +112,41 @@ impl Display for Stack {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pop_empty_stack_underflows() {
+        let mut stack = Stack::new();
+        assert!(matches!(stack.pop(), Err(VMErrorKind::StackUnderflow)));
+        assert!(matches!(stack.last(), Err(VMErrorKind::StackUnderflow)));
+        assert!(matches!(stack.last_n(1), Err(VMErrorKind::StackUnderflow)));
+        assert_eq!(stack.top, 1);
+    }
+
+    #[test]
+    fn pop_gives_back_pushed_values() {
+        let mut stack = Stack::new();
+        stack.push(VMData::new_i64(1)).unwrap();
+        stack.push(VMData::new_i64(2)).unwrap();
+        assert_eq!(stack.last_n(2).unwrap().len(), 2);
+        assert_eq!(stack.pop().unwrap().as_i64(), 2);
+        assert_eq!(stack.pop().unwrap().as_i64(), 1);
+        assert!(matches!(stack.pop(), Err(VMErrorKind::StackUnderflow)));
+        assert_eq!(stack.top, 1);
+    }
+
+    #[test]
+    fn push_full_stack_overflows() {
+        let mut stack = Stack::new();
+        for i in 1..STACK_SIZE {
+            stack.push(VMData::new_i64(i as i64)).unwrap();
+        }
+        assert!(matches!(
+            stack.push(VMData::new_unit()),
+            Err(VMErrorKind::StackOverflow)
+        ));
+    }
+}
@@ -1,6 +1,7 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display};
 
 use super::object_map::ObjectIndex;
+use crate::runtime::error::VMErrorKind;
 
 pub type TAG = u64;
 
@@ -67,6 +68,87 @@ impl VMData {
     def_new_vmdata_func!(new_f64, as_f64, f64, TAG_FLOAT);
     def_new_vmdata_func!(new_bool, as_bool, bool, TAG_BOOL);
     def_new_vmdata_func!(new_char, as_char, char, TAG_CHAR);
+
+    pub fn tag_name(tag: TAG) -> &'static str {
+        match tag {
+            Self::TAG_BOOL => "bool",
+            Self::TAG_UNIT => "unit",
+            Self::TAG_FLOAT => "f64",
+            Self::TAG_I64 => "i64",
+            Self::TAG_U64 => "u64",
+            Self::TAG_CHAR => "char",
+            Self::TAG_STR => "str",
+            _ if tag > 256 => "obj",
+            _ => "res",
+        }
+    }
+
+    /// Raw payload of the value, its meaning depends on the tag
+    pub fn to_bits(self) -> u64 {
+        match self.tag {
+            Self::TAG_UNIT => 0,
+            Self::TAG_I64 => self.as_i64() as u64,
+            Self::TAG_U64 => self.as_u64(),
+            Self::TAG_FLOAT => self.as_f64().to_bits(),
+            Self::TAG_BOOL => self.as_bool() as u64,
+            Self::TAG_CHAR => self.as_char() as u64,
+            _ if self.is_object() => self.as_object().idx,
+            _ => 0,
+        }
+    }
+
+    /// Inverse of `to_bits()`, returns None if `bits` isn't a valid payload for `tag`
+    pub fn from_bits(tag: TAG, bits: u64) -> Option<Self> {
+        match tag {
+            Self::TAG_UNIT => Some(Self::new_unit()),
+            Self::TAG_I64 => Some(Self::new_i64(bits as i64)),
+            Self::TAG_U64 => Some(Self::new_u64(bits)),
+            Self::TAG_FLOAT => Some(Self::new_f64(f64::from_bits(bits))),
+            Self::TAG_BOOL if bits <= 1 => Some(Self::new_bool(bits == 1)),
+            Self::TAG_CHAR => char::from_u32(u32::try_from(bits).ok()?).map(Self::new_char),
+            Self::TAG_STR => Some(Self::new_string(ObjectIndex::new(bits))),
+            _ if tag > 256 => Some(Self::new_object(tag, ObjectIndex::new(bits))),
+            _ => None,
+        }
+    }
+
+    /// Same as `==` but returns an error instead of panicking on values that can't be compared
+    pub fn try_eq(&self, other: &Self) -> Result<bool, VMErrorKind> {
+        if self.tag != other.tag {
+            return Ok(false);
+        }
+        match self.tag {
+            Self::TAG_BOOL => Ok(self.as_bool() == other.as_bool()),
+            Self::TAG_FLOAT => Ok(self.as_f64() == other.as_f64()),
+            Self::TAG_I64 => Ok(self.as_i64() == other.as_i64()),
+            Self::TAG_U64 => Ok(self.as_u64() == other.as_u64()),
+            Self::TAG_CHAR => Ok(self.as_char() == other.as_char()),
+            Self::TAG_UNIT => Ok(true),
+            _ if self.is_object() => Ok(self.as_object() == other.as_object()),
+            _ => Err(VMErrorKind::IllegalComparison {
+                left: self.tag,
+                right: other.tag,
+            }),
+        }
+    }
+
+    /// Same as `partial_cmp` but returns an error instead of panicking on values that can't be ordered
+    pub fn try_partial_cmp(&self, other: &Self) -> Result<Option<Ordering>, VMErrorKind> {
+        let err = VMErrorKind::IllegalComparison {
+            left: self.tag,
+            right: other.tag,
+        };
+        if self.tag != other.tag {
+            return Err(err);
+        }
+        match self.tag {
+            Self::TAG_FLOAT => Ok(self.as_f64().partial_cmp(&other.as_f64())),
+            Self::TAG_U64 => Ok(self.as_u64().partial_cmp(&other.as_u64())),
+            Self::TAG_I64 => Ok(self.as_i64().partial_cmp(&other.as_i64())),
+            Self::TAG_CHAR => Ok(self.as_char().partial_cmp(&other.as_char())),
+            _ => Err(err),
+        }
+    }
 }
 
 impl PartialEq for VMData {
@@ -81,7 +163,7 @@ impl PartialEq for VMData {
             Self::TAG_U64 => self.as_u64() == other.as_u64(),
             Self::TAG_CHAR => self.as_char() == other.as_char(),
             Self::TAG_UNIT => true,
-            _ if self.tag > 256 => self.as_object() == other.as_object(),
+            _ if self.is_object() => self.as_object() == other.as_object(),
             _ => panic!("Illegal comparison"),
         }
     }
@@ -128,16 +210,7 @@ impl std::fmt::Debug for VMData {
             f,
             "VMData {{ tag: {}({}), data: {}}}",
             self.tag,
-            match self.tag {
-                Self::TAG_BOOL => "bool",
-                Self::TAG_UNIT => "unit",
-                Self::TAG_FLOAT => "f64",
-                Self::TAG_I64 => "i64",
-                Self::TAG_U64 => "u64",
-                Self::TAG_CHAR => "char",
-                _ if self.is_object() => "obj",
-                _ => "res",
-            },
+            Self::tag_name(self.tag),
             match self.tag {
                 Self::TAG_UNIT => "()".to_string(),
                 Self::TAG_I64 => self.as_i64().to_string(),
@@ -153,7 +226,7 @@ impl std::fmt::Debug for VMData {
 }
 
 macro_rules! enum_variant_function {
-    ($getter: ident, $is: ident, $variant: ident, $ty: ty) => {
+    ($getter: ident, $is: ident, $try: ident, $variant: ident, $ty: ty) => {
         #[inline(always)]
         #[must_use]
         pub fn $getter(self) -> $ty {
@@ -165,16 +238,50 @@ macro_rules! enum_variant_function {
         pub fn $is(self) -> bool {
             self.tag == Self::$variant
         }
+
+        /// Checked version of the getter, fails if the tag doesn't match
+        #[inline(always)]
+        pub fn $try(self) -> Result<$ty, VMErrorKind> {
+            if self.$is() {
+                Ok(self.$getter())
+            } else {
+                Err(VMErrorKind::TypeMismatch {
+                    expected: Self::$variant,
+                    found: self.tag,
+                })
+            }
+        }
     };
 }
 
 impl VMData {
-    enum_variant_function!(as_i64, is_i64, TAG_I64, i64);
-    enum_variant_function!(as_f64, is_f64, TAG_FLOAT, f64);
-    enum_variant_function!(as_u64, is_u64, TAG_U64, u64);
-    enum_variant_function!(as_bool, is_bool, TAG_BOOL, bool);
-    enum_variant_function!(as_char, is_char, TAG_CHAR, char);
-    enum_variant_function!(as_unit, is_unit, TAG_UNIT, ());
+    enum_variant_function!(as_i64, is_i64, try_i64, TAG_I64, i64);
+    enum_variant_function!(as_f64, is_f64, try_f64, TAG_FLOAT, f64);
+    enum_variant_function!(as_u64, is_u64, try_u64, TAG_U64, u64);
+    enum_variant_function!(as_bool, is_bool, try_bool, TAG_BOOL, bool);
+    enum_variant_function!(as_char, is_char, try_char, TAG_CHAR, char);
+
+    #[inline(always)]
+    pub fn as_unit(self) {}
+
+    #[inline(always)]
+    #[must_use]
+    pub fn is_unit(self) -> bool {
+        self.tag == Self::TAG_UNIT
+    }
+
+    /// Checked version of [`VMData::as_unit`], fails if the value isn't unit
+    #[inline(always)]
+    pub fn try_unit(self) -> Result<(), VMErrorKind> {
+        if self.is_unit() {
+            Ok(())
+        } else {
+            Err(VMErrorKind::TypeMismatch {
+                expected: Self::TAG_UNIT,
+                found: self.tag,
+            })
+        }
+    }
 
     #[inline(always)]
     #[must_use]
@@ -191,4 +298,23 @@ impl VMData {
 
         unsafe { self.data.as_object }
     }
+
+    /// Same object type, but pointing somewhere else
+    #[inline(always)]
+    pub(crate) fn with_object(self, val: ObjectIndex) -> Self {
+        debug_assert!(self.is_object());
+        Self::new(self.tag, RawVMData { as_object: val })
+    }
+
+    #[inline(always)]
+    pub fn try_object(self) -> Result<ObjectIndex, VMErrorKind> {
+        if self.is_object() {
+            Ok(unsafe { self.data.as_object })
+        } else {
+            Err(VMErrorKind::TypeMismatch {
+                expected: 257,
+                found: self.tag,
+            })
+        }
+    }
 }
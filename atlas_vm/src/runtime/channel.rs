@@ -0,0 +1,214 @@
+//! Messages sent between VMs, each one having its own memory.
+//!
+//! A message is a deep copy of a value & every object it references, with its pointers
+//! rewritten to positions in the message. Objects referenced more than once, cycles included,
+//! are only copied once, so the received value has the same shape as the sent one.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    memory::{
+        object_map::{Memory, Object, ObjectIndex},
+        vm_data::VMData,
+    },
+    runtime::error::VMErrorKind,
+};
+
+/// A value copied out of the memory of a VM
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// Points into `objects` if it's an object
+    value: VMData,
+    objects: Vec<Object>,
+}
+
+impl Message {
+    /// Copy `value` & everything it references out of `memory`.
+    /// Coroutines & class instances can't be copied since they only make sense in their VM.
+    pub fn new(memory: &Memory, value: VMData) -> Result<Self, VMErrorKind> {
+        // Position in `objects` of every object copied so far
+        let mut copied: HashMap<ObjectIndex, ObjectIndex> = HashMap::new();
+        let mut objects = vec![];
+        let mut gray = vec![];
+        if value.is_object() {
+            gray.push(value.as_object());
+        }
+        while let Some(ptr) = gray.pop() {
+            if copied.contains_key(&ptr) {
+                continue;
+            }
+            let object = memory.try_get(ptr)?;
+            if let Object::Coroutine(_) | Object::Class(_) = object {
+                return Err(VMErrorKind::NotSendable(ptr));
+            }
+            copied.insert(ptr, ObjectIndex::new(objects.len() as u64));
+            objects.push(object.clone());
+            object.trace(&mut gray);
+        }
+        let forward = |ptr: ObjectIndex| copied.get(&ptr).copied();
+        objects.iter_mut().for_each(|o| o.relocate(&forward));
+        let value = forward_value(value, &forward).unwrap_or(value);
+        Ok(Self { value, objects })
+    }
+    /// Number of objects copied along with the value
+    pub fn objects(&self) -> usize {
+        self.objects.len()
+    }
+    /// Allocate the objects of the message in `memory`, giving back the copied value.
+    /// A collection isn't triggered, so the value should be made reachable before the next one.
+    pub fn paste(self, memory: &mut Memory) -> Result<VMData, VMErrorKind> {
+        let mut pasted = Vec::with_capacity(self.objects.len());
+        for object in self.objects {
+            pasted.push(memory.put(object).map_err(|_| VMErrorKind::OutOfMemory)?);
+        }
+        let forward = |ptr: ObjectIndex| pasted.get(ptr.idx as usize).copied();
+        for ptr in &pasted {
+            memory.get_mut(*ptr).relocate(&forward);
+        }
+        Ok(forward_value(self.value, &forward).unwrap_or(self.value))
+    }
+}
+
+/// `value` pointing to its new location, if it's a moved object
+fn forward_value(
+    value: VMData,
+    forward: &impl Fn(ObjectIndex) -> Option<ObjectIndex>,
+) -> Option<VMData> {
+    if value.is_object() {
+        forward(value.as_object()).map(|ptr| value.with_object(ptr))
+    } else {
+        None
+    }
+}
+
+/// A queue of messages shared by every clone of it, so each VM given a clone can use it.
+/// It's never full, sending doesn't wait.
+#[derive(Debug, Clone, Default)]
+pub struct Channel {
+    queue: Arc<Mutex<VecDeque<Message>>>,
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn send(&self, message: Message) {
+        self.queue.lock().unwrap().push_back(message);
+    }
+    /// The oldest message, if there's one
+    pub fn try_recv(&self) -> Option<Message> {
+        self.queue.lock().unwrap().pop_front()
+    }
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::object_map::{Coroutine, Structure, Vector};
+
+    fn put(memory: &mut Memory, object: impl Into<Object>) -> VMData {
+        VMData::new_object(257, memory.put(object.into()).unwrap())
+    }
+
+    fn structure(memory: &Memory, data: VMData) -> &Structure {
+        memory.get(data.as_object()).structure()
+    }
+
+    #[test]
+    fn message_keeps_the_shape_of_the_value() {
+        let mut memory = Memory::new(4);
+        let s = VMData::new_string(memory.put("shared".to_owned().into()).unwrap());
+        let vector = put(
+            &mut memory,
+            Vector {
+                vec: vec![s],
+                tag: VMData::TAG_STR,
+            },
+        );
+        let root = put(
+            &mut memory,
+            Structure {
+                fields: vec![s, vector],
+            },
+        );
+        // A cycle back to the root
+        memory
+            .get_mut(root.as_object())
+            .structure_mut()
+            .fields
+            .push(root);
+        let message = Message::new(&memory, root).unwrap();
+        assert_eq!(message.objects(), 3);
+
+        let mut other = Memory::new(4);
+        for _ in 0..5 {
+            other.put("taken".to_owned().into()).unwrap();
+        }
+        let pasted = message.paste(&mut other).unwrap();
+        assert_eq!(other.memory_pressure(), 8);
+        let fields = structure(&other, pasted).fields.clone();
+        assert_eq!(fields[2].as_object(), pasted.as_object());
+        assert_eq!(other.get(fields[0].as_object()).string(), "shared");
+        let in_vector = other.get(fields[1].as_object()).vector().vec[0];
+        // Still the same string, not a copy of it
+        assert_eq!(in_vector.as_object(), fields[0].as_object());
+    }
+
+    #[test]
+    fn scalars_are_sent_as_is() {
+        let memory = Memory::new(1);
+        let message = Message::new(&memory, VMData::new_i64(42)).unwrap();
+        assert_eq!(message.objects(), 0);
+        let mut other = Memory::new(1);
+        assert_eq!(message.paste(&mut other).unwrap(), VMData::new_i64(42));
+        assert_eq!(other.memory_pressure(), 0);
+    }
+
+    #[test]
+    fn objects_bound_to_their_vm_arent_sendable() {
+        let mut memory = Memory::new(4);
+        let coroutine = put(&mut memory, Coroutine::new(0));
+        let root = put(
+            &mut memory,
+            Structure {
+                fields: vec![coroutine],
+            },
+        );
+        assert!(matches!(
+            Message::new(&memory, root),
+            Err(VMErrorKind::NotSendable(ptr)) if ptr == coroutine.as_object()
+        ));
+        let forged = VMData::new_object(257, ObjectIndex::new(100));
+        assert!(matches!(
+            Message::new(&memory, forged),
+            Err(VMErrorKind::InvalidObject(_))
+        ));
+    }
+
+    #[test]
+    fn channel_is_first_in_first_out() {
+        let memory = Memory::new(1);
+        let channel = Channel::new();
+        let other_end = channel.clone();
+        for i in 0..3 {
+            channel.send(Message::new(&memory, VMData::new_i64(i)).unwrap());
+        }
+        assert_eq!(other_end.len(), 3);
+        let mut other = Memory::new(1);
+        for i in 0..3 {
+            let message = other_end.try_recv().unwrap();
+            assert_eq!(message.paste(&mut other).unwrap().as_i64(), i);
+        }
+        assert!(channel.is_empty());
+        assert!(channel.try_recv().is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Run a program instruction by instruction, stopping on breakpoints so the VM can be
+//! inspected in between.
+//!
+//! The instructions are run with every check, as by [`VM::execute_instruction`]. Unlike
+//! [`VM::execute`], the VM isn't cleaned when the program ends or fails, so its last state
+//! can still be looked at until [`Debugger::reset`].
+
+use std::collections::BTreeSet;
+
+use crate::{
+    instruction::Instruction,
+    runtime::{error::VMError, VM},
+};
+
+/// Why the debugger gave back control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested step is done
+    Step,
+    /// The next instruction has a breakpoint
+    Breakpoint(usize),
+    /// The program reached `hlt` or its end
+    Finished,
+}
+
+pub struct Debugger<'a> {
+    vm: &'a mut VM,
+    ins: &'a [Instruction],
+    breakpoints: BTreeSet<usize>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Debug `ins`, which should have been linked by `vm` so breakpoints can use its labels
+    pub fn new(vm: &'a mut VM, ins: &'a [Instruction]) -> Self {
+        Self {
+            vm,
+            ins,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+    pub fn vm(&self) -> &VM {
+        self.vm
+    }
+    pub fn vm_mut(&mut self) -> &mut VM {
+        self.vm
+    }
+    pub fn instructions(&self) -> &'a [Instruction] {
+        self.ins
+    }
+    /// The next instruction to run, `None` once the program is over
+    pub fn current(&self) -> Option<&'a Instruction> {
+        match self.ins.get(self.vm.pc) {
+            Some(Instruction::HLT) | None => None,
+            ins => ins,
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.current().is_none()
+    }
+    /// Stop before running the instruction at `pc`, false if there's no such instruction
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        if pc >= self.ins.len() {
+            return false;
+        }
+        self.breakpoints.insert(pc);
+        true
+    }
+    /// Stop before running the first instruction of `label`, giving back its position
+    pub fn add_breakpoint_at(&mut self, label: &str) -> Option<usize> {
+        let pc = self.vm.label_address(label)?;
+        self.add_breakpoint(pc).then_some(pc)
+    }
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+    /// Run the next instruction
+    pub fn step(&mut self) -> Result<Stop, VMError> {
+        self.run_until(|_| true)
+    }
+    /// Same as [`Debugger::step`], but a call is run until it returns
+    pub fn step_over(&mut self) -> Result<Stop, VMError> {
+        match self.current() {
+            Some(Instruction::Call(_) | Instruction::CallMethod(_)) => {
+                let depth = self.vm.frames.len();
+                self.run_until(|vm| vm.frames.len() <= depth)
+            }
+            _ => self.step(),
+        }
+    }
+    /// Run until the current call returns, or until the end from the entry point
+    pub fn step_out(&mut self) -> Result<Stop, VMError> {
+        let depth = self.vm.frames.len();
+        self.run_until(|vm| vm.frames.len() < depth)
+    }
+    /// Run until a breakpoint or the end of the program
+    pub fn resume(&mut self) -> Result<Stop, VMError> {
+        self.run_until(|_| false)
+    }
+    /// Clean the VM so the program can be debugged again from the start
+    pub fn reset(&mut self) {
+        self.vm.clean();
+    }
+    /// Run at least one instruction, then stop as soon as `done` is true or on a breakpoint
+    fn run_until(&mut self, done: impl Fn(&VM) -> bool) -> Result<Stop, VMError> {
+        loop {
+            let Some(ins) = self.current() else {
+                return Ok(Stop::Finished);
+            };
+            self.vm.execute_instruction(ins)?;
+            if self.is_finished() {
+                return Ok(Stop::Finished);
+            }
+            if done(self.vm) {
+                return Ok(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.vm.pc) {
+                return Ok(Stop::Breakpoint(self.vm.pc));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{compiler::assemble, Address};
+
+    const PROGRAM: &str = "
+.section
+    @string greeting \"hi\"
+.code
+main:
+    load_const #greeting
+    call &twice
+    push_i $2
+    hlt
+twice:
+    call &leaf
+    call &leaf
+    ret
+leaf:
+    push_i $3
+    pop
+    ret
+";
+
+    fn link(vm: &mut VM) -> Vec<Instruction> {
+        vm.link(&assemble(PROGRAM)).unwrap()
+    }
+
+    #[test]
+    fn steps_into_over_and_out_of_calls() {
+        let mut vm = VM::new(16, vec![]);
+        let ins = link(&mut vm);
+        let mut debugger = Debugger::new(&mut vm, &ins);
+        assert_eq!(debugger.step().unwrap(), Stop::Step);
+        assert_eq!(debugger.step().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (4, 2));
+        assert_eq!(debugger.step().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (7, 3));
+        let trace: Vec<_> = debugger
+            .vm()
+            .backtrace()
+            .into_iter()
+            .map(|f| (f.pc, f.function.unwrap()))
+            .collect();
+        assert_eq!(
+            trace,
+            [
+                (7, "leaf".to_owned()),
+                (4, "twice".to_owned()),
+                (1, "main".to_owned())
+            ]
+        );
+        assert_eq!(debugger.step_out().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (5, 2));
+        assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (6, 2));
+        assert_eq!(debugger.step_out().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (2, 1));
+        // Out of the entry point is the end of the program
+        assert_eq!(debugger.step_out().unwrap(), Stop::Finished);
+        assert!(debugger.is_finished());
+        assert_eq!(debugger.vm().stack.last().unwrap().as_i64(), 2);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        let mut vm = VM::new(16, vec![]);
+        let ins = link(&mut vm);
+        let mut debugger = Debugger::new(&mut vm, &ins);
+        debugger.step().unwrap();
+        assert_eq!(
+            debugger.current(),
+            Some(&Instruction::Call(Address::Val(4)))
+        );
+        assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (2, 1));
+        // Unless it has a breakpoint
+        debugger.reset();
+        assert!(debugger.add_breakpoint(8));
+        debugger.step().unwrap();
+        assert_eq!(debugger.step_over().unwrap(), Stop::Breakpoint(8));
+        assert_eq!(debugger.vm().frames().len(), 3);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_their_instruction() {
+        let mut vm = VM::new(16, vec![]);
+        let ins = link(&mut vm);
+        let mut debugger = Debugger::new(&mut vm, &ins);
+        assert_eq!(debugger.add_breakpoint_at("leaf"), Some(7));
+        assert_eq!(debugger.add_breakpoint_at("nowhere"), None);
+        assert!(!debugger.add_breakpoint(ins.len()));
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [7]);
+        // Once per call
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(7));
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (7, 3));
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(7));
+        assert!(debugger.remove_breakpoint(7));
+        assert!(!debugger.remove_breakpoint(7));
+        assert_eq!(debugger.resume().unwrap(), Stop::Finished);
+        assert_eq!(debugger.resume().unwrap(), Stop::Finished);
+        // The last state is kept until the reset
+        assert_eq!(debugger.vm().pc(), 3);
+        debugger.reset();
+        assert_eq!((debugger.vm().pc(), debugger.vm().frames().len()), (0, 1));
+        assert_eq!(debugger.current(), Some(&ins[0]));
+    }
+
+    #[test]
+    fn objects_can_be_inspected() {
+        let mut vm = VM::new(16, vec![]);
+        let ins = link(&mut vm);
+        let mut debugger = Debugger::new(&mut vm, &ins);
+        debugger.step().unwrap();
+        let ptr = debugger.vm().stack.last().unwrap().try_object().unwrap();
+        let object = debugger.vm().object_map.object(ptr).unwrap();
+        assert_eq!(object.to_string(), "String: hi");
+        assert_eq!(debugger.vm().constants()[0].try_object().unwrap(), ptr);
+    }
+}
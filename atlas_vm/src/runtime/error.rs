@@ -0,0 +1,275 @@
+use std::fmt::Display;
+
+use internment::Intern;
+
+use crate::{
+    instruction::{compiler::parser::Location, Instruction},
+    memory::{
+        object_map::{CoroutineStatus, MapKey, ObjectIndex},
+        vm_data::{VMData, TAG},
+    },
+};
+
+/// What went wrong while executing an instruction.
+///
+/// It's kept separate from [`VMError`] so every part of the VM (stack, memory, data...) can
+/// report a failure without knowing which instruction triggered it.
+#[derive(Debug, Clone)]
+pub enum VMErrorKind {
+    StackUnderflow,
+    StackOverflow,
+    CallStackUnderflow,
+    DivisionByZero,
+    /// The result of an integer operation doesn't fit in its type, see [`crate::instruction::Overflow`]
+    IntegerOverflow,
+    /// `shl`, `shr` & `sar` by a negative int
+    NegativeShift(i64),
+    TypeMismatch {
+        expected: TAG,
+        found: TAG,
+    },
+    InvalidCast {
+        to: TAG,
+        found: TAG,
+    },
+    IllegalComparison {
+        left: TAG,
+        right: TAG,
+    },
+    InvalidConstant(usize),
+    InvalidGlobal(usize),
+    UnknownGlobal(Intern<String>),
+    UnknownExternCall(usize),
+    /// The program calls an extern the host didn't register
+    MissingExtern(Intern<String>),
+    /// The extern returned an error
+    ExternFailed {
+        name: Intern<String>,
+        source: Box<VMErrorKind>,
+    },
+    ExternStackMismatch {
+        name: Intern<String>,
+        args: usize,
+        popped: isize,
+    },
+    UnresolvedAddress(Intern<String>),
+    InvalidClass(usize),
+    /// The object doesn't have the fields declared by `class`
+    NotAnInstance {
+        ptr: ObjectIndex,
+        class: Intern<String>,
+    },
+    UnknownMethod {
+        class: Intern<String>,
+        method: Intern<String>,
+    },
+    ArgOutOfBounds(usize),
+    LocalOutOfBounds(usize),
+    FieldOutOfBounds {
+        ptr: ObjectIndex,
+        field: usize,
+    },
+    StringIndexOutOfBounds {
+        ptr: ObjectIndex,
+        index: u64,
+    },
+    VectorIndexOutOfBounds {
+        ptr: ObjectIndex,
+        index: u64,
+    },
+    MapIndexOutOfBounds {
+        ptr: ObjectIndex,
+        index: u64,
+    },
+    /// Only unit, numbers, chars, bools & strings can be used as keys
+    InvalidMapKey(TAG),
+    KeyNotFound {
+        ptr: ObjectIndex,
+        key: MapKey,
+    },
+    InvalidObject(ObjectIndex),
+    OutOfMemory,
+    InvalidInput(String),
+    /// A string that can't be parsed into a number
+    InvalidNumber(String),
+    /// The host context isn't set or isn't of the requested type
+    MissingHost(&'static str),
+    /// Error raised by the host, mainly from an extern
+    Custom(String),
+    /// A value thrown outside of any `try_begin`. An extern can also return it to throw
+    /// the value to the program instead of failing.
+    Exception(VMData),
+    /// Only a suspended coroutine can be resumed
+    CoroutineNotSuspended {
+        ptr: ObjectIndex,
+        status: CoroutineStatus,
+    },
+    YieldOutsideCoroutine,
+    /// A [`CoroutineId`](crate::runtime::CoroutineId) that was released or given by another VM
+    InvalidCoroutine(usize),
+    InvalidChannel(usize),
+    /// `recv` on a channel without any message. While running, the VM is blocked instead of failing
+    EmptyChannel(usize),
+    /// Coroutines & class instances can't be copied into a message
+    NotSendable(ObjectIndex),
+}
+
+impl Display for VMErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VMErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            VMErrorKind::StackOverflow => write!(f, "stack overflow"),
+            VMErrorKind::CallStackUnderflow => write!(f, "call stack underflow"),
+            VMErrorKind::DivisionByZero => write!(f, "division by zero"),
+            VMErrorKind::IntegerOverflow => write!(f, "integer overflow"),
+            VMErrorKind::NegativeShift(n) => write!(f, "can't shift by a negative amount ({})", n),
+            VMErrorKind::TypeMismatch { expected, found } => write!(
+                f,
+                "type mismatch: expected {}, found {}",
+                VMData::tag_name(*expected),
+                VMData::tag_name(*found)
+            ),
+            VMErrorKind::InvalidCast { to, found } => write!(
+                f,
+                "can't cast {} to {}",
+                VMData::tag_name(*found),
+                VMData::tag_name(*to)
+            ),
+            VMErrorKind::IllegalComparison { left, right } => write!(
+                f,
+                "illegal comparison between {} and {}",
+                VMData::tag_name(*left),
+                VMData::tag_name(*right)
+            ),
+            VMErrorKind::InvalidConstant(u) => write!(f, "constant #{} doesn't exist", u),
+            VMErrorKind::InvalidGlobal(u) => write!(f, "global #{} doesn't exist", u),
+            VMErrorKind::UnknownGlobal(name) => write!(f, "global #{} isn't declared", name),
+            VMErrorKind::UnknownExternCall(u) => write!(f, "extern call ${} doesn't exist", u),
+            VMErrorKind::MissingExtern(name) => {
+                write!(f, "extern @{} isn't provided by the host", name)
+            }
+            VMErrorKind::ExternFailed { name, source } => {
+                write!(f, "extern @{} failed: {}", name, source)
+            }
+            VMErrorKind::ExternStackMismatch { name, args, popped } => write!(
+                f,
+                "extern @{} takes {} argument(s) but popped {} value(s)",
+                name, args, popped
+            ),
+            VMErrorKind::UnresolvedAddress(label) => {
+                write!(f, "address &{} has never been resolved", label)
+            }
+            VMErrorKind::InvalidClass(u) => write!(f, "class #{} doesn't exist", u),
+            VMErrorKind::NotAnInstance { ptr, class } => {
+                write!(f, "{} isn't an instance of {}", ptr, class)
+            }
+            VMErrorKind::UnknownMethod { class, method } => {
+                write!(f, "class {} has no method {}", class, method)
+            }
+            VMErrorKind::ArgOutOfBounds(u) => {
+                write!(f, "argument ${} isn't declared by the function", u)
+            }
+            VMErrorKind::LocalOutOfBounds(u) => {
+                write!(f, "local ${} isn't declared by the function", u)
+            }
+            VMErrorKind::FieldOutOfBounds { ptr, field } => {
+                write!(f, "field ${} is out of bound for {}", field, ptr)
+            }
+            VMErrorKind::StringIndexOutOfBounds { ptr, index } => {
+                write!(f, "index out of bound for string: {}[{}]", ptr, index)
+            }
+            VMErrorKind::VectorIndexOutOfBounds { ptr, index } => {
+                write!(f, "index out of bound for vector: {}[{}]", ptr, index)
+            }
+            VMErrorKind::MapIndexOutOfBounds { ptr, index } => {
+                write!(f, "index out of bound for map: {}[{}]", ptr, index)
+            }
+            VMErrorKind::InvalidMapKey(tag) => {
+                write!(f, "values with tag {} can't be used as map keys", tag)
+            }
+            VMErrorKind::KeyNotFound { ptr, key } => {
+                write!(f, "key {} not found in map {}", key, ptr)
+            }
+            VMErrorKind::InvalidObject(ptr) => {
+                write!(f, "{} doesn't point to a valid object", ptr)
+            }
+            VMErrorKind::OutOfMemory => write!(f, "out of memory"),
+            VMErrorKind::InvalidInput(s) => write!(f, "invalid input: {}", s),
+            VMErrorKind::InvalidNumber(s) => write!(f, "{:?} isn't a valid number", s),
+            VMErrorKind::MissingHost(t) => write!(f, "no host context of type {}", t),
+            VMErrorKind::Custom(s) => write!(f, "{}", s),
+            VMErrorKind::Exception(val) => write!(f, "uncaught exception: {}", val),
+            VMErrorKind::CoroutineNotSuspended { ptr, status } => {
+                write!(f, "coroutine {} can't be resumed, it's {}", ptr, status)
+            }
+            VMErrorKind::YieldOutsideCoroutine => write!(f, "yield outside of a coroutine"),
+            VMErrorKind::InvalidCoroutine(u) => write!(f, "coroutine id {} isn't held", u),
+            VMErrorKind::InvalidChannel(u) => write!(f, "channel ${} doesn't exist", u),
+            VMErrorKind::EmptyChannel(u) => write!(f, "channel ${} has no message", u),
+            VMErrorKind::NotSendable(ptr) => write!(f, "{} can't be sent to another VM", ptr),
+        }
+    }
+}
+
+/// A runtime error along with where it happened, so the embedder can recover and report it.
+#[derive(Debug, Clone)]
+pub struct VMError {
+    pub kind: VMErrorKind,
+    /// Program counter of the faulting instruction
+    pub pc: usize,
+    pub ins: Instruction,
+    /// Calls leading to the error, the innermost first.
+    /// It's empty if the error didn't happen while running
+    pub backtrace: Vec<TraceFrame>,
+}
+
+impl VMError {
+    pub fn new(kind: VMErrorKind, pc: usize, ins: Instruction) -> Self {
+        Self {
+            kind,
+            pc,
+            ins,
+            backtrace: vec![],
+        }
+    }
+    pub fn with_backtrace(mut self, backtrace: Vec<TraceFrame>) -> Self {
+        self.backtrace = backtrace;
+        self
+    }
+}
+
+impl Display for VMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at [{}] {:?}", self.kind, self.pc, self.ins)?;
+        for frame in &self.backtrace {
+            write!(f, "\n    {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// A function in a backtrace, with the instruction it was running
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// Position of the instruction running in this function, the call for the callers
+    pub pc: usize,
+    /// Label of the function, if the program was linked with its labels
+    pub function: Option<String>,
+    /// Where the instruction comes from, if the program has debug info
+    pub location: Option<Location>,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "at {} [{}]", name, self.pc)?,
+            None => write!(f, "at [{}]", self.pc)?,
+        }
+        match &self.location {
+            Some(location) => write!(f, " ({})", location),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for VMError {}
@@ -1,42 +1,209 @@
+pub mod channel;
+pub mod debugger;
+pub mod error;
+pub mod observer;
+pub mod profiler;
+pub mod verifier;
 pub mod vm_state;
+pub mod workers;
 
-use std::collections::HashMap;
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+};
 
+use channel::{Channel, Message};
+use error::{TraceFrame, VMError, VMErrorKind};
 use internment::Intern;
+use observer::Observer;
+use verifier::{Diagnostic, Verified};
 use vm_state::VMState;
 
 use crate::{
-    instruction::Instruction,
+    instruction::{
+        compiler::parser::{DebugInfo, Program},
+        Instruction, Overflow,
+    },
     memory::{
-        object_map::{Memory, ObjectIndex, Structure},
+        object_map::{
+            Class, Coroutine, CoroutineStatus, Map, MapKey, Memory, Object, ObjectIndex, Structure,
+            Vector,
+        },
         stack::Stack,
-        vm_data::VMData,
+        vm_data::{VMData, TAG},
     },
 };
 
-pub type CallBack = fn(vm_state::VMState) -> Result<VMData, ()>;
+/// Number of instructions run between two checks of the interrupt flag
+const INTERRUPT_PERIOD: u32 = 1024;
+/// pc of the host while a coroutine has its turn in [`VM::run_scheduler`]. It's out of any
+/// program, so the run loop ends as soon as the coroutine gives back control.
+const HOST_PC: usize = usize::MAX;
+
+pub type CallBack = Box<dyn FnMut(vm_state::VMState) -> Result<VMData, VMErrorKind> + Send>;
+
+/// A host function callable from the assembly with `extern_call @name`
+pub struct ExternFn {
+    pub name: Intern<String>,
+    /// Expected tag of each argument, the last one being on top of the stack
+    pub args: Vec<TAG>,
+    pub call: CallBack,
+}
+
+impl ExternFn {
+    /// Accept an argument of any type
+    pub const ANY: TAG = TAG::MAX;
+}
+
+impl Debug for ExternFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternFn")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A function call, pushed by `Call` & filled in by `Enter` if the function declares a frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallFrame {
+    /// Where to continue after `Ret`
+    pub ret: usize,
+    /// Address of the called function
+    pub entry: usize,
+    /// Position in the stack of the first argument
+    pub bp: usize,
+    pub args: usize,
+    pub locals: usize,
+    /// Whether `Enter` set up this frame. If so, `Ret` drops the arguments & locals
+    /// and keeps only the value on top of the stack
+    pub framed: bool,
+}
+
+/// Why [`VM::execute`] gave back control without an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    /// The program reached `hlt` or its end
+    Halted,
+    /// There's no fuel left for the next instruction
+    OutOfFuel,
+    /// The interrupt flag was raised
+    Interrupted,
+    /// [`VM::run_for`] ran all the instructions it was asked to
+    Paused,
+    /// `recv` waits for a message, it's tried again once the execution resumes
+    Blocked,
+}
+
+/// A `try_begin` waiting for a thrown value
+#[derive(Debug, Clone, Copy)]
+pub struct Handler {
+    /// Where to jump when a value is thrown
+    pub address: usize,
+    /// Number of call frames when the region started, the deeper ones are unwound
+    pub frames: usize,
+    /// Height of the stack when the region started
+    pub top: usize,
+}
+
+/// Layout & methods shared by every instance of a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDescriptor {
+    pub name: Intern<String>,
+    pub parent: Option<usize>,
+    /// Number of fields declared by the class itself
+    pub fields: usize,
+    /// Address of every method callable on an instance, the inherited ones included
+    pub vtable: HashMap<Intern<String>, usize>,
+}
+
+/// A coroutine held by the host, see [`VM::spawn`] & [`VM::hold`]. Unlike its [`ObjectIndex`],
+/// it stays valid when the collector moves the coroutine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CoroutineId(usize);
 
 #[derive(Debug)]
 pub struct VM {
-    pub stack: Stack,
+    /// Boxed so switching to a coroutine only swaps pointers
+    pub stack: Box<Stack>,
     pub object_map: Memory,
-    pub extern_fn: Vec<CallBack>,
+    extern_fn: Vec<ExternFn>,
     constants: Vec<VMData>,
-    call_stack: Vec<usize>,
+    /// Program run by [`VM::run`], [`VM::run_for`] & [`VM::resume`]
+    loaded: Vec<Instruction>,
+    /// Mutable values living across calls to `execute`
+    globals: Vec<VMData>,
+    /// Index of each global in `globals` by name
+    global_names: HashMap<Intern<String>, usize>,
+    classes: Vec<ClassDescriptor>,
+    /// Index of each class in `classes` by name
+    class_names: HashMap<Intern<String>, usize>,
+    /// The first frame is the one of the entry point, it's never popped
+    frames: Vec<CallFrame>,
+    /// Try regions currently entered, the innermost being the last one
+    handlers: Vec<Handler>,
+    /// Coroutines currently resumed, the innermost being the last one
+    coroutines: Vec<ObjectIndex>,
+    /// Coroutines waiting for their turn in [`VM::run_scheduler`]
+    scheduled: VecDeque<ObjectIndex>,
+    /// Coroutine having its turn in [`VM::run_scheduler`] & the pc to go back to after it
+    turn: Option<(ObjectIndex, usize)>,
+    /// Coroutines held by the host by [`CoroutineId`], `None` once released
+    handles: Vec<Option<ObjectIndex>>,
+    /// (label, position) of each block of the linked program, to name the functions in backtraces
+    labels: Vec<(String, usize)>,
+    /// Debug info of the linked program
+    debug: Option<DebugInfo>,
+    /// Called while running, see [`Observer`]
+    observer: Option<Box<dyn Observer>>,
+    /// Instructions left to run, without limit if `None`
+    fuel: Option<u64>,
+    /// Stops the execution when it's raised, see [`VM::set_interrupt`]
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Used by `send` & `recv`, see [`VM::add_channel`]
+    channels: Vec<Channel>,
+    /// Index of each extern in `extern_fn` by name
     hooks: HashMap<Intern<String>, usize>,
+    /// Data of the host, given to every extern through [`VMState::host`]
+    host: Option<Box<dyn Any + Send>>,
+    /// How `add_i`, `sub_i`, `mul_i`... behave when they overflow
+    overflow: Overflow,
     pc: usize,
 }
 
 impl Default for VM {
     fn default() -> Self {
         Self {
-            stack: Stack::default(),
+            stack: Box::default(),
             object_map: Memory::new(16),
             extern_fn: vec![],
             constants: vec![],
-            call_stack: vec![],
+            loaded: vec![],
+            globals: vec![],
+            global_names: HashMap::default(),
+            classes: vec![],
+            class_names: HashMap::default(),
+            frames: vec![CallFrame::default()],
+            handlers: vec![],
+            coroutines: vec![],
+            scheduled: VecDeque::new(),
+            turn: None,
+            handles: vec![],
+            labels: vec![],
+            debug: None,
+            observer: None,
+            fuel: None,
+            interrupt: None,
+            channels: vec![],
             hooks: HashMap::default(),
-
+            host: None,
+            overflow: Overflow::default(),
             pc: usize::default(),
         }
     }
@@ -44,410 +211,3133 @@ impl Default for VM {
 impl VM {
     pub fn new(mem_space: usize, constants: Vec<VMData>) -> Self {
         Self {
-            stack: Stack::new(),
+            stack: Box::new(Stack::new()),
             object_map: Memory::new(mem_space),
             extern_fn: vec![],
             constants,
-            call_stack: vec![],
+            loaded: vec![],
+            globals: vec![],
+            global_names: HashMap::default(),
+            classes: vec![],
+            class_names: HashMap::default(),
+            frames: vec![CallFrame::default()],
+            handlers: vec![],
+            coroutines: vec![],
+            scheduled: VecDeque::new(),
+            turn: None,
+            handles: vec![],
+            labels: vec![],
+            debug: None,
+            observer: None,
+            fuel: None,
+            interrupt: None,
+            channels: vec![],
             hooks: HashMap::default(),
+            host: None,
+            overflow: Overflow::default(),
             pc: usize::default(),
         }
     }
-    pub fn add_extern_call(&mut self, call: CallBack) -> &mut Self {
-        self.extern_fn.push(call);
+    /// Register `call` as `@name`, taking arguments of the given tags.
+    /// The extern pops its arguments itself & the VM pushes its result.
+    /// Registering the same name twice replaces the previous extern.
+    pub fn add_extern_fn(
+        &mut self,
+        name: &str,
+        args: &[TAG],
+        call: impl FnMut(VMState) -> Result<VMData, VMErrorKind> + Send + 'static,
+    ) -> &mut Self {
+        let name = Intern::new(name.to_owned());
+        let ext = ExternFn {
+            name,
+            args: args.to_vec(),
+            call: Box::new(call),
+        };
+        match self.hooks.get(&name) {
+            Some(i) => self.extern_fn[*i] = ext,
+            None => {
+                self.hooks.insert(name, self.extern_fn.len());
+                self.extern_fn.push(ext);
+            }
+        }
+        self
+    }
+    /// Set the host context given to the externs, replacing the previous one
+    pub fn set_host<T: Any + Send>(&mut self, host: T) {
+        self.host = Some(Box::new(host));
+    }
+    pub fn host<T: Any>(&self) -> Option<&T> {
+        self.host.as_deref().and_then(|h| h.downcast_ref())
+    }
+    pub fn host_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.host.as_deref_mut().and_then(|h| h.downcast_mut())
+    }
+    /// Remove the host context if it's a `T`
+    pub fn take_host<T: Any>(&mut self) -> Option<T> {
+        match self.host.take()?.downcast() {
+            Ok(host) => Some(*host),
+            Err(host) => {
+                self.host = Some(host);
+                None
+            }
+        }
+    }
+    /// Declare the global `name`, or overwrite its value if it already exists
+    pub fn add_global(&mut self, name: &str, val: VMData) -> &mut Self {
+        let name = Intern::new(name.to_owned());
+        match self.global_names.get(&name) {
+            Some(i) => self.globals[*i] = val,
+            None => {
+                self.global_names.insert(name, self.globals.len());
+                self.globals.push(val);
+            }
+        }
+        self
+    }
+    pub fn get_global(&self, name: &str) -> Option<VMData> {
+        let i = self.global_names.get(&Intern::new(name.to_owned()))?;
+        Some(self.globals[*i])
+    }
+    /// Overwrite the value of an existing global
+    pub fn set_global(&mut self, name: &str, val: VMData) -> Result<(), VMErrorKind> {
+        let name = Intern::new(name.to_owned());
+        let i = self
+            .global_names
+            .get(&name)
+            .ok_or(VMErrorKind::UnknownGlobal(name))?;
+        self.globals[*i] = val;
+        Ok(())
+    }
+    /// Position of the next instruction to run
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+    /// The calls currently running, the entry point being the first one
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+    pub fn constants(&self) -> &[VMData] {
+        &self.constants
+    }
+    /// Position of `label` in the linked program
+    pub fn label_address(&self, label: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(name, _)| name == label)
+            .map(|(_, pos)| *pos)
+    }
+    /// Set the observer called while running, replacing the previous one
+    pub fn set_observer(&mut self, observer: impl Observer) -> &mut Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+    pub fn observer_mut<T: Observer>(&mut self) -> Option<&mut T> {
+        let observer: &mut dyn Any = self.observer.as_deref_mut()?;
+        observer.downcast_mut()
+    }
+    /// Remove the observer if it's a `T`
+    pub fn take_observer<T: Observer>(&mut self) -> Option<T> {
+        self.observer_mut::<T>()?;
+        let observer: Box<dyn Any> = self.observer.take()?;
+        observer.downcast().ok().map(|observer| *observer)
+    }
+    /// Limit the number of instructions [`VM::execute`] can still run, `None` removing the limit.
+    /// Once it's all used, the execution stops with [`ExecutionOutcome::OutOfFuel`].
+    pub fn set_fuel(&mut self, fuel: Option<u64>) -> &mut Self {
+        self.fuel = fuel;
+        self
+    }
+    /// Instructions left to run, `None` if there's no limit
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+    /// Raising `flag` from any thread stops the execution with [`ExecutionOutcome::Interrupted`].
+    /// It's checked every few instructions & lowered once it interrupted the VM.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) -> &mut Self {
+        self.interrupt = Some(flag);
+        self
+    }
+    /// A new interrupt flag for this VM, replacing the previous one
+    pub fn interrupt_handle(&mut self) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.interrupt = Some(flag.clone());
+        flag
+    }
+    /// Make `channel` usable by `send $n` & `recv $n`, `n` being the returned index.
+    /// Giving a clone of the same channel to another VM lets them exchange messages.
+    pub fn add_channel(&mut self, channel: Channel) -> usize {
+        self.channels.push(channel);
+        self.channels.len() - 1
+    }
+    pub fn channel(&self, index: usize) -> Option<&Channel> {
+        self.channels.get(index)
+    }
+    /// Whether the loaded program is waiting for a message on an empty channel
+    pub fn is_blocked(&self) -> bool {
+        match self.loaded.get(self.pc) {
+            Some(Instruction::Recv(u)) => self.channels.get(*u).is_some_and(|c| c.is_empty()),
+            _ => false,
+        }
+    }
+    /// Set how the arithmetic instructions without an explicit mode handle overflows
+    pub fn set_overflow(&mut self, mode: Overflow) -> &mut Self {
+        self.overflow = mode;
         self
     }
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+    pub fn class(&self, name: &str) -> Option<&ClassDescriptor> {
+        let i = self.class_names.get(&Intern::new(name.to_owned()))?;
+        Some(&self.classes[*i])
+    }
+    /// Resolve the externs, globals & classes of `program` by name, giving back instructions this VM can run.
+    /// Globals that don't exist yet are declared with their initial value from the program.
+    /// Classes are always declared, replacing the ones with the same name since their methods
+    /// point into `program`.
+    ///
+    /// The constants of the VM are replaced by the ones of `program`, its string literals being
+    /// allocated in the object map. An error there is reported at the `LoadConst` of that constant.
+    /// Its labels & debug info are kept for the backtraces.
+    pub fn link(&mut self, program: &Program) -> Result<Vec<Instruction>, VMError> {
+        self.constants = program.constants.clone();
+        self.labels = program.fn_name.clone();
+        self.debug = program.debug.clone();
+        for (c, s) in &program.strings {
+            let error = |kind| VMError::new(kind, 0, Instruction::LoadConst(*c));
+            if *c >= self.constants.len() {
+                return Err(error(VMErrorKind::InvalidConstant(*c)));
+            }
+            // Every literal is stored right away, so it's a root if the next one triggers a collection
+            let ptr = self.alloc::<true>(s.clone().into()).map_err(error)?;
+            self.constants[*c] = VMData::new_string(ptr);
+        }
+        let mut globals = Vec::with_capacity(program.globals.len());
+        for (i, val) in program.globals.iter().enumerate() {
+            let name = program.global_name.get(i).map_or("", |n| n.as_str());
+            if self.get_global(name).is_none() {
+                self.add_global(name, *val);
+            }
+            globals.push(self.global_names[&Intern::new(name.to_owned())]);
+        }
+        let mut classes: Vec<usize> = Vec::with_capacity(program.classes.len());
+        for class in &program.classes {
+            // A parent is always declared before its children
+            let parent = class.parent.map(|p| classes[p]);
+            let mut vtable = parent
+                .map(|p| self.classes[p].vtable.clone())
+                .unwrap_or_default();
+            for (method, address) in &class.methods {
+                vtable.insert(Intern::new(method.clone()), *address);
+            }
+            let name = Intern::new(class.name.clone());
+            let class = ClassDescriptor {
+                name,
+                parent,
+                fields: class.fields.len(),
+                vtable,
+            };
+            match self.class_names.get(&name) {
+                Some(i) => {
+                    self.classes[*i] = class;
+                    classes.push(*i);
+                }
+                None => {
+                    self.class_names.insert(name, self.classes.len());
+                    classes.push(self.classes.len());
+                    self.classes.push(class);
+                }
+            }
+        }
+        let mut ins = program.ins.clone();
+        for (pc, i) in ins.iter_mut().enumerate() {
+            *i = self
+                .link_instruction(*i, program, &globals, &classes)
+                .map_err(|kind| VMError::new(kind, pc, *i))?;
+        }
+        Ok(ins)
+    }
+    /// `globals` & `classes` give the index in this VM of each global & class of `program`
+    fn link_instruction(
+        &self,
+        ins: Instruction,
+        program: &Program,
+        globals: &[usize],
+        classes: &[usize],
+    ) -> Result<Instruction, VMErrorKind> {
+        let class = |u: usize| classes.get(u).copied().ok_or(VMErrorKind::InvalidClass(u));
+        match ins {
+            Instruction::NewClass(u) => Ok(Instruction::NewClass(class(u)?)),
+            Instruction::GetField(u, field) => Ok(Instruction::GetField(class(u)?, field)),
+            Instruction::SetField(u, field) => Ok(Instruction::SetField(class(u)?, field)),
+            Instruction::LoadGlobal(u) => Ok(Instruction::LoadGlobal(
+                *globals.get(u).ok_or(VMErrorKind::InvalidGlobal(u))?,
+            )),
+            Instruction::StoreGlobal(u) => Ok(Instruction::StoreGlobal(
+                *globals.get(u).ok_or(VMErrorKind::InvalidGlobal(u))?,
+            )),
+            Instruction::ExternCall(u) => {
+                let name = program
+                    .extern_name
+                    .get(u)
+                    .ok_or(VMErrorKind::UnknownExternCall(u))?;
+                let name = Intern::new(name.clone());
+                let index = self
+                    .hooks
+                    .get(&name)
+                    .ok_or(VMErrorKind::MissingExtern(name))?;
+                Ok(Instruction::ExternCall(*index))
+            }
+            _ => Ok(ins),
+        }
+    }
     #[inline(always)]
     pub fn clean(&mut self) {
         self.stack.top = 1;
-        self.call_stack = vec![];
+        self.frames = vec![CallFrame::default()];
+        self.handlers.clear();
         self.pc = usize::default();
+        // Coroutines left running can't be resumed anymore
+        for ptr in self.coroutines.drain(..) {
+            if let Ok(Object::Coroutine(c)) = self.object_map.try_get_mut(ptr) {
+                c.status = CoroutineStatus::Dead;
+            }
+        }
+        self.turn = None;
     }
 
-    pub fn execute(&mut self, ins: &[Instruction]) {
+    /// Keep `ins`, which should have been linked by this VM, as the program to run with
+    /// [`VM::run`], [`VM::run_for`] & [`VM::resume`]. The VM is reset to its start.
+    pub fn load(&mut self, ins: Vec<Instruction>) -> &mut Self {
+        self.loaded = ins;
+        self.reset();
+        self
+    }
+    /// The program given to [`VM::load`]
+    pub fn loaded(&self) -> &[Instruction] {
+        &self.loaded
+    }
+    /// Go back to the start of the loaded program, dropping its stack, calls & try regions.
+    /// Globals & objects are kept.
+    pub fn reset(&mut self) {
+        self.clean();
+    }
+    /// Whether the loaded program reached `hlt` or its end
+    pub fn is_halted(&self) -> bool {
+        matches!(self.loaded.get(self.pc), Some(Instruction::HLT) | None)
+    }
+    /// Run the loaded program from its start, see [`VM::resume`]
+    pub fn run(&mut self) -> Result<ExecutionOutcome, VMError> {
+        self.reset();
+        self.resume()
+    }
+    /// Run the loaded program from where it stopped, until it halts, fails or is stopped by the
+    /// fuel or an interrupt. Unlike [`VM::execute`], the VM is never cleaned, so the results can be
+    /// read off the stack once it halted. After an error, it should be [`VM::reset`].
+    pub fn resume(&mut self) -> Result<ExecutionOutcome, VMError> {
+        let ins = std::mem::take(&mut self.loaded);
+        let outcome = self.dispatch::<true>(&ins, false);
+        self.loaded = ins;
+        outcome
+    }
+    /// Create a coroutine running the function at `entry` & give it a turn in
+    /// [`VM::run_scheduler`]
+    pub fn spawn(&mut self, entry: usize) -> Result<CoroutineId, VMErrorKind> {
+        let ptr = self.alloc::<true>(Coroutine::new(entry).into())?;
+        let id = self.hold(ptr)?;
+        self.scheduled.push_back(ptr);
+        Ok(id)
+    }
+    /// Keep the coroutine at `ptr`, e.g. one created by the program, alive until it's
+    /// [`VM::release`]d. The id stays valid across collections.
+    pub fn hold(&mut self, ptr: ObjectIndex) -> Result<CoroutineId, VMErrorKind> {
+        self.coroutine_mut(ptr)?;
+        self.handles.push(Some(ptr));
+        Ok(CoroutineId(self.handles.len() - 1))
+    }
+    /// Stop holding a coroutine, it's collected once nothing else references it
+    pub fn release(&mut self, id: CoroutineId) -> Result<(), VMErrorKind> {
+        self.handles
+            .get_mut(id.0)
+            .and_then(Option::take)
+            .map(|_| ())
+            .ok_or(VMErrorKind::InvalidCoroutine(id.0))
+    }
+    /// The coroutine held as `id`, e.g. to check whether it's dead
+    pub fn coroutine(&self, id: CoroutineId) -> Result<&Coroutine, VMErrorKind> {
+        let ptr = self.handle(id)?;
+        match self.object_map.try_get(ptr)? {
+            Object::Coroutine(c) => Ok(c),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+    /// Give a turn in [`VM::run_scheduler`] to a held coroutine
+    pub fn schedule(&mut self, id: CoroutineId) -> Result<(), VMErrorKind> {
+        let ptr = self.handle(id)?;
+        self.scheduled.push_back(ptr);
+        Ok(())
+    }
+    fn handle(&self, id: CoroutineId) -> Result<ObjectIndex, VMErrorKind> {
+        self.handles
+            .get(id.0)
+            .copied()
+            .flatten()
+            .ok_or(VMErrorKind::InvalidCoroutine(id.0))
+    }
+    /// Resume the scheduled coroutines of the loaded program in turn, each one running until it
+    /// yields or returns. A coroutine that yields is scheduled again after the others, so it
+    /// ends once they're all dead. They're resumed with unit & the values they yield are dropped.
+    /// A coroutine reaching `hlt` is dead, the others still get their turns.
+    ///
+    /// If the fuel or an interrupt stops a coroutine, it continues on the next call.
+    pub fn run_scheduler(&mut self) -> Result<ExecutionOutcome, VMError> {
+        let ins = std::mem::take(&mut self.loaded);
+        let outcome = self.round_robin(&ins);
+        self.loaded = ins;
+        outcome
+    }
+    fn round_robin(&mut self, ins: &[Instruction]) -> Result<ExecutionOutcome, VMError> {
+        let error = |kind, pc| VMError::new(kind, pc, Instruction::CoroResume);
+        loop {
+            if self.turn.is_none() {
+                let Some(ptr) = self.scheduled.pop_front() else {
+                    self.finish::<true>(false);
+                    return Ok(ExecutionOutcome::Halted);
+                };
+                let pc = self.pc;
+                let status = self.coroutine_mut(ptr).map_err(|e| error(e, pc))?.status;
+                // It died or was resumed by the program since it was scheduled
+                if status != CoroutineStatus::Suspended {
+                    continue;
+                }
+                self.enter_coroutine(ptr, VMData::new_unit(), HOST_PC)
+                    .map_err(|e| error(e, pc))?;
+                self.turn = Some((ptr, pc));
+            }
+            let outcome = self.dispatch::<true>(ins, false)?;
+            if outcome != ExecutionOutcome::Halted {
+                return Ok(outcome);
+            }
+            let (ptr, pc) = self.turn.take().unwrap();
+            if self.pc != HOST_PC {
+                // It reached `hlt`, which ends it along with the coroutines it resumed
+                while let Some(inner) = self.coroutines.pop() {
+                    self.leave_coroutine(inner, CoroutineStatus::Dead)
+                        .map_err(|e| error(e, pc))?;
+                    if inner == ptr {
+                        break;
+                    }
+                }
+            }
+            self.pc = pc;
+            if self.coroutine_mut(ptr).map_err(|e| error(e, pc))?.status
+                == CoroutineStatus::Suspended
+            {
+                self.scheduled.push_back(ptr);
+            }
+        }
+    }
+    /// Same as [`VM::resume`] but at most `steps` instructions are run, giving back
+    /// [`ExecutionOutcome::Paused`] if the program is still running after them.
+    /// They're taken from the fuel if there's some.
+    pub fn run_for(&mut self, steps: u64) -> Result<ExecutionOutcome, VMError> {
+        let fuel = self.fuel;
+        let budget = fuel.map_or(steps, |f| f.min(steps));
+        self.fuel = Some(budget);
+        let outcome = self.resume();
+        let used = budget - self.fuel.unwrap_or(0);
+        self.fuel = fuel.map(|f| f - used);
+        match outcome {
+            Ok(ExecutionOutcome::OutOfFuel) if self.fuel != Some(0) => Ok(ExecutionOutcome::Paused),
+            outcome => outcome,
+        }
+    }
+
+    /// Run `ins` from the current pc, which is the start unless the last execution was stopped
+    /// by the fuel, an interrupt or a `recv`. In that case the VM is left as is so it can be
+    /// resumed by executing the same instructions again, or cleaned with [`VM::clean`].
+    /// Once `ins` halts or fails, the VM is cleaned, see [`VM::load`] to keep its state.
+    pub fn execute(&mut self, ins: &[Instruction]) -> Result<ExecutionOutcome, VMError> {
+        self.dispatch::<true>(ins, true)
+    }
+    /// Check `ins` against the constants & extern calls of this VM.
+    /// See [`verifier::verify`] for what is checked.
+    pub fn verify<'a>(&self, ins: &'a [Instruction]) -> Result<Verified<'a>, Vec<Diagnostic>> {
+        let extern_args: Vec<usize> = self.extern_fn.iter().map(|e| e.args.len()).collect();
+        let methods: Vec<(Intern<String>, usize)> = self
+            .classes
+            .iter()
+            .flat_map(|c| c.vtable.iter().map(|(name, address)| (*name, *address)))
+            .collect();
+        let diagnostics = verifier::verify(
+            ins,
+            self.constants.len(),
+            self.globals.len(),
+            self.classes.len(),
+            &extern_args,
+            &methods,
+        );
+        if diagnostics.is_empty() {
+            Ok(Verified {
+                ins,
+                constants: self.constants.len(),
+                extern_fn: self.extern_fn.len(),
+            })
+        } else {
+            Err(diagnostics)
+        }
+    }
+    /// Same as [`VM::execute`] but constant & extern call indices aren't checked anymore.
+    /// If `code` was verified by a VM with more constants or extern calls, it's fully checked instead.
+    pub fn execute_verified(&mut self, code: Verified) -> Result<ExecutionOutcome, VMError> {
+        if code.constants <= self.constants.len() && code.extern_fn <= self.extern_fn.len() {
+            self.dispatch::<false>(code.ins, true)
+        } else {
+            self.dispatch::<true>(code.ins, true)
+        }
+    }
+    /// `clean` tells whether the VM is cleaned once `ins` halts or fails
+    fn dispatch<const CHECKED: bool>(
+        &mut self,
+        ins: &[Instruction],
+        clean: bool,
+    ) -> Result<ExecutionOutcome, VMError> {
+        let limited = self.fuel.is_some() || self.interrupt.is_some();
+        match (self.observer.is_some(), limited) {
+            (true, true) => self.run_loop::<CHECKED, true, true>(ins, clean),
+            (true, false) => self.run_loop::<CHECKED, true, false>(ins, clean),
+            (false, true) => self.run_loop::<CHECKED, false, true>(ins, clean),
+            (false, false) => self.run_loop::<CHECKED, false, false>(ins, clean),
+        }
+    }
+    /// The observer is only called if `OBSERVED` & the fuel & interrupt only checked if `LIMITED`,
+    /// so there's no cost at all without them
+    fn run_loop<const CHECKED: bool, const OBSERVED: bool, const LIMITED: bool>(
+        &mut self,
+        ins: &[Instruction],
+        clean: bool,
+    ) -> Result<ExecutionOutcome, VMError> {
+        let mut until_poll = INTERRUPT_PERIOD;
         while self.pc < ins.len() {
             let ins = &ins[self.pc];
-            #[cfg(debug_assertions)]
-            println!("{:?}", ins);
-            //#[cfg(debug_assertions)]
-            //println!("Memory: [{:?}, {:?}, {:?}]", self.object_map.get(ObjectIndex::new(0)), self.object_map.get(ObjectIndex::new(1)), self.object_map.get(ObjectIndex::new(2)));
             match ins {
                 Instruction::HLT => break,
                 _ => {
-                    self.execute_instruction(ins);
+                    if LIMITED {
+                        if let Some(outcome) = self.limit(&mut until_poll) {
+                            return Ok(outcome);
+                        }
+                    }
+                    if let Err(e) = self.step::<CHECKED, OBSERVED>(ins) {
+                        // `recv` is run again once there's a message, its fuel is given back
+                        if let VMErrorKind::EmptyChannel(_) = e.kind {
+                            if LIMITED {
+                                if let Some(fuel) = &mut self.fuel {
+                                    *fuel += 1;
+                                }
+                            }
+                            return Ok(ExecutionOutcome::Blocked);
+                        }
+                        self.finish::<OBSERVED>(clean);
+                        return Err(e);
+                    }
                 }
             }
-            #[cfg(debug_assertions)]
-            println!("{}", self.stack);
-
-            #[cfg(debug_assertions)]
-            {
-                use std::thread;
-                use std::time::Duration;
-                thread::sleep(Duration::from_millis(250));
+        }
+        // The end of a turn isn't the end of the run, see `VM::round_robin`
+        if self.turn.is_none() {
+            self.finish::<OBSERVED>(clean);
+        }
+        Ok(ExecutionOutcome::Halted)
+    }
+    /// Whether the next instruction can run, using its fuel
+    #[inline(always)]
+    fn limit(&mut self, until_poll: &mut u32) -> Option<ExecutionOutcome> {
+        if let Some(flag) = &self.interrupt {
+            *until_poll -= 1;
+            if *until_poll == 0 {
+                *until_poll = INTERRUPT_PERIOD;
+                if flag.swap(false, atomic::Ordering::Relaxed) {
+                    return Some(ExecutionOutcome::Interrupted);
+                }
             }
         }
-        self.clean();
+        match &mut self.fuel {
+            Some(0) => Some(ExecutionOutcome::OutOfFuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                None
+            }
+            None => None,
+        }
     }
-    pub fn execute_instruction(&mut self, ins: &Instruction) {
+    fn finish<const OBSERVED: bool>(&mut self, clean: bool) {
+        if OBSERVED {
+            if let Some(observer) = self.observer.as_deref_mut() {
+                observer.on_finish();
+            }
+        }
+        if clean {
+            self.clean();
+        }
+    }
+    #[inline(always)]
+    fn step<const CHECKED: bool, const OBSERVED: bool>(
+        &mut self,
+        ins: &Instruction,
+    ) -> Result<(), VMError> {
+        let pc = self.pc;
+        if OBSERVED {
+            if let Some(observer) = self.observer.as_deref_mut() {
+                observer.before_instruction(pc, ins, &self.stack);
+            }
+        }
+        if let Err(kind) = self.run_instruction::<CHECKED, OBSERVED>(ins) {
+            return Err(VMError::new(kind, self.pc, *ins).with_backtrace(self.backtrace()));
+        }
+        if OBSERVED {
+            if let Some(observer) = self.observer.as_deref_mut() {
+                observer.after_instruction(pc, ins, &self.stack);
+            }
+        }
+        Ok(())
+    }
+    pub fn execute_instruction(&mut self, ins: &Instruction) -> Result<(), VMError> {
+        if self.observer.is_some() {
+            self.step::<true, true>(ins)
+        } else {
+            self.step::<true, false>(ins)
+        }
+    }
+    /// The functions currently called, the innermost first
+    pub fn backtrace(&self) -> Vec<TraceFrame> {
+        let mut pc = self.pc;
+        let mut trace = Vec::with_capacity(self.frames.len());
+        for frame in self.frames.iter().rev() {
+            trace.push(TraceFrame {
+                pc,
+                function: self.function_name(frame.entry, pc),
+                location: self.debug.as_ref().and_then(|d| d.location(pc)),
+            });
+            // The caller is still at its call
+            pc = frame.ret.saturating_sub(1);
+        }
+        trace
+    }
+    /// Label of the function starting at `entry`, or the one of the block containing `pc`
+    /// if the function has none
+    fn function_name(&self, entry: usize, pc: usize) -> Option<String> {
+        self.labels
+            .iter()
+            .find(|(_, pos)| *pos == entry)
+            .or_else(|| {
+                self.labels
+                    .iter()
+                    .filter(|(_, pos)| *pos <= pc)
+                    .max_by_key(|(_, pos)| *pos)
+            })
+            .map(|(name, _)| name.clone())
+    }
+    /// Constant & extern call indices are only checked if `CHECKED`,
+    /// otherwise the instructions must have been verified against this VM.
+    fn run_instruction<const CHECKED: bool, const OBSERVED: bool>(
+        &mut self,
+        ins: &Instruction,
+    ) -> Result<(), VMErrorKind> {
         use Instruction::*;
         match ins {
-            PushI(i) => self.stack.push(VMData::new_i64(*i)),
-            PushF(f) => self.stack.push(VMData::new_f64(*f)),
-            PushU(u) => self.stack.push(VMData::new_u64(*u)),
+            PushI(i) => self.stack.push(VMData::new_i64(*i))?,
+            PushF(f) => self.stack.push(VMData::new_f64(*f))?,
+            PushU(u) => self.stack.push(VMData::new_u64(*u))?,
             LoadConst(u) => {
                 //constants aren't loaded as is, but are fetched from constants: Vec<VMData>
-                #[cfg(debug_assertions)]
-                if self.constants.len() < *u {
-                    panic!("Can't load that constant, it doesn't exist");
-                }
-                self.stack.push(self.constants[*u]);
+                let c = if CHECKED {
+                    *self
+                        .constants
+                        .get(*u)
+                        .ok_or(VMErrorKind::InvalidConstant(*u))?
+                } else {
+                    // SAFETY: the verifier checked every index against the constants of this VM
+                    unsafe { *self.constants.get_unchecked(*u) }
+                };
+                self.stack.push(c)?;
+            }
+            LoadGlobal(u) => {
+                let val = *self.globals.get(*u).ok_or(VMErrorKind::InvalidGlobal(*u))?;
+                self.stack.push(val)?;
+            }
+            StoreGlobal(u) => {
+                let val = self.stack.pop()?;
+                let global = self
+                    .globals
+                    .get_mut(*u)
+                    .ok_or(VMErrorKind::InvalidGlobal(*u))?;
+                *global = val;
             }
             Pop => {
-                self.stack.pop().expect("Stack Underflow");
+                self.stack.pop()?;
             }
             Print => {
-                let value = self.stack.last().expect("Stack Underflow");
+                let value = self.stack.last()?;
                 println!("val: {}", value)
             }
-            AddI => {
-                let b = self.stack.pop().expect("Stack Underflow").as_i64();
-                let a = self.stack.pop().expect("Stack Underflow").as_i64();
-                self.stack.push(VMData::new_i64(a + b));
+            AddI | SubI | MulI | AddIWith(_) | SubIWith(_) | MulIWith(_) => {
+                let (op, mode) = self.int_op(ins);
+                let b = self.stack.pop()?.try_i64()?;
+                let a = self.stack.pop()?.try_i64()?;
+                self.stack
+                    .push(VMData::new_i64(arith_i64(op, mode, a, b)?))?;
             }
             AddF => {
-                let b = self.stack.pop().expect("Stack Underflow").as_f64();
-                let a = self.stack.pop().expect("Stack Underflow").as_f64();
-                self.stack.push(VMData::new_f64(a + b));
+                let b = self.stack.pop()?.try_f64()?;
+                let a = self.stack.pop()?.try_f64()?;
+                self.stack.push(VMData::new_f64(a + b))?;
             }
-            AddU => {
-                let b = self.stack.pop().expect("Stack Underflow").as_u64();
-                let a = self.stack.pop().expect("Stack Underflow").as_u64();
-                self.stack.push(VMData::new_u64(a + b));
-            }
-            MulI => {
-                let b = self.stack.pop().expect("Stack Underflow").as_i64();
-                let a = self.stack.pop().expect("Stack Underflow").as_i64();
-                self.stack.push(VMData::new_i64(a * b));
+            AddU | SubU | MulU | AddUWith(_) | SubUWith(_) | MulUWith(_) => {
+                let (op, mode) = self.int_op(ins);
+                let b = self.stack.pop()?.try_u64()?;
+                let a = self.stack.pop()?.try_u64()?;
+                self.stack
+                    .push(VMData::new_u64(arith_u64(op, mode, a, b)?))?;
             }
             MulF => {
-                let b = self.stack.pop().expect("Stack Underflow").as_f64();
-                let a = self.stack.pop().expect("Stack Underflow").as_f64();
-                self.stack.push(VMData::new_f64(a * b));
-            }
-            MulU => {
-                let b = self.stack.pop().expect("Stack Underflow").as_u64();
-                let a = self.stack.pop().expect("Stack Underflow").as_u64();
-                self.stack.push(VMData::new_u64(a + b));
+                let b = self.stack.pop()?.try_f64()?;
+                let a = self.stack.pop()?.try_f64()?;
+                self.stack.push(VMData::new_f64(a * b))?;
             }
-            DivI => {
-                let b = self.stack.pop().expect("Stack Underflow").as_i64();
+            DivI | DivIWith(_) => {
+                let b = self.stack.pop()?.try_i64()?;
                 if b == 0 {
-                    panic!("Can't divide by 0");
+                    return Err(VMErrorKind::DivisionByZero);
                 }
-                let a = self.stack.pop().expect("Stack Underflow").as_i64();
-                self.stack.push(VMData::new_i64(a / b));
+                let a = self.stack.pop()?.try_i64()?;
+                // `i64::MIN / -1` is the only division that can overflow
+                let res = match self.overflow_of(ins) {
+                    Overflow::Checked => a.checked_div(b).ok_or(VMErrorKind::IntegerOverflow)?,
+                    Overflow::Wrapping => a.wrapping_div(b),
+                    Overflow::Saturating => a.saturating_div(b),
+                };
+                self.stack.push(VMData::new_i64(res))?;
             }
             DivF => {
-                let b = self.stack.pop().expect("Stack Underflow").as_f64();
+                let b = self.stack.pop()?.try_f64()?;
                 if b == 0.0 {
-                    panic!("Can't divide by 0");
+                    return Err(VMErrorKind::DivisionByZero);
                 }
-                let a = self.stack.pop().expect("Stack Underflow").as_f64();
-                self.stack.push(VMData::new_f64(a / b));
+                let a = self.stack.pop()?.try_f64()?;
+                self.stack.push(VMData::new_f64(a / b))?;
             }
             DivU => {
-                let b = self.stack.pop().expect("Stack Underflow").as_u64();
+                let b = self.stack.pop()?.try_u64()?;
                 if b == 0 {
-                    panic!("Can't divide by 0");
+                    return Err(VMErrorKind::DivisionByZero);
                 }
-                let a = self.stack.pop().expect("Stack Underflow").as_u64();
-                self.stack.push(VMData::new_u64(a / b));
+                let a = self.stack.pop()?.try_u64()?;
+                self.stack.push(VMData::new_u64(a / b))?;
             }
-            SubI => {
-                let b = self.stack.pop().expect("Stack Underflow").as_i64();
-                let a = self.stack.pop().expect("Stack Underflow").as_i64();
-                self.stack.push(VMData::new_i64(a - b));
+            ModI => {
+                let b = self.stack.pop()?.try_i64()?;
+                if b == 0 {
+                    return Err(VMErrorKind::DivisionByZero);
+                }
+                let a = self.stack.pop()?.try_i64()?;
+                // `i64::MIN % -1` overflows, but its remainder is still 0
+                self.stack.push(VMData::new_i64(a.wrapping_rem(b)))?;
             }
-            SubF => {
-                let b = self.stack.pop().expect("Stack Underflow").as_f64();
-                let a = self.stack.pop().expect("Stack Underflow").as_f64();
-                self.stack.push(VMData::new_f64(a - b));
+            ModU => {
+                let b = self.stack.pop()?.try_u64()?;
+                if b == 0 {
+                    return Err(VMErrorKind::DivisionByZero);
+                }
+                let a = self.stack.pop()?.try_u64()?;
+                self.stack.push(VMData::new_u64(a % b))?;
+            }
+            NegI | NegIWith(_) => {
+                let a = self.stack.pop()?.try_i64()?;
+                let res = match self.overflow_of(ins) {
+                    Overflow::Checked => a.checked_neg().ok_or(VMErrorKind::IntegerOverflow)?,
+                    Overflow::Wrapping => a.wrapping_neg(),
+                    Overflow::Saturating => a.saturating_neg(),
+                };
+                self.stack.push(VMData::new_i64(res))?;
             }
-            SubU => {
-                let b = self.stack.pop().expect("Stack Underflow").as_u64();
-                let a = self.stack.pop().expect("Stack Underflow").as_u64();
-                self.stack.push(VMData::new_u64(a - b));
+            AbsI | AbsIWith(_) => {
+                let a = self.stack.pop()?.try_i64()?;
+                let res = match self.overflow_of(ins) {
+                    Overflow::Checked => a.checked_abs().ok_or(VMErrorKind::IntegerOverflow)?,
+                    Overflow::Wrapping => a.wrapping_abs(),
+                    Overflow::Saturating => a.saturating_abs(),
+                };
+                self.stack.push(VMData::new_i64(res))?;
+            }
+            MinI | MaxI => {
+                let b = self.stack.pop()?.try_i64()?;
+                let a = self.stack.pop()?.try_i64()?;
+                let res = if matches!(ins, MinI) {
+                    a.min(b)
+                } else {
+                    a.max(b)
+                };
+                self.stack.push(VMData::new_i64(res))?;
+            }
+            MinU | MaxU => {
+                let b = self.stack.pop()?.try_u64()?;
+                let a = self.stack.pop()?.try_u64()?;
+                let res = if matches!(ins, MinU) {
+                    a.min(b)
+                } else {
+                    a.max(b)
+                };
+                self.stack.push(VMData::new_u64(res))?;
+            }
+            BAnd | BOr | BXor => {
+                let b = self.stack.pop()?;
+                let (tag, a) = self.pop_bits()?;
+                if b.tag != tag {
+                    return Err(VMErrorKind::TypeMismatch {
+                        expected: tag,
+                        found: b.tag,
+                    });
+                }
+                let b = int_bits(&b)?;
+                let res = match ins {
+                    BAnd => a & b,
+                    BOr => a | b,
+                    _ => a ^ b,
+                };
+                self.stack.push(from_int_bits(tag, res))?;
+            }
+            BNot => {
+                let (tag, a) = self.pop_bits()?;
+                self.stack.push(from_int_bits(tag, !a))?;
+            }
+            Shl | Shr | Sar => {
+                let amount = *self.stack.last()?;
+                if amount.tag == VMData::TAG_I64 && amount.as_i64() < 0 {
+                    return Err(VMErrorKind::NegativeShift(amount.as_i64()));
+                }
+                let n = self.pop_index()?;
+                let (tag, a) = self.pop_bits()?;
+                let res = match ins {
+                    Sar if tag == VMData::TAG_I64 => ((a as i64) >> n.min(63)) as u64,
+                    _ if n >= 64 => 0,
+                    Shl => a << n,
+                    _ => a >> n,
+                };
+                self.stack.push(from_int_bits(tag, res))?;
+            }
+            SubF => {
+                let b = self.stack.pop()?.try_f64()?;
+                let a = self.stack.pop()?.try_f64()?;
+                self.stack.push(VMData::new_f64(a - b))?;
             }
             Dup => {
-                let last = self.stack.last().expect("Stack Underflow");
-                self.stack.push(*last);
+                let last = *self.stack.last()?;
+                self.stack.push(last)?;
             }
             Swap => {
-                let a = self.stack.pop().expect("Stack Underflow");
-                let b = self.stack.pop().expect("Stack Underflow");
-                self.stack.push(a);
-                self.stack.push(b);
+                let a = self.stack.pop()?;
+                let b = self.stack.pop()?;
+                self.stack.push(a)?;
+                self.stack.push(b)?;
             }
             Rot => {
-                let a = self.stack.pop().expect("Stack Underflow");
-                let b = self.stack.pop().expect("Stack Underflow");
-                let c = self.stack.pop().expect("Stack Underflow");
-                self.stack.push(c);
-                self.stack.push(b);
-                self.stack.push(a);
+                let a = self.stack.pop()?;
+                let b = self.stack.pop()?;
+                let c = self.stack.pop()?;
+                self.stack.push(c)?;
+                self.stack.push(b)?;
+                self.stack.push(a)?;
             }
             Jmp(address) => {
-                self.pc = address.into();
-                return;
+                self.pc = address.try_into()?;
+                return Ok(());
             }
             JmpNZ(address) => {
-                let val = self.stack.pop().expect("Stack Underflow").as_u64();
-                if val != 0 {
-                    self.pc = address.into();
-                    return;
+                let val = self.pop_condition()?;
+                if val {
+                    self.pc = address.try_into()?;
+                    return Ok(());
                 }
             }
             JmpZ(address) => {
-                let val = self.stack.pop().expect("Stack Underflow").as_u64();
-                if val == 0 {
-                    self.pc = address.into();
-                    return;
+                let val = self.pop_condition()?;
+                if !val {
+                    self.pc = address.try_into()?;
+                    return Ok(());
                 }
             }
             ExternCall(address) => {
-                let vm_state = VMState::new(&mut self.stack, &mut self.object_map, &self.constants);
-                match self.extern_fn[*address](vm_state) {
-                    Ok(val) => {
-                        self.stack.push(val);
+                let ext = if CHECKED {
+                    self.extern_fn
+                        .get_mut(*address)
+                        .ok_or(VMErrorKind::UnknownExternCall(*address))?
+                } else {
+                    // SAFETY: the verifier checked every index & extern calls can't be removed
+                    // while running
+                    unsafe { self.extern_fn.get_unchecked_mut(*address) }
+                };
+                let args = self.stack.last_n(ext.args.len())?;
+                for (val, tag) in args.iter().zip(&ext.args) {
+                    if *tag != ExternFn::ANY && val.tag != *tag {
+                        return Err(VMErrorKind::TypeMismatch {
+                            expected: *tag,
+                            found: val.tag,
+                        });
+                    }
+                }
+                if OBSERVED {
+                    if let Some(observer) = self.observer.as_deref_mut() {
+                        observer.on_extern_call(ext.name, args);
                     }
-                    Err(_) => {
-                        println!("there was an error with extern call");
+                }
+                let top = self.stack.top;
+                let vm_state = VMState::new(
+                    &mut self.stack,
+                    &mut self.object_map,
+                    &self.constants,
+                    self.host.as_deref_mut(),
+                );
+                let val = match (ext.call)(vm_state) {
+                    Ok(val) => val,
+                    Err(VMErrorKind::Exception(val)) => return self.throw::<OBSERVED>(val),
+                    Err(e) => {
+                        return Err(VMErrorKind::ExternFailed {
+                            name: ext.name,
+                            source: Box::new(e),
+                        })
                     }
+                };
+                // The verifier relies on externs only popping their own arguments
+                if self.stack.top + ext.args.len() != top {
+                    return Err(VMErrorKind::ExternStackMismatch {
+                        name: ext.name,
+                        args: ext.args.len(),
+                        popped: top as isize - self.stack.top as isize,
+                    });
                 }
+                self.stack.push(val)?;
             }
             Call(address) => {
-                self.call_stack.push(self.pc + 1);
-                self.pc = address.into();
-                return;
+                let address = address.try_into()?;
+                self.frames.push(CallFrame {
+                    ret: self.pc + 1,
+                    entry: address,
+                    bp: self.stack.top,
+                    ..Default::default()
+                });
+                if OBSERVED {
+                    if let Some(observer) = self.observer.as_deref_mut() {
+                        observer.on_call(self.pc, address, self.frames.len());
+                    }
+                }
+                self.pc = address;
+                return Ok(());
             }
             Ret => {
-                self.pc = self.call_stack.pop().expect("Call Stack Underflow");
-                //println!("return: {}", self.stack);
-                return;
+                if self.frames.len() <= 1 {
+                    // The end of a coroutine gives back the top of its stack to its resumer
+                    if self.coroutines.is_empty() {
+                        return Err(VMErrorKind::CallStackUnderflow);
+                    }
+                    let val = self.stack.pop()?;
+                    let ptr = self.coroutines.pop().unwrap();
+                    self.leave_coroutine(ptr, CoroutineStatus::Dead)?;
+                    self.stack.push(val)?;
+                    return Ok(());
+                }
+                let frame = self.frames.pop().unwrap();
+                // Regions left open by the function end with it
+                let depth = self.frames.len();
+                self.handlers.retain(|h| h.frames <= depth);
+                if frame.framed {
+                    if self.stack.top <= frame.bp + frame.args + frame.locals {
+                        return Err(VMErrorKind::StackUnderflow);
+                    }
+                    let val = self.stack.pop()?;
+                    self.stack.top = frame.bp;
+                    self.stack.push(val)?;
+                }
+                if OBSERVED {
+                    if let Some(observer) = self.observer.as_deref_mut() {
+                        observer.on_ret(self.pc, frame.ret, self.frames.len());
+                    }
+                }
+                self.pc = frame.ret;
+                return Ok(());
+            }
+            Enter(args, locals) => {
+                let frame = self.frames.last_mut().unwrap();
+                // The first slot of the stack is never used, so it can't be an argument
+                frame.bp = self
+                    .stack
+                    .top
+                    .checked_sub(*args)
+                    .filter(|bp| *bp >= 1)
+                    .ok_or(VMErrorKind::StackUnderflow)?;
+                frame.args = *args;
+                frame.locals = *locals;
+                frame.framed = true;
+                for _ in 0..*locals {
+                    self.stack.push(VMData::new_unit())?;
+                }
+            }
+            TryBegin(address) => {
+                self.handlers.push(Handler {
+                    address: address.try_into()?,
+                    frames: self.frames.len(),
+                    top: self.stack.top,
+                });
+            }
+            TryEnd => {
+                self.handlers.pop();
+            }
+            Throw => {
+                let val = self.stack.pop()?;
+                return self.throw::<OBSERVED>(val);
+            }
+            CoroCreate(address) => {
+                let ptr = self.alloc::<OBSERVED>(Coroutine::new(address.try_into()?).into())?;
+                self.stack.push(VMData::new_object(257, ptr))?;
+            }
+            CoroResume => {
+                let val = self.stack.pop()?;
+                let ptr = self.stack.pop()?.try_object()?;
+                return self.enter_coroutine(ptr, val, self.pc + 1);
+            }
+            Yield => {
+                let val = self.stack.pop()?;
+                let ptr = self
+                    .coroutines
+                    .pop()
+                    .ok_or(VMErrorKind::YieldOutsideCoroutine)?;
+                self.pc += 1;
+                self.leave_coroutine(ptr, CoroutineStatus::Suspended)?;
+                self.stack.push(val)?;
+                return Ok(());
+            }
+            CoroStatus => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let status = self.coroutine_mut(ptr)?.status;
+                self.stack.push(VMData::new_i64(status as i64))?;
+            }
+            Send(u) => {
+                let channel = self
+                    .channels
+                    .get(*u)
+                    .ok_or(VMErrorKind::InvalidChannel(*u))?;
+                let val = self.stack.pop()?;
+                channel.send(Message::new(&self.object_map, val)?);
+            }
+            Recv(u) => {
+                let message = self
+                    .channels
+                    .get(*u)
+                    .ok_or(VMErrorKind::InvalidChannel(*u))?
+                    .try_recv()
+                    .ok_or(VMErrorKind::EmptyChannel(*u))?;
+                let val = message.paste(&mut self.object_map)?;
+                self.stack.push(val)?;
+                if self.object_map.should_collect() {
+                    self.gc(&mut []);
+                }
+            }
+            LoadArg(u) => {
+                let frame = self.frames.last().unwrap();
+                if *u >= frame.args {
+                    return Err(VMErrorKind::ArgOutOfBounds(*u));
+                }
+                let val = self.stack.get(frame.bp + *u)?;
+                self.stack.push(val)?;
+            }
+            LoadLocal(u) => {
+                let frame = self.frames.last().unwrap();
+                if *u >= frame.locals {
+                    return Err(VMErrorKind::LocalOutOfBounds(*u));
+                }
+                let val = self.stack.get(frame.bp + frame.args + *u)?;
+                self.stack.push(val)?;
+            }
+            StoreLocal(u) => {
+                let frame = *self.frames.last().unwrap();
+                if *u >= frame.locals {
+                    return Err(VMErrorKind::LocalOutOfBounds(*u));
+                }
+                let val = self.stack.pop()?;
+                self.stack.set(frame.bp + frame.args + *u, val)?;
             }
             CastToI => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
                 let res = match val.tag {
                     VMData::TAG_CHAR => val.as_char() as i64,
                     VMData::TAG_I64 => val.as_i64(),
                     VMData::TAG_FLOAT => val.as_f64() as i64,
                     VMData::TAG_U64 => val.as_u64() as i64,
                     VMData::TAG_BOOL => val.as_bool() as i64,
-                    f if f > 256 | VMData::TAG_STR => val.as_object().idx as i64,
+                    _ if val.is_object() => val.as_object().idx as i64,
                     _ => {
-                        if val.tag > 256 || val.tag == VMData::TAG_STR {
-                            val.as_object().idx as i64
-                        } else {
-                            unimplemented!("cast_to_int isn't implemented for tag: {}", val.tag)
-                        }
+                        return Err(VMErrorKind::InvalidCast {
+                            to: VMData::TAG_I64,
+                            found: val.tag,
+                        })
                     }
                 };
-                self.stack.push(VMData::new_i64(res));
+                self.stack.push(VMData::new_i64(res))?;
             }
             CastToPtr => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
                 let res = match val.tag {
                     VMData::TAG_I64 => ObjectIndex::new(val.as_i64() as u64),
                     VMData::TAG_U64 => ObjectIndex::new(val.as_u64()),
-                    f if f > 256 | VMData::TAG_STR => val.as_object(),
-                    _ => unimplemented!("cast_to_ptr isn't implemented for tag; {}", val.tag),
+                    _ if val.is_object() => val.as_object(),
+                    _ => {
+                        return Err(VMErrorKind::InvalidCast {
+                            to: 257,
+                            found: val.tag,
+                        })
+                    }
                 };
-                self.stack.push(VMData::new_object(257, res));
+                self.stack.push(VMData::new_object(257, res))?;
             }
             CastToF => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
                 let res = match val.tag {
                     VMData::TAG_CHAR => val.as_char() as i64 as f64,
                     VMData::TAG_I64 => val.as_i64() as f64,
                     VMData::TAG_FLOAT => val.as_f64(),
                     VMData::TAG_U64 => val.as_u64() as f64,
                     VMData::TAG_BOOL => val.as_bool() as i64 as f64,
-                    _ => unimplemented!("cast_to_float isn't implement for tag: {}", val.tag),
+                    _ => {
+                        return Err(VMErrorKind::InvalidCast {
+                            to: VMData::TAG_FLOAT,
+                            found: val.tag,
+                        })
+                    }
                 };
-                self.stack.push(VMData::new_f64(res));
+                self.stack.push(VMData::new_f64(res))?;
             }
             CastToU => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
                 let res = match val.tag {
                     VMData::TAG_CHAR => val.as_char() as u64,
                     VMData::TAG_I64 => val.as_i64() as u64,
                     VMData::TAG_FLOAT => val.as_f64() as u64,
                     VMData::TAG_U64 => val.as_u64(),
                     VMData::TAG_BOOL => val.as_bool() as u64,
-                    _ => unimplemented!("cast_to_uint isn't implement for tag: {}", val.tag),
+                    _ => {
+                        return Err(VMErrorKind::InvalidCast {
+                            to: VMData::TAG_U64,
+                            found: val.tag,
+                        })
+                    }
                 };
-                self.stack.push(VMData::new_u64(res));
+                self.stack.push(VMData::new_u64(res))?;
             }
             CastToChar => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
+                let err = VMErrorKind::InvalidCast {
+                    to: VMData::TAG_CHAR,
+                    found: val.tag,
+                };
                 let res = match val.tag {
                     VMData::TAG_CHAR => val.as_char(),
                     VMData::TAG_I64 => val.as_i64() as u8 as char,
-                    VMData::TAG_FLOAT => char::from_u32(val.as_f64() as u32).unwrap(),
-                    VMData::TAG_U64 => char::from_u32(val.as_u64() as u32).unwrap(),
+                    VMData::TAG_FLOAT => char::from_u32(val.as_f64() as u32).ok_or(err)?,
+                    VMData::TAG_U64 => char::from_u32(val.as_u64() as u32).ok_or(err)?,
                     VMData::TAG_BOOL => val.as_bool() as u8 as char,
-                    _ => unimplemented!(
-                        "cast_to_int isn't implement for tag: {} [{}]",
-                        val.tag,
-                        self.pc
-                    ),
+                    _ => return Err(err),
                 };
-                self.stack.push(VMData::new_char(res));
+                self.stack.push(VMData::new_char(res))?;
             }
             CastToBool => {
-                let val = self.stack.pop().expect("Stack Underflow");
+                let val = self.stack.pop()?;
                 let res = match val.tag {
                     VMData::TAG_CHAR => val.as_char() as i64,
                     VMData::TAG_I64 => val.as_i64(),
                     VMData::TAG_FLOAT => val.as_f64() as i64,
                     VMData::TAG_U64 => val.as_u64() as i64,
                     VMData::TAG_BOOL => val.as_bool() as i64,
-                    _ => unimplemented!("cast_to_int isn't implement for tag: {}", val.tag),
+                    _ => {
+                        return Err(VMErrorKind::InvalidCast {
+                            to: VMData::TAG_BOOL,
+                            found: val.tag,
+                        })
+                    }
                 };
-                self.stack.push(VMData::new_i64(res));
+                self.stack.push(VMData::new_i64(res))?;
             }
             Read => {
                 let mut input = String::new();
                 std::io::stdin()
                     .read_line(&mut input)
-                    .expect("Failed to read input");
+                    .map_err(|e| VMErrorKind::InvalidInput(e.to_string()))?;
                 let val = String::from(input.trim());
-                match self.object_map.put(val.into()) {
-                    Ok(i) => {
-                        self.stack.push(VMData::new_string(i));
-                    }
-                    Err(o) => {
-                        panic!("Memory full, can't insert: [{:?}]", o);
-                    }
-                }
+                let ptr = self.alloc::<OBSERVED>(val.into())?;
+                self.stack.push(VMData::new_string(ptr))?;
             }
             ReadI => {
                 let mut input = String::new();
                 std::io::stdin()
                     .read_line(&mut input)
-                    .expect("Failed to read input");
-                let val = match String::from(input.trim()).parse::<i64>() {
-                    Ok(i) => i,
-                    Err(e) => {
-                        panic!("{}", e);
-                    }
-                };
-                self.stack.push(VMData::new_i64(val));
+                    .map_err(|e| VMErrorKind::InvalidInput(e.to_string()))?;
+                let val = input
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|e| VMErrorKind::InvalidInput(e.to_string()))?;
+                self.stack.push(VMData::new_i64(val))?;
             }
             SetStruct(u) => {
-                let ptr = self.stack.pop().expect("Stack underflow").as_object();
-                let val = self.stack.pop().expect("Stack underflow");
-                self.object_map.get_mut(ptr).structure_mut().fields[*u] = val;
+                let ptr = self.stack.pop()?.try_object()?;
+                let val = self.stack.pop()?;
+                let field = self
+                    .structure_mut(ptr)?
+                    .fields
+                    .get_mut(*u)
+                    .ok_or(VMErrorKind::FieldOutOfBounds { ptr, field: *u })?;
+                *field = val;
             }
             GetStruct(u) => {
-                let ptr = self.stack.pop().expect("Stack underflow").as_object();
-                let field = self.object_map.get(ptr).structure().fields[*u];
-                self.stack.push(field);
+                let ptr = self.stack.pop()?.try_object()?;
+                let field = *self
+                    .structure_mut(ptr)?
+                    .fields
+                    .get(*u)
+                    .ok_or(VMErrorKind::FieldOutOfBounds { ptr, field: *u })?;
+                self.stack.push(field)?;
             }
             CreateStruct(u) => {
                 let s = Structure {
                     fields: vec![VMData::new_unit(); *u],
                 };
-                match self.object_map.put(s.into()) {
-                    Ok(ptr) => {
-                        self.stack.push(VMData::new_object(257, ptr));
-                    }
-                    Err(o) => {
-                        panic!("Can't add :[{:?}] in the memory", o);
-                    }
-                }
+                let ptr = self.alloc::<OBSERVED>(s.into())?;
+                self.stack.push(VMData::new_object(257, ptr))?;
+            }
+            CreateString => {
+                let ptr = self.alloc::<OBSERVED>(String::new().into())?;
+                self.stack.push(VMData::new_string(ptr))?;
             }
-            CreateString => match self.object_map.put(String::new().into()) {
-                Ok(ptr) => {
-                    self.stack.push(VMData::new_string(ptr));
-                }
-                Err(o) => {
-                    panic!("Can't add :[{:?}] in the memory", o);
-                }
-            },
             StrLen => {
-                let ptr = self.stack.pop().expect("Stack underflow").as_object();
-                let len = self.object_map.get(ptr).string().len();
-                self.stack.push(VMData::new_i64(len as i64));
+                let ptr = self.stack.pop()?.try_object()?;
+                let len = self.string_mut(ptr)?.len();
+                self.stack.push(VMData::new_i64(len as i64))?;
             }
             WriteCharToString => {
-                let ptr = self.stack.pop().expect("Stack underflow").as_object();
-                let ch = self.stack.pop().expect("Stack underflow").as_char();
-                self.object_map.get_mut(ptr).string_mut().push(ch);
+                let ptr = self.stack.pop()?.try_object()?;
+                let ch = self.stack.pop()?.try_char()?;
+                self.string_mut(ptr)?.push(ch);
             }
             ReadCharFromString => {
-                let ptr = self.stack.pop().expect("Stack underflow").as_object();
-                let i = self.stack.pop().expect("Stack underflow").as_u64();
-                let ch = match self.object_map.get(ptr).string().chars().nth(i as usize) {
-                    Some(c) => c,
-                    None => {
-                        panic!("Index out of bound for string: {}[{}]", ptr, i);
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let ch = self.string_mut(ptr)?.chars().nth(i).ok_or(
+                    VMErrorKind::StringIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    },
+                )?;
+                self.stack.push(VMData::new_char(ch))?;
+            }
+            StrConcat => {
+                let b = self.stack.pop()?.try_object()?;
+                let a = self.stack.pop()?.try_object()?;
+                let s = format!("{}{}", self.string(a)?, self.string(b)?);
+                self.push_string::<OBSERVED>(s)?;
+            }
+            StrSlice => {
+                let end = self.pop_index()?;
+                let start = self.pop_index()?;
+                let ptr = self.stack.pop()?.try_object()?;
+                let s = char_slice(self.string(ptr)?, start, end)
+                    .ok_or(VMErrorKind::StringIndexOutOfBounds {
+                        ptr,
+                        index: if start > end { start } else { end } as u64,
+                    })?
+                    .to_owned();
+                self.push_string::<OBSERVED>(s)?;
+            }
+            StrFind => {
+                let needle = self.stack.pop()?.try_object()?;
+                let haystack = self.stack.pop()?.try_object()?;
+                let s = self.string(haystack)?;
+                let i = match s.find(self.string(needle)?.as_str()) {
+                    Some(byte) => s[..byte].chars().count() as i64,
+                    None => -1,
+                };
+                self.stack.push(VMData::new_i64(i))?;
+            }
+            StrUpper => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let s = self.string(ptr)?.to_uppercase();
+                self.push_string::<OBSERVED>(s)?;
+            }
+            StrLower => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let s = self.string(ptr)?.to_lowercase();
+                self.push_string::<OBSERVED>(s)?;
+            }
+            ParseInt => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let s = self.string(ptr)?;
+                let val = s
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| VMErrorKind::InvalidNumber(s.clone()))?;
+                self.stack.push(VMData::new_i64(val))?;
+            }
+            ParseFloat => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let s = self.string(ptr)?;
+                let val = s
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| VMErrorKind::InvalidNumber(s.clone()))?;
+                self.stack.push(VMData::new_f64(val))?;
+            }
+            ToStr => {
+                let val = self.stack.pop()?;
+                let s = match val.tag {
+                    VMData::TAG_I64
+                    | VMData::TAG_U64
+                    | VMData::TAG_FLOAT
+                    | VMData::TAG_CHAR
+                    | VMData::TAG_BOOL => val.to_string(),
+                    _ => {
+                        return Err(VMErrorKind::InvalidCast {
+                            to: VMData::TAG_STR,
+                            found: val.tag,
+                        })
                     }
                 };
-                self.stack.push(VMData::new_char(ch));
+                self.push_string::<OBSERVED>(s)?;
+            }
+            CreateVec(tag) => {
+                let ptr = self.alloc::<OBSERVED>(Vector::new(*tag).into())?;
+                self.stack.push(VMData::new_object(257, ptr))?;
+            }
+            VecPush => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let val = self.stack.pop()?;
+                let vec = self.vector_mut(ptr)?;
+                vec_check(vec, &val)?;
+                vec.vec.push(val);
+            }
+            VecPop => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let val = self
+                    .vector_mut(ptr)?
+                    .vec
+                    .pop()
+                    .ok_or(VMErrorKind::VectorIndexOutOfBounds { ptr, index: 0 })?;
+                self.stack.push(val)?;
+            }
+            VecGet => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let val = *self.vector_mut(ptr)?.vec.get(i).ok_or(
+                    VMErrorKind::VectorIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    },
+                )?;
+                self.stack.push(val)?;
+            }
+            VecSet => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let val = self.stack.pop()?;
+                let vec = self.vector_mut(ptr)?;
+                vec_check(vec, &val)?;
+                let slot = vec
+                    .vec
+                    .get_mut(i)
+                    .ok_or(VMErrorKind::VectorIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    })?;
+                *slot = val;
+            }
+            VecLen => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let len = self.vector_mut(ptr)?.vec.len();
+                self.stack.push(VMData::new_i64(len as i64))?;
+            }
+            VecInsert => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let val = self.stack.pop()?;
+                let vec = self.vector_mut(ptr)?;
+                vec_check(vec, &val)?;
+                if i > vec.vec.len() {
+                    return Err(VMErrorKind::VectorIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    });
+                }
+                vec.vec.insert(i, val);
+            }
+            VecRemove => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let vec = self.vector_mut(ptr)?;
+                if i >= vec.vec.len() {
+                    return Err(VMErrorKind::VectorIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    });
+                }
+                let val = vec.vec.remove(i);
+                self.stack.push(val)?;
+            }
+            CreateMap => {
+                let ptr = self.alloc::<OBSERVED>(Map::new().into())?;
+                self.stack.push(VMData::new_object(257, ptr))?;
+            }
+            MapGet => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let key = self.stack.pop()?;
+                let key = self.map_key(&key)?;
+                let val = self
+                    .map_mut(ptr)?
+                    .get(&key)
+                    .ok_or(VMErrorKind::KeyNotFound { ptr, key })?;
+                self.stack.push(val)?;
+            }
+            MapSet => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let data = self.stack.pop()?;
+                let key = self.map_key(&data)?;
+                let val = self.stack.pop()?;
+                self.map_mut(ptr)?.insert(key, data, val);
+            }
+            MapHas => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let key = self.stack.pop()?;
+                let key = self.map_key(&key)?;
+                let has = self.map_mut(ptr)?.get(&key).is_some();
+                self.stack.push(VMData::new_bool(has))?;
+            }
+            MapRemove => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let key = self.stack.pop()?;
+                let key = self.map_key(&key)?;
+                let val = self
+                    .map_mut(ptr)?
+                    .remove(&key)
+                    .ok_or(VMErrorKind::KeyNotFound { ptr, key })?;
+                self.stack.push(val)?;
+            }
+            MapLen => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let len = self.map_mut(ptr)?.len();
+                self.stack.push(VMData::new_i64(len as i64))?;
+            }
+            MapKeyAt => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let i = self.pop_index()?;
+                let key = self
+                    .map_mut(ptr)?
+                    .key_at(i)
+                    .ok_or(VMErrorKind::MapIndexOutOfBounds {
+                        ptr,
+                        index: i as u64,
+                    })?;
+                self.stack.push(key)?;
+            }
+            NewClass(u) => {
+                let class = self.instantiate(*u)?;
+                let ptr = self.alloc::<OBSERVED>(class.into())?;
+                self.stack.push(VMData::new_object(257, ptr))?;
+            }
+            GetField(class, field) => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let val = *self
+                    .class_part(ptr, *class)?
+                    .fields
+                    .get(*field)
+                    .ok_or(VMErrorKind::FieldOutOfBounds { ptr, field: *field })?;
+                self.stack.push(val)?;
+            }
+            SetField(class, field) => {
+                let ptr = self.stack.pop()?.try_object()?;
+                let val = self.stack.pop()?;
+                let slot = self
+                    .class_part(ptr, *class)?
+                    .fields
+                    .get_mut(*field)
+                    .ok_or(VMErrorKind::FieldOutOfBounds { ptr, field: *field })?;
+                *slot = val;
+            }
+            CallMethod(method) => {
+                let ptr = self.stack.last()?.try_object()?;
+                let class = self.class_mut(ptr)?.class;
+                let class = self
+                    .classes
+                    .get(class)
+                    .ok_or(VMErrorKind::InvalidClass(class))?;
+                let address = *class.vtable.get(method).ok_or(VMErrorKind::UnknownMethod {
+                    class: class.name,
+                    method: *method,
+                })?;
+                self.frames.push(CallFrame {
+                    ret: self.pc + 1,
+                    entry: address,
+                    bp: self.stack.top,
+                    ..Default::default()
+                });
+                if OBSERVED {
+                    if let Some(observer) = self.observer.as_deref_mut() {
+                        observer.on_call(self.pc, address, self.frames.len());
+                    }
+                }
+                self.pc = address;
+                return Ok(());
             }
             Instruction::Eq => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a == b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                self.stack.push(VMData::new_bool(self.values_eq(&a, &b)?))?;
             }
             Instruction::Neq => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a != b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                self.stack
+                    .push(VMData::new_bool(!self.values_eq(&a, &b)?))?;
             }
             Instruction::Lt => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a < b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                let res = matches!(self.values_cmp(&a, &b)?, Some(Ordering::Less));
+                self.stack.push(VMData::new_bool(res))?;
             }
             Instruction::Gt => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a > b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                let res = matches!(self.values_cmp(&a, &b)?, Some(Ordering::Greater));
+                self.stack.push(VMData::new_bool(res))?;
             }
             Instruction::Lte => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a <= b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                let res = matches!(
+                    self.values_cmp(&a, &b)?,
+                    Some(Ordering::Less | Ordering::Equal)
+                );
+                self.stack.push(VMData::new_bool(res))?;
             }
             Instruction::Gte => {
-                let b = self.stack.pop().expect("Stack underflow");
-                let a = self.stack.pop().expect("Stack underflow");
-                self.stack.push(VMData::new_bool(a >= b));
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                let res = matches!(
+                    self.values_cmp(&a, &b)?,
+                    Some(Ordering::Greater | Ordering::Equal)
+                );
+                self.stack.push(VMData::new_bool(res))?;
             }
             Instruction::And => {
-                let b = self.stack.pop().expect("Stack underflow").as_bool();
-                let a = self.stack.pop().expect("Stack underflow").as_bool();
-                self.stack.push(VMData::new_bool(a && b));
+                let b = self.stack.pop()?.try_bool()?;
+                let a = self.stack.pop()?.try_bool()?;
+                self.stack.push(VMData::new_bool(a && b))?;
             }
             Instruction::Or => {
-                let b = self.stack.pop().expect("Stack underflow").as_bool();
-                let a = self.stack.pop().expect("Stack underflow").as_bool();
-                self.stack.push(VMData::new_bool(a || b));
+                let b = self.stack.pop()?.try_bool()?;
+                let a = self.stack.pop()?.try_bool()?;
+                self.stack.push(VMData::new_bool(a || b))?;
             }
             Instruction::Not => {
-                let value = self.stack.pop().expect("Stack underflow").as_bool();
-                self.stack.push(VMData::new_bool(!value));
+                let value = self.stack.pop()?.try_bool()?;
+                self.stack.push(VMData::new_bool(!value))?;
             }
             PrintChar => {
-                let value = self.stack.pop().expect("Stack Underflow").as_char();
+                let value = self.stack.pop()?.try_char()?;
                 print!("{}", value);
             }
             Nop => {}
-            _ => unimplemented!(),
+            HLT => {}
         }
         self.pc += 1;
+        Ok(())
+    }
+
+    /// Pop the condition of a conditional jump, anything that isn't a bool, an int or an uint is rejected
+    #[inline(always)]
+    fn pop_condition(&mut self) -> Result<bool, VMErrorKind> {
+        let val = self.stack.pop()?;
+        match val.tag {
+            VMData::TAG_BOOL => Ok(val.as_bool()),
+            VMData::TAG_I64 => Ok(val.as_i64() != 0),
+            VMData::TAG_U64 => Ok(val.as_u64() != 0),
+            _ => Err(VMErrorKind::TypeMismatch {
+                expected: VMData::TAG_BOOL,
+                found: val.tag,
+            }),
+        }
+    }
+
+    /// Overflow mode of `div_i`, `neg_i` or `abs_i`, the mode of the VM without an explicit one
+    #[inline(always)]
+    fn overflow_of(&self, ins: &Instruction) -> Overflow {
+        match *ins {
+            Instruction::DivIWith(mode)
+            | Instruction::NegIWith(mode)
+            | Instruction::AbsIWith(mode) => mode,
+            _ => self.overflow,
+        }
+    }
+
+    /// Operation & overflow mode of an `add`, `sub` or `mul` on ints or uints.
+    /// The ones without an explicit mode use the mode of the VM.
+    #[inline(always)]
+    fn int_op(&self, ins: &Instruction) -> (IntOp, Overflow) {
+        use Instruction::*;
+        match *ins {
+            AddIWith(mode) | AddUWith(mode) => (IntOp::Add, mode),
+            SubIWith(mode) | SubUWith(mode) => (IntOp::Sub, mode),
+            MulIWith(mode) | MulUWith(mode) => (IntOp::Mul, mode),
+            AddI | AddU => (IntOp::Add, self.overflow),
+            SubI | SubU => (IntOp::Sub, self.overflow),
+            _ => (IntOp::Mul, self.overflow),
+        }
+    }
+
+    /// Unwind to the innermost try region & jump to its handler with `val` on top of the stack.
+    /// Without any, `val` is given back as an [`VMErrorKind::Exception`].
+    /// A coroutine without any passes `val` on to its resumer & dies.
+    fn throw<const OBSERVED: bool>(&mut self, val: VMData) -> Result<(), VMErrorKind> {
+        // Each resumed coroutine holds the regions of its resumer
+        let caught = !self.handlers.is_empty()
+            || self
+                .coroutines
+                .iter()
+                .any(|ptr| match self.object_map.try_get(*ptr) {
+                    Ok(Object::Coroutine(c)) => !c.handlers.is_empty(),
+                    _ => false,
+                });
+        if !caught {
+            return Err(VMErrorKind::Exception(val));
+        }
+        while self.handlers.is_empty() {
+            let ptr = self.coroutines.pop().unwrap();
+            self.leave_coroutine(ptr, CoroutineStatus::Dead)?;
+        }
+        let handler = self.handlers.pop().unwrap();
+        if self.stack.top < handler.top {
+            return Err(VMErrorKind::StackUnderflow);
+        }
+        self.frames.truncate(handler.frames);
+        self.stack.top = handler.top;
+        self.stack.push(val)?;
+        if OBSERVED {
+            if let Some(observer) = self.observer.as_deref_mut() {
+                observer.on_throw(self.pc, handler.address, self.frames.len());
+            }
+        }
+        self.pc = handler.address;
+        Ok(())
+    }
+
+    /// Switch to the suspended coroutine at `ptr` with `val` on top of its stack, the current
+    /// code continuing at `ret` once it gives back control
+    fn enter_coroutine(
+        &mut self,
+        ptr: ObjectIndex,
+        val: VMData,
+        ret: usize,
+    ) -> Result<(), VMErrorKind> {
+        let coro = self.coroutine_mut(ptr)?;
+        if coro.status != CoroutineStatus::Suspended {
+            return Err(VMErrorKind::CoroutineNotSuspended {
+                ptr,
+                status: coro.status,
+            });
+        }
+        coro.status = CoroutineStatus::Running;
+        if let Some(resumer) = self.coroutines.last() {
+            self.coroutine_mut(*resumer)?.status = CoroutineStatus::Normal;
+        }
+        self.pc = ret;
+        self.switch(ptr)?;
+        self.coroutines.push(ptr);
+        self.stack.push(val)
+    }
+
+    /// Switch back from the coroutine at `ptr`, which was the innermost one, to its resumer
+    fn leave_coroutine(
+        &mut self,
+        ptr: ObjectIndex,
+        status: CoroutineStatus,
+    ) -> Result<(), VMErrorKind> {
+        self.switch(ptr)?;
+        let coro = self.coroutine_mut(ptr)?;
+        coro.status = status;
+        if status == CoroutineStatus::Dead {
+            coro.stack.top = 1;
+            coro.frames.clear();
+            coro.handlers.clear();
+        }
+        if let Some(resumer) = self.coroutines.last() {
+            self.coroutine_mut(*resumer)?.status = CoroutineStatus::Running;
+        }
+        Ok(())
+    }
+
+    /// Swap the stack, calls, try regions & pc of the VM with the ones kept by a coroutine
+    fn switch(&mut self, ptr: ObjectIndex) -> Result<(), VMErrorKind> {
+        let coro = match self.object_map.try_get_mut(ptr)? {
+            Object::Coroutine(c) => c,
+            _ => return Err(VMErrorKind::InvalidObject(ptr)),
+        };
+        std::mem::swap(&mut self.stack, &mut coro.stack);
+        std::mem::swap(&mut self.frames, &mut coro.frames);
+        std::mem::swap(&mut self.handlers, &mut coro.handlers);
+        std::mem::swap(&mut self.pc, &mut coro.pc);
+        Ok(())
+    }
+
+    /// Pop an int or an uint as raw bits, along with its tag
+    #[inline(always)]
+    fn pop_bits(&mut self) -> Result<(TAG, u64), VMErrorKind> {
+        let val = self.stack.pop()?;
+        Ok((val.tag, int_bits(&val)?))
+    }
+
+    /// Pop an index, it can either be a positive int or an uint
+    #[inline(always)]
+    fn pop_index(&mut self) -> Result<usize, VMErrorKind> {
+        let val = self.stack.pop()?;
+        match val.tag {
+            VMData::TAG_I64 if val.as_i64() >= 0 => Ok(val.as_i64() as usize),
+            VMData::TAG_U64 => Ok(val.as_u64() as usize),
+            _ => Err(VMErrorKind::TypeMismatch {
+                expected: VMData::TAG_U64,
+                found: val.tag,
+            }),
+        }
+    }
+
+    /// Run a full garbage collection, the stack & the constants are used as roots.
+    /// The memory is then compacted if it has become too sparse.
+    ///
+    /// Return the number of freed objects
+    pub fn collect_garbage(&mut self) -> usize {
+        self.gc(&mut [])
+    }
+
+    /// `extra` are roots that aren't reachable from the VM yet, they're relocated if needed
+    fn gc(&mut self, extra: &mut [VMData]) -> usize {
+        // The coroutines held by the VM itself are roots too
+        let mut held: Vec<VMData> = self
+            .held_coroutines()
+            .map(|ptr| VMData::new_object(257, *ptr))
+            .collect();
+        let freed = self.object_map.collect(
+            self.stack
+                .iter()
+                .chain(self.constants.iter())
+                .chain(self.globals.iter())
+                .chain(held.iter())
+                .chain(extra.iter())
+                .copied(),
+        );
+        self.object_map.shrink(
+            self.stack
+                .iter_mut()
+                .chain(self.constants.iter_mut())
+                .chain(self.globals.iter_mut())
+                .chain(held.iter_mut())
+                .chain(extra.iter_mut()),
+        );
+        for (ptr, moved) in self.held_coroutines().zip(held) {
+            *ptr = moved.as_object();
+        }
+        freed
+    }
+
+    /// The resumed, scheduled, the coroutine having its turn & the ones held by the host
+    fn held_coroutines(&mut self) -> impl Iterator<Item = &mut ObjectIndex> {
+        self.coroutines
+            .iter_mut()
+            .chain(self.scheduled.iter_mut())
+            .chain(self.turn.iter_mut().map(|(ptr, _)| ptr))
+            .chain(self.handles.iter_mut().flatten())
+    }
+
+    #[inline(always)]
+    fn alloc<const OBSERVED: bool>(&mut self, obj: Object) -> Result<ObjectIndex, VMErrorKind> {
+        let ptr = self
+            .object_map
+            .put(obj)
+            .map_err(|_| VMErrorKind::OutOfMemory)?;
+        let ptr = if self.object_map.should_collect() {
+            // The new object isn't on the stack yet, so it's a root on its own
+            let mut new = [VMData::new_object(257, ptr)];
+            self.gc(&mut new);
+            new[0].as_object()
+        } else {
+            ptr
+        };
+        if OBSERVED {
+            if let Some(observer) = self.observer.as_deref_mut() {
+                observer.on_alloc(ptr, self.object_map.get(ptr));
+            }
+        }
+        Ok(ptr)
+    }
+
+    fn structure_mut(&mut self, ptr: ObjectIndex) -> Result<&mut Structure, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::Structure(s) => Ok(s),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    fn string(&self, ptr: ObjectIndex) -> Result<&String, VMErrorKind> {
+        match self.object_map.try_get(ptr)? {
+            Object::String(s) => Ok(s),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    fn push_string<const OBSERVED: bool>(&mut self, s: String) -> Result<(), VMErrorKind> {
+        let ptr = self.alloc::<OBSERVED>(s.into())?;
+        self.stack.push(VMData::new_string(ptr))
+    }
+
+    /// Same as [`VMData::try_eq`] but strings are compared by their contents
+    fn values_eq(&self, a: &VMData, b: &VMData) -> Result<bool, VMErrorKind> {
+        if a.tag == VMData::TAG_STR && b.tag == VMData::TAG_STR {
+            Ok(self.string(a.as_object())? == self.string(b.as_object())?)
+        } else {
+            a.try_eq(b)
+        }
+    }
+
+    /// Same as [`VMData::try_partial_cmp`] but strings are ordered by their contents
+    fn values_cmp(&self, a: &VMData, b: &VMData) -> Result<Option<Ordering>, VMErrorKind> {
+        if a.tag == VMData::TAG_STR && b.tag == VMData::TAG_STR {
+            Ok(Some(
+                self.string(a.as_object())?.cmp(self.string(b.as_object())?),
+            ))
+        } else {
+            a.try_partial_cmp(b)
+        }
+    }
+
+    fn string_mut(&mut self, ptr: ObjectIndex) -> Result<&mut String, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::String(s) => Ok(s),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    fn coroutine_mut(&mut self, ptr: ObjectIndex) -> Result<&mut Coroutine, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::Coroutine(c) => Ok(c),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    fn vector_mut(&mut self, ptr: ObjectIndex) -> Result<&mut Vector, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::Vector(v) => Ok(v),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    fn map_mut(&mut self, ptr: ObjectIndex) -> Result<&mut Map, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::Map(m) => Ok(m),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    /// Turn `val` into a map key, strings are copied so the key doesn't change with them
+    fn map_key(&self, val: &VMData) -> Result<MapKey, VMErrorKind> {
+        match val.tag {
+            VMData::TAG_UNIT => Ok(MapKey::Unit),
+            VMData::TAG_I64 => Ok(MapKey::I64(val.as_i64())),
+            VMData::TAG_U64 => Ok(MapKey::U64(val.as_u64())),
+            VMData::TAG_FLOAT => Ok(MapKey::Float(val.as_f64().to_bits())),
+            VMData::TAG_BOOL => Ok(MapKey::Bool(val.as_bool())),
+            VMData::TAG_CHAR => Ok(MapKey::Char(val.as_char())),
+            VMData::TAG_STR => Ok(MapKey::String(self.string(val.as_object())?.clone())),
+            _ => Err(VMErrorKind::InvalidMapKey(val.tag)),
+        }
+    }
+
+    fn class_mut(&mut self, ptr: ObjectIndex) -> Result<&mut Class, VMErrorKind> {
+        match self.object_map.try_get_mut(ptr)? {
+            Object::Class(c) => Ok(c),
+            _ => Err(VMErrorKind::InvalidObject(ptr)),
+        }
+    }
+
+    /// The fields declared by `class` in the instance at `ptr`
+    fn class_part(&mut self, ptr: ObjectIndex, class: usize) -> Result<&mut Class, VMErrorKind> {
+        let name = self
+            .classes
+            .get(class)
+            .ok_or(VMErrorKind::InvalidClass(class))?
+            .name;
+        self.class_mut(ptr)?
+            .part_mut(class)
+            .ok_or(VMErrorKind::NotAnInstance { ptr, class: name })
+    }
+
+    /// A new instance of `class` with every field set to unit
+    fn instantiate(&self, class: usize) -> Result<Class, VMErrorKind> {
+        let desc = self
+            .classes
+            .get(class)
+            .ok_or(VMErrorKind::InvalidClass(class))?;
+        let prototype = match desc.parent {
+            Some(parent) => Some(Box::new(self.instantiate(parent)?)),
+            None => None,
+        };
+        Ok(Class {
+            class,
+            prototype,
+            fields: vec![VMData::new_unit(); desc.fields],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum IntOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+macro_rules! arith {
+    ($name:ident, $ty:ty) => {
+        #[inline(always)]
+        fn $name(op: IntOp, mode: Overflow, a: $ty, b: $ty) -> Result<$ty, VMErrorKind> {
+            match (op, mode) {
+                (IntOp::Add, Overflow::Checked) => {
+                    a.checked_add(b).ok_or(VMErrorKind::IntegerOverflow)
+                }
+                (IntOp::Sub, Overflow::Checked) => {
+                    a.checked_sub(b).ok_or(VMErrorKind::IntegerOverflow)
+                }
+                (IntOp::Mul, Overflow::Checked) => {
+                    a.checked_mul(b).ok_or(VMErrorKind::IntegerOverflow)
+                }
+                (IntOp::Add, Overflow::Wrapping) => Ok(a.wrapping_add(b)),
+                (IntOp::Sub, Overflow::Wrapping) => Ok(a.wrapping_sub(b)),
+                (IntOp::Mul, Overflow::Wrapping) => Ok(a.wrapping_mul(b)),
+                (IntOp::Add, Overflow::Saturating) => Ok(a.saturating_add(b)),
+                (IntOp::Sub, Overflow::Saturating) => Ok(a.saturating_sub(b)),
+                (IntOp::Mul, Overflow::Saturating) => Ok(a.saturating_mul(b)),
+            }
+        }
+    };
+}
+arith!(arith_i64, i64);
+arith!(arith_u64, u64);
+
+/// Bits of an int or an uint
+#[inline(always)]
+fn int_bits(val: &VMData) -> Result<u64, VMErrorKind> {
+    match val.tag {
+        VMData::TAG_I64 => Ok(val.as_i64() as u64),
+        VMData::TAG_U64 => Ok(val.as_u64()),
+        _ => Err(VMErrorKind::TypeMismatch {
+            expected: VMData::TAG_I64,
+            found: val.tag,
+        }),
+    }
+}
+
+/// Inverse of [`int_bits`]
+#[inline(always)]
+fn from_int_bits(tag: TAG, bits: u64) -> VMData {
+    if tag == VMData::TAG_I64 {
+        VMData::new_i64(bits as i64)
+    } else {
+        VMData::new_u64(bits)
+    }
+}
+
+/// The chars in `[start, end)` of `s`
+fn char_slice(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end {
+        return None;
+    }
+    let mut bounds = s
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()));
+    let from = bounds.nth(start)?;
+    let to = if start == end {
+        from
+    } else {
+        bounds.nth(end - start - 1)?
+    };
+    Some(&s[from..to])
+}
+
+/// Make sure `val` has the tag of the values stored in `vec`
+#[inline(always)]
+fn vec_check(vec: &Vector, val: &VMData) -> Result<(), VMErrorKind> {
+    if vec.accepts(val) {
+        Ok(())
+    } else {
+        Err(VMErrorKind::TypeMismatch {
+            expected: vec.tag,
+            found: val.tag,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::{assemble, EXAMPLES};
+
+    /// A VM with `source` linked & loaded
+    fn load(source: &str) -> VM {
+        let program = assemble(source);
+        let mut vm = VM::new(16, vec![]);
+        let ins = vm.link(&program).unwrap();
+        vm.load(ins);
+        vm
+    }
+
+    /// Count from 0 to 10 in 61 instructions
+    const COUNT: &str = "
+.section
+    @int n 10
+.code
+main:
+    push_i $0
+again:
+    push_i $1
+    add_i
+    dup
+    load_const #n
+    lt
+    jmp_nz &again
+    hlt
+";
+
+    #[test]
+    fn out_of_fuel_keeps_the_state() {
+        let mut vm = load(COUNT);
+        vm.set_fuel(Some(20));
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(0));
+        assert!(!vm.is_halted());
+        vm.set_fuel(Some(40));
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::OutOfFuel);
+        // `hlt` doesn't use any fuel
+        vm.set_fuel(Some(1));
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.fuel(), Some(0));
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 10);
+    }
+
+    #[test]
+    fn raised_interrupt_stops_the_execution() {
+        // Long enough for the flag to be checked
+        let mut vm = load(&COUNT.replace("@int n 10", "@int n 1000"));
+        vm.interrupt_handle().store(true, atomic::Ordering::Relaxed);
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Interrupted);
+        // The flag is lowered once it stopped the VM
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 1000);
+    }
+
+    #[test]
+    fn run_for_pauses_until_halted() {
+        let mut vm = load(COUNT);
+        assert_eq!(vm.run_for(25).unwrap(), ExecutionOutcome::Paused);
+        assert_eq!(vm.run_for(25).unwrap(), ExecutionOutcome::Paused);
+        assert_eq!(vm.pc(), vm.label_address("again").unwrap() + 1);
+        assert_eq!(vm.run_for(25).unwrap(), ExecutionOutcome::Halted);
+        assert!(vm.is_halted());
+        // The results are kept once halted
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 10);
+        assert_eq!(vm.fuel(), None);
+    }
+
+    #[test]
+    fn run_for_uses_the_fuel() {
+        let mut vm = load(COUNT);
+        vm.set_fuel(Some(30));
+        assert_eq!(vm.run_for(20).unwrap(), ExecutionOutcome::Paused);
+        assert_eq!(vm.fuel(), Some(10));
+        assert_eq!(vm.run_for(20).unwrap(), ExecutionOutcome::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(0));
+    }
+
+    #[test]
+    fn reset_goes_back_to_the_start() {
+        let mut vm = load(COUNT);
+        vm.run_for(30).unwrap();
+        vm.reset();
+        assert_eq!(vm.pc(), 0);
+        assert_eq!(vm.stack.top, 1);
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 10);
+        // `run` starts over instead of staying halted
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.iter().count(), 2);
+    }
+
+    #[test]
+    fn blocked_recv_uses_no_fuel() {
+        let mut vm = load(
+            "
+.section
+.code
+main:
+    push_i $1
+    recv $0
+    add_i
+    hlt
+",
+        );
+        let channel = Channel::new();
+        vm.add_channel(channel.clone());
+        vm.set_fuel(Some(10));
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Blocked);
+        assert_eq!(vm.fuel(), Some(9));
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Blocked);
+        assert_eq!(vm.fuel(), Some(9));
+        assert!(vm.is_blocked());
+        channel.send(Message::new(&vm.object_map, VMData::new_i64(2)).unwrap());
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.fuel(), Some(7));
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 3);
+    }
+
+    /// Run `source` until it halts, giving back the VM to read the results off its stack
+    fn run(source: &str) -> VM {
+        let mut vm = load(source);
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Halted);
+        vm
+    }
+
+    /// Contents of the string on top of the stack
+    fn top_string(vm: &VM) -> String {
+        let ptr = vm.stack.last().unwrap().try_object().unwrap();
+        vm.string(ptr).unwrap().clone()
+    }
+
+    const STRINGS: &str = "
+.section
+    @string greeting \"Hello, \"
+    @string name \"World\"
+.code
+main:
+";
+
+    #[test]
+    fn string_literals_are_allocated_when_linked() {
+        let vm = run(&format!("{STRINGS}    load_const #name\n    hlt"));
+        assert_eq!(top_string(&vm), "World");
+    }
+
+    #[test]
+    fn allocations_trigger_collections() {
+        let mut vm = load(&format!(
+            "{STRINGS}    push_i $3000
+again:
+    load_const #greeting
+    load_const #name
+    str_concat
+    pop
+    push_i $1
+    sub_i
+    dup
+    push_i $0
+    swap
+    lt
+    jmp_nz &again
+    load_const #greeting
+    load_const #name
+    str_concat
+    hlt"
+        ));
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        // The literals & the last concatenation are still reachable
+        assert_eq!(top_string(&vm), "Hello, World");
+        // 3000 strings were created but never more than the threshold at once
+        assert!(vm.object_map.memory_pressure() <= 1024);
+    }
+
+    #[test]
+    fn collection_relocates_the_roots() {
+        let mut vm = load(&format!("{STRINGS}    hlt"));
+        garbage(&mut vm);
+        let on_stack = vm.object_map.put("on stack".to_owned().into()).unwrap();
+        vm.stack.push(VMData::new_string(on_stack)).unwrap();
+        let global = vm.object_map.put("global".to_owned().into()).unwrap();
+        vm.add_global("g", VMData::new_string(global));
+        assert_eq!(vm.collect_garbage(), 64);
+        assert_ne!(vm.stack.last().unwrap().as_object(), on_stack);
+        assert_eq!(top_string(&vm), "on stack");
+        let moved = vm.get_global("g").unwrap().as_object();
+        assert_ne!(moved, global);
+        assert_eq!(vm.string(moved).unwrap(), "global");
+        // The literals were allocated first, so they stay in place
+        assert_eq!(vm.string(vm.constants()[1].as_object()).unwrap(), "World");
+    }
+
+    #[test]
+    fn enter_without_its_arguments_underflows() {
+        let mut vm = load(".section\n.code\nmain:\n    call &f\n    hlt\nf:\n    .args $1\n    load_arg $0\n    ret\n");
+        let e = vm.run().unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::StackUnderflow));
+        assert_eq!(e.pc, 2);
+        // With its argument, the result replaces it
+        let vm = run(".section\n.code\nmain:\n    push_i $7\n    call &f\n    hlt\nf:\n    .args $1\n    load_arg $0\n    ret\n");
+        assert_eq!(vm.stack.top, 2);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 7);
+    }
+
+    #[test]
+    fn str_concat_slice_upper() {
+        let vm = run(&format!(
+            "{STRINGS}
+    load_const #greeting
+    load_const #name
+    str_concat
+    push_i $5
+    push_i $12
+    str_slice
+    str_upper
+    hlt"
+        ));
+        assert_eq!(top_string(&vm), ", WORLD");
+    }
+
+    #[test]
+    fn str_find_gives_the_position() {
+        let vm = run(&format!(
+            "{STRINGS}
+    load_const #name
+    load_const #name
+    push_i $2
+    push_i $3
+    str_slice
+    str_find
+    load_const #name
+    load_const #greeting
+    str_find
+    hlt"
+        ));
+        assert_eq!(vm.stack.get(1).unwrap().as_i64(), 2);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), -1);
+    }
+
+    #[test]
+    fn strings_are_compared_by_contents() {
+        let vm = run(&format!(
+            "{STRINGS}
+    load_const #greeting
+    load_const #name
+    str_concat
+    load_const #greeting
+    load_const #name
+    str_concat
+    eq
+    load_const #greeting
+    load_const #name
+    lt
+    hlt"
+        ));
+        assert!(vm.stack.get(1).unwrap().as_bool());
+        assert!(vm.stack.get(2).unwrap().as_bool());
+    }
+
+    #[test]
+    fn numbers_to_and_from_strings() {
+        let vm = run("
+.section
+    @string number \" 42\"
+    @string pi \"3.25\"
+.code
+main:
+    load_const #number
+    parse_int
+    load_const #pi
+    parse_float
+    cast_to_int
+    add_i
+    to_str
+    hlt
+");
+        assert_eq!(top_string(&vm), "45");
+    }
+
+    #[test]
+    fn invalid_number_fails() {
+        let mut vm = load(&format!(
+            "{STRINGS}    load_const #name\n    parse_int\n    hlt"
+        ));
+        let e = vm.run().unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::InvalidNumber(s) if s == "World"));
+    }
+
+    #[test]
+    fn str_slice_out_of_bounds_fails() {
+        let mut vm = load(&format!(
+            "{STRINGS}    load_const #name\n    push_i $2\n    push_i $6\n    str_slice\n    hlt"
+        ));
+        let e = vm.run().unwrap_err();
+        assert!(matches!(
+            e.kind,
+            VMErrorKind::StringIndexOutOfBounds { index: 6, .. }
+        ));
+    }
+
+    #[test]
+    fn error_backtrace_has_functions_and_lines() {
+        let mut vm = load(
+            "
+.section
+.code
+main:
+    push_i $1
+    call &f
+    hlt
+f:
+    push_i $0
+    div_i
+    ret
+",
+        );
+        let e = vm.run().unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::DivisionByZero));
+        let trace: Vec<(usize, Option<&str>, Option<usize>)> = e
+            .backtrace
+            .iter()
+            .map(|f| {
+                (
+                    f.pc,
+                    f.function.as_deref(),
+                    f.location.as_ref().map(|l| l.line),
+                )
+            })
+            .collect();
+        assert_eq!(
+            trace,
+            [(4, Some("f"), Some(10)), (1, Some("main"), Some(6))]
+        );
+    }
+
+    /// Run `ins` on `args` with the VM in `vm_mode`
+    fn arith(vm_mode: Overflow, args: &[i64], ins: &str) -> Result<i64, String> {
+        let pushes: String = args.iter().map(|a| format!("    push_i ${a}\n")).collect();
+        let mut vm = load(&format!(
+            ".section\n.code\nmain:\n{pushes}    {ins}\n    hlt"
+        ));
+        vm.set_overflow(vm_mode);
+        vm.run().map_err(|e| e.kind.to_string())?;
+        Ok(vm.stack.last().unwrap().as_i64())
+    }
+
+    fn overflow() -> Result<i64, String> {
+        Err("integer overflow".to_owned())
+    }
+
+    #[test]
+    fn overflow_follows_the_vm_mode() {
+        use Overflow::*;
+        let min = i64::MIN;
+        assert_eq!(arith(Wrapping, &[i64::MAX, 1], "add_i"), Ok(min));
+        assert_eq!(arith(Saturating, &[i64::MAX, 1], "add_i"), Ok(i64::MAX));
+        assert_eq!(arith(Checked, &[i64::MAX, 1], "add_i"), overflow());
+        assert_eq!(arith(Wrapping, &[min, -1], "div_i"), Ok(min));
+        assert_eq!(arith(Saturating, &[min, -1], "div_i"), Ok(i64::MAX));
+        assert_eq!(arith(Checked, &[min, -1], "div_i"), overflow());
+        assert_eq!(arith(Wrapping, &[min], "neg_i"), Ok(min));
+        assert_eq!(arith(Saturating, &[min], "abs_i"), Ok(i64::MAX));
+        assert_eq!(arith(Checked, &[min], "abs_i"), overflow());
+    }
+
+    #[test]
+    fn explicit_overflow_mode_wins() {
+        use Overflow::*;
+        let min = i64::MIN;
+        assert_eq!(arith(Wrapping, &[i64::MAX, 2], "mul_i_checked"), overflow());
+        assert_eq!(arith(Checked, &[min, 1], "sub_i_wrapping"), Ok(i64::MAX));
+        assert_eq!(arith(Checked, &[min, -1], "div_i_wrapping"), Ok(min));
+        assert_eq!(
+            arith(Wrapping, &[min, -1], "div_i_saturating"),
+            Ok(i64::MAX)
+        );
+        assert_eq!(arith(Wrapping, &[min, -1], "div_i_checked"), overflow());
+        assert_eq!(arith(Checked, &[min], "neg_i_saturating"), Ok(i64::MAX));
+        assert_eq!(arith(Saturating, &[min], "neg_i_checked"), overflow());
+        assert_eq!(arith(Checked, &[min], "abs_i_wrapping"), Ok(min));
+        assert_eq!(arith(Checked, &[-5], "abs_i_checked"), Ok(5));
+        assert_eq!(
+            arith(Saturating, &[1, 0], "div_i_saturating"),
+            Err("division by zero".to_owned())
+        );
+    }
+
+    /// `task` adds 1 to `count` twice, yielding in between
+    const TASKS: &str = "
+.section
+    @global int count 0
+    @global int co 0
+.code
+main:
+    coro_create &task
+    store_global #co
+    hlt
+task:
+    .args $1
+    load_global #count
+    push_i $1
+    add_i
+    store_global #count
+    push_i $0
+    yield
+    pop
+    load_global #count
+    push_i $1
+    add_i
+    store_global #count
+    push_i $0
+    ret
+";
+
+    /// Unreachable objects allocated before the next ones, so the collector moves those
+    fn garbage(vm: &mut VM) {
+        for i in 0..64 {
+            vm.object_map.put(i.to_string().into()).unwrap();
+        }
+    }
+
+    #[test]
+    fn spawned_coroutine_survives_compaction() {
+        let mut vm = load(TASKS);
+        garbage(&mut vm);
+        let id = vm.spawn(vm.label_address("task").unwrap()).unwrap();
+        let before = vm.handles[0].unwrap();
+        assert_eq!(vm.collect_garbage(), 64);
+        assert_ne!(vm.handles[0].unwrap(), before);
+        assert_eq!(vm.coroutine(id).unwrap().status, CoroutineStatus::Suspended);
+        // It's still scheduled once from `spawn`, then again
+        vm.schedule(id).unwrap();
+        assert_eq!(vm.run_scheduler().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.get_global("count").unwrap().as_i64(), 2);
+        assert_eq!(vm.coroutine(id).unwrap().status, CoroutineStatus::Dead);
+    }
+
+    /// Count the runs that finished
+    struct Finishes(usize);
+
+    impl Observer for Finishes {
+        fn on_finish(&mut self) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn halted_coroutine_is_dead() {
+        let mut vm = load(&format!(
+            "{TASKS}halter:
+    .args $1
+    load_global #count
+    push_i $10
+    add_i
+    store_global #count
+    hlt
+"
+        ));
+        vm.set_observer(Finishes(0));
+        let halter = vm.spawn(vm.label_address("halter").unwrap()).unwrap();
+        let task = vm.spawn(vm.label_address("task").unwrap()).unwrap();
+        assert_eq!(vm.run_scheduler().unwrap(), ExecutionOutcome::Halted);
+        // The task still got its turns after it
+        assert_eq!(vm.get_global("count").unwrap().as_i64(), 12);
+        assert_eq!(vm.coroutine(halter).unwrap().status, CoroutineStatus::Dead);
+        assert_eq!(vm.coroutine(task).unwrap().status, CoroutineStatus::Dead);
+        assert_eq!(vm.pc(), 0);
+        // Only the end of the whole run is observed
+        assert_eq!(vm.observer_mut::<Finishes>().unwrap().0, 1);
+
+        // Nothing is left to run, a dead coroutine is skipped
+        vm.schedule(halter).unwrap();
+        assert_eq!(vm.run_scheduler().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.get_global("count").unwrap().as_i64(), 12);
+    }
+
+    #[test]
+    fn held_coroutine_is_a_root() {
+        let mut vm = load(TASKS);
+        garbage(&mut vm);
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Halted);
+        let id = vm.hold(vm.get_global("co").unwrap().as_object()).unwrap();
+        vm.set_global("co", VMData::new_unit()).unwrap();
+        assert_eq!(vm.collect_garbage(), 64);
+        vm.schedule(id).unwrap();
+        assert_eq!(vm.run_scheduler().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.get_global("count").unwrap().as_i64(), 2);
+        // Nothing references it once released
+        vm.release(id).unwrap();
+        assert_eq!(vm.collect_garbage(), 1);
+        assert!(matches!(
+            vm.schedule(id),
+            Err(VMErrorKind::InvalidCoroutine(0))
+        ));
+        assert!(vm.release(id).is_err());
+    }
+
+    /// `Dog` overrides `sound` & inherits `legs` from `Animal`
+    const CLASSES: &str = "
+.section
+    @class Animal
+        field legs
+        method legs &animal_legs
+        method sound &animal_sound
+    @class Dog extends Animal
+        field name
+        method sound &dog_sound
+.code
+main:
+    .locals $1
+    new_class #Dog
+    store_local $0
+    push_i $4
+    load_local $0
+    set_field #Dog.legs
+    push_i $7
+    load_local $0
+    set_field #Dog.name
+";
+
+    const CLASS_METHODS: &str = "
+animal_legs:
+    .args $1
+    load_arg $0
+    get_field #Animal.legs
+    ret
+animal_sound:
+    .args $1
+    push_i $1
+    ret
+dog_sound:
+    .args $1
+    push_i $2
+    ret
+";
+
+    fn run_classes(main: &str) -> Result<VM, VMError> {
+        let mut vm = load(&format!("{CLASSES}{main}\n    hlt\n{CLASS_METHODS}"));
+        vm.resume()?;
+        Ok(vm)
+    }
+
+    #[test]
+    fn fields_are_shared_with_the_parent() {
+        let vm = run_classes(
+            "    load_local $0
+    get_field #Animal.legs
+    load_local $0
+    get_field #Dog.name
+    new_class #Dog
+    get_field #Dog.name",
+        )
+        .unwrap();
+        let top: Vec<_> = vm.stack.iter().skip(2).copied().collect();
+        assert_eq!(top[..2], [VMData::new_i64(4), VMData::new_i64(7)]);
+        // New instances start with unit fields
+        assert!(top[2].is_unit());
+    }
+
+    #[test]
+    fn methods_are_dispatched_by_the_vtable() {
+        let vm = run_classes(
+            "    load_local $0
+    call_method #legs
+    load_local $0
+    call_method #sound
+    new_class #Animal
+    call_method #sound",
+        )
+        .unwrap();
+        let top: Vec<_> = vm.stack.iter().skip(2).map(|d| d.as_i64()).collect();
+        assert_eq!(top, [4, 2, 1]);
+
+        let animal = vm.class("Animal").unwrap();
+        let dog = vm.class("Dog").unwrap();
+        assert_eq!(dog.parent, Some(0));
+        let method = |name: &str| Intern::new(name.to_owned());
+        assert_eq!(dog.vtable[&method("legs")], animal.vtable[&method("legs")]);
+        assert_ne!(
+            dog.vtable[&method("sound")],
+            animal.vtable[&method("sound")]
+        );
+    }
+
+    #[test]
+    fn parent_instance_lacks_child_members() {
+        let e = run_classes("    new_class #Animal\n    get_field #Dog.name").unwrap_err();
+        assert_eq!(e.kind.to_string(), "[@1] isn't an instance of Dog");
+        let e = run_classes("    new_class #Animal\n    call_method #bark").unwrap_err();
+        assert_eq!(e.kind.to_string(), "class Animal has no method bark");
+    }
+
+    fn example(name: &str) -> &'static str {
+        EXAMPLES.iter().find(|(n, _)| *n == name).unwrap().1
+    }
+
+    /// `@double` & `@negate`, registered in another order than they're called
+    fn with_externs() -> VM {
+        let mut vm = VM::new(16, vec![]);
+        vm.add_extern_fn("negate", &[VMData::TAG_I64], |s: VMState| {
+            Ok(VMData::new_i64(-s.stack.pop()?.try_i64()?))
+        });
+        vm.add_extern_fn("double", &[VMData::TAG_I64], |s: VMState| {
+            Ok(VMData::new_i64(s.stack.pop()?.try_i64()? * 2))
+        });
+        vm
+    }
+
+    #[test]
+    fn externs_are_linked_by_name() {
+        let mut vm = VM::new(16, vec![]);
+        vm.add_extern_fn("fib", &[VMData::TAG_I64], |s: VMState| {
+            let (mut a, mut b) = (0, 1);
+            for _ in 0..s.stack.pop()?.try_i64()? {
+                (a, b) = (b, a + b);
+            }
+            Ok(VMData::new_i64(a))
+        });
+        let ins = vm.link(&assemble(example("extern_call"))).unwrap();
+        vm.load(ins);
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 6765);
+
+        let mut vm = with_externs();
+        let program = assemble(".section\n.code\nmain:\n    push_i $3\n    extern_call @double\n    extern_call @negate\n    hlt\n");
+        let ins = vm.link(&program).unwrap();
+        assert_eq!(ins[1], Instruction::ExternCall(1));
+        assert_eq!(ins[2], Instruction::ExternCall(0));
+        vm.load(ins);
+        assert_eq!(vm.resume().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), -6);
+    }
+
+    #[test]
+    fn missing_extern_fails_to_link() {
+        let e = VM::new(16, vec![])
+            .link(&assemble(example("extern_call")))
+            .unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::MissingExtern(name) if name.as_str() == "fib"));
+        assert_eq!(e.pc, 1);
+    }
+
+    #[test]
+    fn extern_arguments_are_type_checked() {
+        let mut vm = with_externs();
+        let program =
+            assemble(".section\n.code\nmain:\n    push_f $1.5\n    extern_call @double\n    hlt\n");
+        let ins = vm.link(&program).unwrap();
+        let e = vm.execute(&ins).unwrap_err();
+        assert!(matches!(
+            e.kind,
+            VMErrorKind::TypeMismatch {
+                expected: VMData::TAG_I64,
+                found: VMData::TAG_FLOAT
+            }
+        ));
+    }
+
+    #[test]
+    fn remainder_has_the_sign_of_the_dividend() {
+        use Overflow::*;
+        assert_eq!(arith(Checked, &[7, 3], "mod_i"), Ok(1));
+        assert_eq!(arith(Checked, &[-7, 3], "mod_i"), Ok(-1));
+        assert_eq!(arith(Checked, &[7, -3], "mod_i"), Ok(1));
+        assert_eq!(arith(Checked, &[-7, -3], "mod_i"), Ok(-1));
+        assert_eq!(arith(Checked, &[i64::MIN, -1], "mod_i"), Ok(0));
+        assert_eq!(
+            arith(Checked, &[7, 0], "mod_i"),
+            Err("division by zero".to_owned())
+        );
+        assert_eq!(arith(Checked, &[-7, 3], "min_i"), Ok(-7));
+        assert_eq!(arith(Checked, &[-7, 3], "max_i"), Ok(3));
+    }
+
+    #[test]
+    fn shifts_by_64_or_more() {
+        use Overflow::*;
+        assert_eq!(arith(Checked, &[1, 63], "shl"), Ok(i64::MIN));
+        assert_eq!(arith(Checked, &[1, 64], "shl"), Ok(0));
+        assert_eq!(arith(Checked, &[-1, 100], "shr"), Ok(0));
+        assert_eq!(arith(Checked, &[-1, 1], "shr"), Ok(i64::MAX));
+        // `sar` fills with the sign, as if it was shifted by 63
+        assert_eq!(arith(Checked, &[-8, 1], "sar"), Ok(-4));
+        assert_eq!(arith(Checked, &[-8, 64], "sar"), Ok(-1));
+        assert_eq!(arith(Checked, &[8, 1000], "sar"), Ok(0));
+        assert_eq!(arith(Checked, &[0b1100, 0b1010], "band"), Ok(0b1000));
+        assert_eq!(arith(Checked, &[0b1100, 0b1010], "bxor"), Ok(0b0110));
+        assert_eq!(arith(Checked, &[0], "bnot"), Ok(-1));
+    }
+
+    #[test]
+    fn negative_shift_fails() {
+        assert_eq!(
+            arith(Overflow::Checked, &[1, -1], "shl"),
+            Err("can't shift by a negative amount (-1)".to_owned())
+        );
+    }
+
+    #[test]
+    fn neg_abs_of_the_minimum() {
+        use Overflow::*;
+        for ins in ["neg_i", "abs_i"] {
+            assert_eq!(arith(Checked, &[i64::MIN], ins), overflow());
+            assert_eq!(arith(Wrapping, &[i64::MIN], ins), Ok(i64::MIN));
+            assert_eq!(arith(Saturating, &[i64::MIN], ins), Ok(i64::MAX));
+        }
+        assert_eq!(arith(Checked, &[-5], "abs_i"), Ok(5));
+        assert_eq!(arith(Checked, &[-5], "neg_i"), Ok(5));
+    }
+
+    fn uncaught(source: &str) -> VMError {
+        let e = load(source).run().unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::Exception(_)), "{}", e);
+        e
+    }
+
+    #[test]
+    fn exception_example_recovers() {
+        let vm = run(example("exception"));
+        assert_eq!(vm.stack.top, 1);
+        assert_eq!(vm.frames().len(), 1);
+        assert!(vm.handlers.is_empty());
+    }
+
+    #[test]
+    fn throw_unwinds_to_the_handler() {
+        let vm = run(".section
+.code
+main:
+    push_i $1
+    try_begin &caught
+    push_i $2
+    call &outer
+    hlt
+caught:
+    hlt
+outer:
+    .locals $1
+    push_i $5
+    call &inner
+    ret
+inner:
+    .args $1
+    push_i $9
+    throw
+");
+        // The values pushed since `try_begin` & the calls are dropped
+        let stack: Vec<_> = vm.stack.iter().skip(1).map(|d| d.as_i64()).collect();
+        assert_eq!(stack, [1, 9]);
+        assert_eq!(vm.frames().len(), 1);
+        assert!(vm.handlers.is_empty());
+        assert_eq!(vm.pc(), 5);
+    }
+
+    #[test]
+    fn nested_handlers_catch_in_turn() {
+        let vm = run(".section
+.code
+main:
+    try_begin &outer_caught
+    try_begin &inner_caught
+    push_i $1
+    throw
+inner_caught:
+    push_i $10
+    add_i
+    throw
+outer_caught:
+    push_i $100
+    add_i
+    hlt
+");
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 111);
+        assert_eq!(vm.stack.top, 2);
+    }
+
+    #[test]
+    fn ended_regions_dont_catch() {
+        let e = uncaught(
+            ".section\n.code\nmain:\n    try_begin &caught\n    try_end\n    push_i $3\n    throw\ncaught:\n    hlt\n",
+        );
+        assert_eq!(e.kind.to_string(), "uncaught exception: 3");
+        assert_eq!(e.pc, 3);
+        // Nor do the ones left open by a returning function
+        let e = uncaught(
+            ".section
+.code
+main:
+    call &f
+    push_i $4
+    throw
+f:
+    try_begin &never
+    ret
+never:
+    hlt
+",
+        );
+        assert_eq!(e.kind.to_string(), "uncaught exception: 4");
+    }
+
+    #[test]
+    fn map_example_runs() {
+        let vm = run(example("map"));
+        assert!(!vm.stack.last().unwrap().as_bool());
+    }
+
+    #[test]
+    fn map_opcodes() {
+        let vm = run(".section
+    @string x \"x\"
+.code
+main:
+    .locals $1
+    create_map
+    store_local $0
+    push_i $1
+    push_f $0.0
+    load_local $0
+    map_set
+    push_i $2
+    push_f $-0.0
+    load_local $0
+    map_set
+    push_i $3
+    load_const #x
+    load_local $0
+    map_set
+    ; the same key, in another string
+    push_i $4
+    load_const #x
+    create_string
+    str_concat
+    load_local $0
+    map_set
+    load_const #x
+    load_local $0
+    map_remove
+    push_f $0.0
+    load_local $0
+    map_remove
+    load_local $0
+    map_len
+    ; -0.0 took the place of 0.0
+    push_i $0
+    load_local $0
+    map_key_at
+    load_local $0
+    map_get
+    load_const #x
+    load_local $0
+    map_has
+    hlt
+");
+        let top: Vec<_> = vm.stack.iter().skip(2).map(|d| d.as_i64()).collect();
+        assert_eq!(top, [4, 1, 1, 2, 0]);
+        assert_eq!(vm.stack.last().unwrap().tag, VMData::TAG_BOOL);
+    }
+
+    #[test]
+    fn map_errors() {
+        let fails = |body: &str| {
+            let source = format!(".section\n.code\nmain:\n{body}    hlt\n");
+            load(&source).run().unwrap_err().kind
+        };
+        let e = fails("    push_i $3\n    create_map\n    map_get\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::KeyNotFound {
+                key: MapKey::I64(3),
+                ..
+            }
+        ));
+        let e = fails("    push_i $3\n    create_map\n    map_remove\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::KeyNotFound {
+                key: MapKey::I64(3),
+                ..
+            }
+        ));
+        let e = fails("    push_i $0\n    create_map\n    map_key_at\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::MapIndexOutOfBounds { index: 0, .. }
+        ));
+        let e = fails("    create_map\n    create_map\n    map_has\n");
+        assert!(matches!(e, VMErrorKind::InvalidMapKey(_)));
+    }
+
+    const GLOBALS: &str = "
+.section
+    @global int total 0
+    @global int limit 0
+.code
+main:
+    load_global #limit
+    push_i $2
+    mul_i
+    store_global #total
+    hlt
+";
+
+    #[test]
+    fn globals_are_linked_by_name() {
+        let mut vm = VM::new(16, vec![]);
+        vm.add_global("limit", VMData::new_i64(5));
+        let ins = vm.link(&assemble(GLOBALS)).unwrap();
+        // The host's global is kept, the other one is declared after it
+        assert_eq!(ins[0], Instruction::LoadGlobal(0));
+        assert_eq!(ins[3], Instruction::StoreGlobal(1));
+        assert_eq!(vm.get_global("limit").unwrap().as_i64(), 5);
+        assert_eq!(vm.get_global("total").unwrap().as_i64(), 0);
+        vm.load(ins);
+        vm.run().unwrap();
+        assert_eq!(vm.get_global("total").unwrap().as_i64(), 10);
+
+        vm.set_global("limit", VMData::new_i64(7)).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.get_global("total").unwrap().as_i64(), 14);
+        // Linking again doesn't reset them
+        vm.link(&assemble(GLOBALS)).unwrap();
+        assert_eq!(vm.get_global("total").unwrap().as_i64(), 14);
+    }
+
+    #[test]
+    fn unknown_globals() {
+        let mut vm = load(GLOBALS);
+        assert!(vm.get_global("nope").is_none());
+        let e = vm.set_global("nope", VMData::new_i64(1)).unwrap_err();
+        assert_eq!(e.to_string(), "global #nope isn't declared");
+        assert!(vm.get_global("nope").is_none());
+        // Only linked indices are checked
+        let e = vm
+            .execute_instruction(&Instruction::LoadGlobal(2))
+            .unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::InvalidGlobal(2)));
+        let mut program = assemble(GLOBALS);
+        program.ins.insert(1, Instruction::StoreGlobal(2));
+        let e = vm.link(&program).unwrap_err();
+        assert!(matches!(e.kind, VMErrorKind::InvalidGlobal(2)));
+        assert_eq!(e.pc, 1);
+    }
+
+    #[test]
+    fn vector_opcodes() {
+        let vm = run(".section
+.code
+main:
+    .locals $1
+    create_vec int
+    store_local $0
+    push_i $1
+    load_local $0
+    vec_push
+    push_i $3
+    load_local $0
+    vec_push
+    ; [1, 3] -> [1, 2, 3]
+    push_i $2
+    push_i $1
+    load_local $0
+    vec_insert
+    ; -> [1, 2, 3, 4]
+    push_i $4
+    push_i $3
+    load_local $0
+    vec_insert
+    ; -> [1, 20, 3, 4]
+    push_i $20
+    push_i $1
+    load_local $0
+    vec_set
+    push_i $0
+    load_local $0
+    vec_remove
+    load_local $0
+    vec_pop
+    push_i $1
+    load_local $0
+    vec_get
+    load_local $0
+    vec_len
+    hlt
+");
+        let top: Vec<_> = vm.stack.iter().skip(2).map(|d| d.as_i64()).collect();
+        assert_eq!(top, [1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn vector_errors() {
+        let fails = |body: &str| {
+            let source = format!(
+                ".section\n.code\nmain:\n    .locals $1\n    create_vec int\n    store_local $0\n    push_i $1\n    load_local $0\n    vec_push\n{body}    hlt\n"
+            );
+            load(&source).run().unwrap_err().kind
+        };
+        let out_of_bounds = |e: VMErrorKind, i: u64| {
+            assert!(
+                matches!(e, VMErrorKind::VectorIndexOutOfBounds { index, .. } if index == i),
+                "{}",
+                e
+            );
+        };
+        out_of_bounds(fails("    push_i $1\n    load_local $0\n    vec_get\n"), 1);
+        out_of_bounds(
+            fails("    push_i $0\n    push_i $1\n    load_local $0\n    vec_set\n"),
+            1,
+        );
+        out_of_bounds(
+            fails("    push_i $1\n    load_local $0\n    vec_remove\n"),
+            1,
+        );
+        // Inserting at the end is pushing
+        out_of_bounds(
+            fails("    push_i $0\n    push_i $2\n    load_local $0\n    vec_insert\n"),
+            2,
+        );
+        out_of_bounds(
+            fails("    load_local $0\n    vec_pop\n    load_local $0\n    vec_pop\n"),
+            0,
+        );
+        let e = fails("    push_i $-1\n    load_local $0\n    vec_get\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::TypeMismatch {
+                found: VMData::TAG_I64,
+                ..
+            }
+        ));
+        // Values of another type are rejected
+        let e = fails("    push_f $1.5\n    load_local $0\n    vec_push\n");
+        assert_eq!(
+            e.to_string(),
+            VMErrorKind::TypeMismatch {
+                expected: VMData::TAG_I64,
+                found: VMData::TAG_FLOAT
+            }
+            .to_string()
+        );
+        let e = fails("    push_u $1\n    push_i $0\n    load_local $0\n    vec_set\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::TypeMismatch {
+                expected: VMData::TAG_I64,
+                found: VMData::TAG_U64
+            }
+        ));
+        let e = fails("    push_f $1.5\n    push_i $0\n    load_local $0\n    vec_insert\n");
+        assert!(matches!(
+            e,
+            VMErrorKind::TypeMismatch {
+                found: VMData::TAG_FLOAT,
+                ..
+            }
+        ));
+    }
+
+    /// Run `source` with `name` as its only extern, taking an int
+    fn with_extern(
+        source: &str,
+        name: &str,
+        call: impl FnMut(VMState) -> Result<VMData, VMErrorKind> + Send + 'static,
+    ) -> VM {
+        let mut vm = VM::new(16, vec![]);
+        vm.add_extern_fn(name, &[VMData::TAG_I64], call);
+        let ins = vm.link(&assemble(source)).unwrap();
+        vm.load(ins);
+        vm
+    }
+
+    #[test]
+    fn extern_closures_keep_their_state() {
+        let mut calls = 0;
+        let mut vm = with_extern(
+            ".section\n.code\nmain:\n    push_i $0\n    extern_call @count\n    extern_call @count\n    hlt\n",
+            "count",
+            move |s: VMState| {
+                calls += 1;
+                Ok(VMData::new_i64(s.stack.pop()?.try_i64()? + calls))
+            },
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 3);
+        vm.run().unwrap();
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 7);
+    }
+
+    #[test]
+    fn externs_reach_the_host() {
+        let mut vm = with_extern(
+            ".section\n.code\nmain:\n    push_i $1\n    extern_call @log\n    push_i $2\n    extern_call @log\n    hlt\n",
+            "log",
+            |mut s: VMState| {
+                let val = s.stack.pop()?.try_i64()?;
+                s.try_host::<Vec<i64>>()?.push(val);
+                Ok(VMData::new_unit())
+            },
+        );
+        vm.set_host(vec![0i64]);
+        vm.run().unwrap();
+        assert_eq!(vm.host::<Vec<i64>>().unwrap(), &[0, 1, 2]);
+        assert_eq!(vm.take_host::<Vec<i64>>().unwrap(), [0, 1, 2]);
+        // A host of another type is missing too
+        vm.set_host("log".to_owned());
+        let e = vm.run().unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            format!(
+                "extern @log failed: no host context of type {}",
+                std::any::type_name::<Vec<i64>>()
+            )
+        );
+        assert_eq!(e.pc, 1);
+        assert_eq!(vm.host::<String>().unwrap(), "log");
+    }
+
+    #[test]
+    fn extern_failures_are_wrapped() {
+        let mut vm = with_extern(
+            ".section\n.code\nmain:\n    push_i $1\n    extern_call @fail\n    hlt\n",
+            "fail",
+            |_| Err(VMErrorKind::Custom("boom".to_owned())),
+        );
+        let e = vm.run().unwrap_err();
+        assert!(matches!(&e.kind, VMErrorKind::ExternFailed { source, .. }
+            if matches!(**source, VMErrorKind::Custom(_))));
+        assert_eq!(e.kind.to_string(), "extern @fail failed: boom");
+        // Exceptions are thrown as is
+        let mut vm = with_extern(
+            ".section\n.code\nmain:\n    try_begin &caught\n    push_i $1\n    extern_call @throw\n    hlt\ncaught:\n    hlt\n",
+            "throw",
+            |s: VMState| Err(VMErrorKind::Exception(s.stack.pop()?)),
+        );
+        assert_eq!(vm.run().unwrap(), ExecutionOutcome::Halted);
+        assert_eq!(vm.stack.last().unwrap().as_i64(), 1);
+        // An extern can only pop its own arguments
+        let mut vm = with_extern(
+            ".section\n.code\nmain:\n    push_i $1\n    extern_call @keep\n    hlt\n",
+            "keep",
+            |_| Ok(VMData::new_unit()),
+        );
+        let e = vm.run().unwrap_err();
+        assert_eq!(
+            e.kind.to_string(),
+            "extern @keep takes 1 argument(s) but popped 0 value(s)"
+        );
     }
 }
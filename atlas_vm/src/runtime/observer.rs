@@ -0,0 +1,247 @@
+//! Hooks called by the VM while it runs, to trace or profile a program.
+//!
+//! The VM picks a version of its main loop without any hook when no observer is set,
+//! so observing has no cost unless it's used.
+
+use std::{
+    any::Any,
+    fmt::Debug,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use internment::Intern;
+
+use crate::{
+    instruction::Instruction,
+    memory::{
+        object_map::{Object, ObjectIndex},
+        stack::Stack,
+        vm_data::VMData,
+    },
+};
+
+/// Every method does nothing by default, so an observer only implements what it needs
+pub trait Observer: Any + Send {
+    /// `ins` is about to run at `pc`
+    fn before_instruction(&mut self, _pc: usize, _ins: &Instruction, _stack: &Stack) {}
+    /// `ins` ran without error, `pc` being where it was
+    fn after_instruction(&mut self, _pc: usize, _ins: &Instruction, _stack: &Stack) {}
+    /// A `call` or `call_method` at `from` jumps to `to`, `depth` being the number of calls
+    /// running once it's done
+    fn on_call(&mut self, _from: usize, _to: usize, _depth: usize) {}
+    /// A `ret` at `from` goes back to `to`
+    fn on_ret(&mut self, _from: usize, _to: usize, _depth: usize) {}
+    /// The VM allocated `object` at `ptr`, the objects allocated by externs aren't reported
+    fn on_alloc(&mut self, _ptr: ObjectIndex, _object: &Object) {}
+    /// A value thrown at `from` is caught by the handler at `to`, `depth` being the number of
+    /// calls left once they're unwound
+    fn on_throw(&mut self, _from: usize, _to: usize, _depth: usize) {}
+    /// The extern `name` is about to be called with `args`, the last one being on top of the stack
+    fn on_extern_call(&mut self, _name: Intern<String>, _args: &[VMData]) {}
+    /// The program halted or failed, see `VM::execute` & `VM::resume`
+    fn on_finish(&mut self) {}
+}
+
+impl Debug for dyn Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observer")
+    }
+}
+
+/// Write every event as a line of JSON, e.g.
+/// `{"event":"ins","pc":3,"ins":"AddI","stack":2,"top":"5"}`.
+///
+/// Writing stops at the first io error, which is kept in [`JsonTracer::error`].
+pub struct JsonTracer<W: Write + Send> {
+    out: BufWriter<W>,
+    error: Option<io::Error>,
+}
+
+impl JsonTracer<File> {
+    /// Trace into the file at `path`, replacing it if it exists
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: BufWriter::new(out),
+            error: None,
+        }
+    }
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+    /// Flush the trace & give back the writer
+    pub fn finish(self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.out.into_inner().map_err(|e| e.into_error())
+    }
+    fn line(&mut self, line: std::fmt::Arguments) {
+        if self.error.is_none() {
+            if let Err(e) = self.out.write_fmt(line) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Observer for JsonTracer<W> {
+    fn after_instruction(&mut self, pc: usize, ins: &Instruction, stack: &Stack) {
+        let top = match stack.last() {
+            Ok(val) => json_string(&val.to_string()),
+            Err(_) => "null".to_owned(),
+        };
+        self.line(format_args!(
+            "{{\"event\":\"ins\",\"pc\":{},\"ins\":{},\"stack\":{},\"top\":{}}}\n",
+            pc,
+            json_string(&format!("{:?}", ins)),
+            stack.top,
+            top
+        ));
+    }
+    fn on_call(&mut self, from: usize, to: usize, depth: usize) {
+        self.line(format_args!(
+            "{{\"event\":\"call\",\"from\":{},\"to\":{},\"depth\":{}}}\n",
+            from, to, depth
+        ));
+    }
+    fn on_ret(&mut self, from: usize, to: usize, depth: usize) {
+        self.line(format_args!(
+            "{{\"event\":\"ret\",\"from\":{},\"to\":{},\"depth\":{}}}\n",
+            from, to, depth
+        ));
+    }
+    fn on_throw(&mut self, from: usize, to: usize, depth: usize) {
+        self.line(format_args!(
+            "{{\"event\":\"throw\",\"from\":{},\"to\":{},\"depth\":{}}}\n",
+            from, to, depth
+        ));
+    }
+    fn on_alloc(&mut self, ptr: ObjectIndex, object: &Object) {
+        self.line(format_args!(
+            "{{\"event\":\"alloc\",\"ptr\":{},\"object\":{}}}\n",
+            ptr.idx,
+            json_string(&object.to_string())
+        ));
+    }
+    fn on_extern_call(&mut self, name: Intern<String>, args: &[VMData]) {
+        let args: Vec<String> = args.iter().map(|a| json_string(&a.to_string())).collect();
+        self.line(format_args!(
+            "{{\"event\":\"extern\",\"name\":{},\"args\":[{}]}}\n",
+            json_string(&name),
+            args.join(",")
+        ));
+    }
+    fn on_finish(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.out.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// `s` as a quoted JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{instruction::compiler::assemble, runtime::VM};
+
+    /// A writer whose contents can be read while the tracer still owns it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails every write
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::other("flush"))
+        }
+    }
+
+    const THROW: &str = "
+.section
+.code
+main:
+    try_begin &caught
+    create_string
+    throw
+caught:
+    pop
+    hlt
+";
+
+    fn execute(observer: impl Observer) -> VM {
+        let program = assemble(THROW);
+        let mut vm = VM::new(16, vec![]);
+        let ins = vm.link(&program).unwrap();
+        vm.set_observer(observer);
+        vm.execute(&ins).unwrap();
+        vm
+    }
+
+    #[test]
+    fn trace_is_flushed_once_finished() {
+        let out = Shared::default();
+        let mut vm = execute(JsonTracer::new(out.clone()));
+        let trace = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<&str> = trace
+            .lines()
+            .map(|l| l.split('"').nth(3).unwrap())
+            .collect();
+        assert_eq!(events, ["ins", "alloc", "ins", "throw", "ins", "ins"]);
+        assert!(vm
+            .observer_mut::<JsonTracer<Shared>>()
+            .unwrap()
+            .error()
+            .is_none());
+    }
+
+    #[test]
+    fn first_error_is_kept() {
+        let mut vm = execute(JsonTracer::new(Broken));
+        let tracer = vm.take_observer::<JsonTracer<Broken>>().unwrap();
+        assert_eq!(tracer.error().unwrap().to_string(), "broken");
+        assert_eq!(tracer.finish().unwrap_err().to_string(), "broken");
+    }
+}
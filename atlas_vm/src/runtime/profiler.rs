@@ -0,0 +1,411 @@
+//! Count where a program spends its instructions & time, as an [`Observer`].
+//!
+//! Every run instruction is counted by pc & added to the function running it. Functions are
+//! told apart by the address they were called at, along the path of calls leading to them,
+//! so the same function called from 2 places is 2 nodes of the call tree.
+//! Wall time is only measured on calls & returns, so it includes the profiling itself.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    instruction::{compiler::parser::Program, Instruction},
+    memory::stack::Stack,
+    runtime::observer::Observer,
+};
+
+/// Number of instructions listed in the hottest ones of the report
+const HOT_INSTRUCTIONS: usize = 10;
+
+/// A function called along a given path of calls
+#[derive(Debug, Clone)]
+struct Node {
+    entry: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    calls: u64,
+    /// Instructions run by the function itself
+    instructions: u64,
+    /// Time spent in the function & its callees
+    time: Duration,
+}
+
+/// Totals of a function over every path it was called from
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub entry: usize,
+    pub calls: u64,
+    /// Instructions run by the function & its callees
+    pub inclusive: u64,
+    /// Instructions run by the function itself
+    pub exclusive: u64,
+    pub inclusive_time: Duration,
+    pub exclusive_time: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// (label, position) of each block, see [`Program::fn_name`]
+    labels: Vec<(String, usize)>,
+    /// Number of runs of each instruction by pc
+    counts: Vec<u64>,
+    /// Last instruction run at each pc, to name the opcodes
+    ins: Vec<Option<Instruction>>,
+    /// The root is the entry point
+    nodes: Vec<Node>,
+    /// Node & start of each call currently running, the entry point being the first one
+    calls: Vec<(usize, Instant)>,
+}
+
+impl Profiler {
+    /// Profile `program`, its labels naming the functions
+    pub fn new(program: &Program) -> Self {
+        Self {
+            labels: program.fn_name.clone(),
+            counts: vec![],
+            ins: vec![],
+            nodes: vec![Node {
+                entry: 0,
+                parent: None,
+                children: vec![],
+                calls: 0,
+                instructions: 0,
+                time: Duration::ZERO,
+            }],
+            calls: vec![],
+        }
+    }
+    /// Number of runs of the instruction at `pc`
+    pub fn count(&self, pc: usize) -> u64 {
+        self.counts.get(pc).copied().unwrap_or(0)
+    }
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+    /// Number of runs of each opcode, the most run first
+    pub fn opcodes(&self) -> Vec<(String, u64)> {
+        let mut opcodes: HashMap<String, u64> = HashMap::new();
+        for (ins, count) in self.ins.iter().zip(&self.counts) {
+            if let Some(ins) = ins {
+                *opcodes.entry(opcode(ins)).or_default() += count;
+            }
+        }
+        let mut opcodes: Vec<(String, u64)> = opcodes.into_iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        opcodes
+    }
+    /// Totals of every called function, the one running the most instructions itself first.
+    /// Recursive calls are only counted once in the inclusive totals.
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let inclusive = self.inclusive();
+        let mut functions: Vec<FunctionProfile> = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.calls == 0 {
+                continue;
+            }
+            let f = match functions.iter().position(|f| f.entry == node.entry) {
+                Some(pos) => &mut functions[pos],
+                None => {
+                    functions.push(FunctionProfile {
+                        name: self.name(node.entry),
+                        entry: node.entry,
+                        calls: 0,
+                        inclusive: 0,
+                        exclusive: 0,
+                        inclusive_time: Duration::ZERO,
+                        exclusive_time: Duration::ZERO,
+                    });
+                    functions.last_mut().unwrap()
+                }
+            };
+            let children_time: Duration = node.children.iter().map(|c| self.nodes[*c].time).sum();
+            f.calls += node.calls;
+            f.exclusive += node.instructions;
+            f.exclusive_time += node.time.saturating_sub(children_time);
+            if !self.is_recursive(i) {
+                f.inclusive += inclusive[i];
+                f.inclusive_time += node.time;
+            }
+        }
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.entry.cmp(&b.entry)));
+        functions
+    }
+    /// Readable summary of the functions, opcodes & hottest instructions
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} instruction(s) run", self.total()).unwrap();
+
+        writeln!(out, "\nfunctions:").unwrap();
+        writeln!(
+            out,
+            "  {:<20} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            "name", "calls", "inclusive", "exclusive", "incl. time", "excl. time"
+        )
+        .unwrap();
+        for f in self.functions() {
+            writeln!(
+                out,
+                "  {:<20} {:>8} {:>12} {:>12} {:>12} {:>12}",
+                f.name,
+                f.calls,
+                f.inclusive,
+                f.exclusive,
+                format!("{:.2?}", f.inclusive_time),
+                format!("{:.2?}", f.exclusive_time)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nopcodes:").unwrap();
+        for (opcode, count) in self.opcodes() {
+            writeln!(out, "  {:<20} {:>12}", opcode, count).unwrap();
+        }
+
+        writeln!(out, "\nhottest instructions:").unwrap();
+        let mut hot: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, c)| *c > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (pc, count) in hot.into_iter().take(HOT_INSTRUCTIONS) {
+            let ins = self.ins[pc].map(|i| format!("{:?}", i)).unwrap_or_default();
+            writeln!(out, "  [{}] {:<24} {:>12}", pc, ins, count).unwrap();
+        }
+        out
+    }
+    /// One line per path of calls with the instructions run at its end, e.g. `main;fib;fib 42`.
+    /// It's the input of flamegraph tools such as `inferno-flamegraph` or `flamegraph.pl`.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for node in &self.nodes {
+            if node.instructions == 0 {
+                continue;
+            }
+            let mut path = vec![self.name(node.entry)];
+            let mut parent = node.parent;
+            while let Some(p) = parent {
+                path.push(self.name(self.nodes[p].entry));
+                parent = self.nodes[p].parent;
+            }
+            path.reverse();
+            writeln!(out, "{} {}", path.join(";"), node.instructions).unwrap();
+        }
+        out
+    }
+
+    /// Instructions run by each node & its children
+    fn inclusive(&self) -> Vec<u64> {
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|n| n.instructions).collect();
+        // Children are always created after their parent
+        for i in (1..self.nodes.len()).rev() {
+            if let Some(p) = self.nodes[i].parent {
+                inclusive[p] += inclusive[i];
+            }
+        }
+        inclusive
+    }
+    /// Whether the function of `node` is also one of its callers
+    fn is_recursive(&self, node: usize) -> bool {
+        let entry = self.nodes[node].entry;
+        let mut parent = self.nodes[node].parent;
+        while let Some(p) = parent {
+            if self.nodes[p].entry == entry {
+                return true;
+            }
+            parent = self.nodes[p].parent;
+        }
+        false
+    }
+    /// Label of the function starting at `entry`
+    fn name(&self, entry: usize) -> String {
+        match self.labels.iter().find(|(_, pos)| *pos == entry) {
+            Some((name, _)) => name.clone(),
+            None => format!("[{}]", entry),
+        }
+    }
+    /// End the calls deeper than `depth`
+    fn close(&mut self, depth: usize, now: Instant) {
+        while self.calls.len() > depth {
+            let (node, start) = self.calls.pop().unwrap();
+            self.nodes[node].time += now - start;
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn before_instruction(&mut self, pc: usize, ins: &Instruction, _stack: &Stack) {
+        if self.calls.is_empty() {
+            self.nodes[0].calls += 1;
+            self.calls.push((0, Instant::now()));
+        }
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+            self.ins.resize(pc + 1, None);
+        }
+        self.counts[pc] += 1;
+        self.ins[pc] = Some(*ins);
+        let (node, _) = self.calls.last().unwrap();
+        self.nodes[*node].instructions += 1;
+    }
+    fn on_call(&mut self, _from: usize, to: usize, _depth: usize) {
+        let now = Instant::now();
+        let parent = self.calls.last().map_or(0, |(node, _)| *node);
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|c| self.nodes[*c].entry == to);
+        let node = match existing {
+            Some(node) => node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(Node {
+                    entry: to,
+                    parent: Some(parent),
+                    children: vec![],
+                    calls: 0,
+                    instructions: 0,
+                    time: Duration::ZERO,
+                });
+                self.nodes[parent].children.push(node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.calls.push((node, now));
+    }
+    fn on_ret(&mut self, _from: usize, _to: usize, depth: usize) {
+        self.close(depth, Instant::now());
+    }
+    fn on_throw(&mut self, _from: usize, _to: usize, depth: usize) {
+        self.close(depth, Instant::now());
+    }
+    fn on_finish(&mut self) {
+        self.close(0, Instant::now());
+    }
+}
+
+/// Name of the instruction without its operands
+fn opcode(ins: &Instruction) -> String {
+    let name = format!("{:?}", ins);
+    match name.find('(') {
+        Some(pos) => name[..pos].to_owned(),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::assemble;
+    use crate::runtime::VM;
+
+    fn profile(source: &str) -> Profiler {
+        let program = assemble(source);
+        let mut vm = VM::new(16, vec![]);
+        vm.set_observer(Profiler::new(&program));
+        let ins = vm.link(&program).unwrap();
+        vm.execute(&ins).unwrap();
+        vm.take_observer::<Profiler>().unwrap()
+    }
+
+    /// (name, calls, inclusive, exclusive) of every function
+    fn totals(profiler: &Profiler) -> Vec<(String, u64, u64, u64)> {
+        profiler
+            .functions()
+            .into_iter()
+            .map(|f| (f.name, f.calls, f.inclusive, f.exclusive))
+            .collect()
+    }
+
+    #[test]
+    fn totals_add_up_across_call_paths() {
+        let profiler = profile(
+            "
+.section
+.code
+main:
+    call &leaf
+    call &twice
+    hlt
+leaf:
+    push_i $1
+    pop
+    ret
+twice:
+    call &leaf
+    call &leaf
+    ret
+",
+        );
+        // `hlt` isn't counted
+        assert_eq!(profiler.total(), 14);
+        assert_eq!(profiler.count(3), 3);
+        assert_eq!(profiler.count(2), 0);
+        assert_eq!(
+            totals(&profiler),
+            [
+                ("leaf".to_owned(), 3, 9, 9),
+                ("twice".to_owned(), 1, 9, 3),
+                ("main".to_owned(), 1, 14, 2),
+            ]
+        );
+        assert_eq!(
+            profiler.opcodes(),
+            [
+                ("Call".to_owned(), 4),
+                ("Ret".to_owned(), 4),
+                ("Pop".to_owned(), 3),
+                ("PushI".to_owned(), 3),
+            ]
+        );
+        assert_eq!(
+            profiler.folded(),
+            "main 2\nmain;leaf 3\nmain;twice 3\nmain;twice;leaf 6\n"
+        );
+        for f in profiler.functions() {
+            assert!(f.exclusive_time <= f.inclusive_time, "{}", f.name);
+        }
+    }
+
+    #[test]
+    fn recursion_is_counted_once_inclusively() {
+        let profiler = profile(
+            "
+.section
+.code
+main:
+    push_i $3
+    call &down
+    hlt
+down:
+    dup
+    jmp_z &done
+    push_i $1
+    sub_i
+    call &down
+    ret
+done:
+    ret
+",
+        );
+        assert_eq!(
+            totals(&profiler),
+            [
+                ("down".to_owned(), 4, 21, 21),
+                ("main".to_owned(), 1, 23, 2),
+            ]
+        );
+        assert_eq!(
+            profiler.folded().lines().last(),
+            Some("main;down;down;down;down 3")
+        );
+    }
+}
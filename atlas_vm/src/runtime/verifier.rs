@@ -0,0 +1,686 @@
+//! Static checks over a list of instructions, done once before running them.
+//!
+//! Every `call` target is treated as the start of a function. Each function is walked
+//! with its stack depth relative to its entry, which gives how deep it reaches into its
+//! caller's stack & how much it leaves on return. Code reached from `pc == 0` is the
+//! entry point and starts with an empty stack.
+//!
+//! A function starting with `.args`/`.locals` has a frame: it can't pop below its locals
+//! and its arguments are replaced by a single returned value.
+//!
+//! Every method is a function too, and all the methods a `call_method` can reach
+//! must have the same effect on the stack.
+//!
+//! The handler of a `try_begin` is walked as part of the same function, with the stack
+//! depth of the `try_begin` plus the thrown value.
+//!
+//! The target of a `coro_create` is a function too, run on the stack of its coroutine.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use internment::Intern;
+
+use crate::instruction::{Address, Instruction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    UnresolvedAddress(Intern<String>),
+    AddressOutOfBounds(usize),
+    InvalidConstant(usize),
+    InvalidGlobal(usize),
+    InvalidClass(usize),
+    UnknownExternCall(usize),
+    /// No class has a method with this name
+    UnknownMethod(Intern<String>),
+    StackUnderflow,
+    /// The same instruction can be reached with different stack depths
+    StackMismatch {
+        expected: isize,
+        found: isize,
+    },
+    ReturnOutsideCall,
+    /// `.args` & `.locals` are only allowed at the start of a function
+    MisplacedFrame,
+    ArgOutOfBounds(usize),
+    LocalOutOfBounds(usize),
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::UnresolvedAddress(label) => {
+                write!(f, "address &{} has never been resolved", label)
+            }
+            DiagnosticKind::AddressOutOfBounds(u) => {
+                write!(f, "address {} is out of the program", u)
+            }
+            DiagnosticKind::InvalidConstant(u) => write!(f, "constant #{} doesn't exist", u),
+            DiagnosticKind::InvalidGlobal(u) => write!(f, "global #{} doesn't exist", u),
+            DiagnosticKind::InvalidClass(u) => write!(f, "class #{} doesn't exist", u),
+            DiagnosticKind::UnknownMethod(name) => {
+                write!(f, "no class has a method {}", name)
+            }
+            DiagnosticKind::UnknownExternCall(u) => {
+                write!(f, "extern call ${} doesn't exist", u)
+            }
+            DiagnosticKind::StackUnderflow => write!(f, "stack underflow"),
+            DiagnosticKind::StackMismatch { expected, found } => write!(
+                f,
+                "stack depth mismatch: expected {}, found {}",
+                expected, found
+            ),
+            DiagnosticKind::ReturnOutsideCall => write!(f, "return outside of a call"),
+            DiagnosticKind::MisplacedFrame => {
+                write!(f, "frame declared outside of the start of a function")
+            }
+            DiagnosticKind::ArgOutOfBounds(u) => {
+                write!(f, "argument ${} isn't declared by the function", u)
+            }
+            DiagnosticKind::LocalOutOfBounds(u) => {
+                write!(f, "local ${} isn't declared by the function", u)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub pc: usize,
+    pub ins: Instruction,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at [{}] {:?}", self.kind, self.pc, self.ins)
+    }
+}
+
+/// Instructions that went through [`verify`] without any diagnostic.
+///
+/// It can only be built by [`crate::runtime::VM::verify`], so running it can skip
+/// the checks the verifier already did.
+#[derive(Debug, Clone, Copy)]
+pub struct Verified<'a> {
+    pub(crate) ins: &'a [Instruction],
+    pub(crate) constants: usize,
+    pub(crate) extern_fn: usize,
+}
+
+impl<'a> Verified<'a> {
+    pub fn instructions(&self) -> &'a [Instruction] {
+        self.ins
+    }
+}
+
+/// Check `ins` against a constant pool of `constants` values, `globals` globals, `classes` classes,
+/// extern calls taking `extern_args[i]` arguments & the (name, address) of every method
+pub fn verify(
+    ins: &[Instruction],
+    constants: usize,
+    globals: usize,
+    classes: usize,
+    extern_args: &[usize],
+    methods: &[(Intern<String>, usize)],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut report = |kind, pc: usize| {
+        diagnostics.push(Diagnostic {
+            kind,
+            pc,
+            ins: ins[pc],
+        })
+    };
+
+    let mut functions: BTreeMap<usize, Option<Summary>> = BTreeMap::new();
+    for (pc, i) in ins.iter().enumerate() {
+        match i {
+            Instruction::LoadConst(u) if *u >= constants => {
+                report(DiagnosticKind::InvalidConstant(*u), pc)
+            }
+            Instruction::LoadGlobal(u) | Instruction::StoreGlobal(u) if *u >= globals => {
+                report(DiagnosticKind::InvalidGlobal(*u), pc)
+            }
+            Instruction::NewClass(u)
+            | Instruction::GetField(u, _)
+            | Instruction::SetField(u, _)
+                if *u >= classes =>
+            {
+                report(DiagnosticKind::InvalidClass(*u), pc)
+            }
+            Instruction::ExternCall(u) if *u >= extern_args.len() => {
+                report(DiagnosticKind::UnknownExternCall(*u), pc)
+            }
+            Instruction::CallMethod(name) if !methods.iter().any(|(n, _)| n == name) => {
+                report(DiagnosticKind::UnknownMethod(*name), pc)
+            }
+            Instruction::Jmp(a)
+            | Instruction::JmpNZ(a)
+            | Instruction::JmpZ(a)
+            | Instruction::Call(a)
+            | Instruction::TryBegin(a)
+            | Instruction::CoroCreate(a) => match a {
+                Address::ToDefine(label) => report(DiagnosticKind::UnresolvedAddress(*label), pc),
+                Address::Val(v) if *v > ins.len() => {
+                    report(DiagnosticKind::AddressOutOfBounds(*v), pc)
+                }
+                Address::Val(v) => {
+                    if matches!(i, Instruction::Call(_) | Instruction::CoroCreate(_)) {
+                        functions.insert(*v, None);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    if ins.is_empty() {
+        return diagnostics;
+    }
+    for (_, address) in methods {
+        if *address < ins.len() {
+            functions.insert(*address, None);
+        }
+    }
+
+    // Recursive functions need their own summary, so it's refined until it doesn't change anymore
+    for _ in 0..=functions.len() {
+        let mut changed = false;
+        for entry in functions.keys().copied().collect::<Vec<_>>() {
+            let summary = walk(ins, extern_args, methods, entry, &functions, None);
+            if functions[&entry] != summary {
+                functions.insert(entry, summary);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for entry in functions.keys() {
+        walk(
+            ins,
+            extern_args,
+            methods,
+            *entry,
+            &functions,
+            Some(&mut report),
+        );
+    }
+    if !functions.contains_key(&0) {
+        walk(ins, extern_args, methods, 0, &functions, Some(&mut report));
+    }
+    diagnostics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Summary {
+    /// Lowest depth reached, relative to the entry of the function
+    lowest: isize,
+    /// Depth left on return, `None` if it never returns
+    ret: Option<isize>,
+}
+
+/// How many values an instruction pops & pushes, control flow aside
+fn stack_effect(ins: &Instruction, extern_args: &[usize]) -> (isize, isize) {
+    use Instruction::*;
+    match ins {
+        PushI(_) | PushU(_) | PushF(_) | LoadConst(_) => (0, 1),
+        LoadArg(_) | LoadLocal(_) | LoadGlobal(_) => (0, 1),
+        Pop | PrintChar | JmpNZ(_) | JmpZ(_) | StoreLocal(_) | StoreGlobal(_) | Throw => (1, 0),
+        Enter(_, locals) => (0, *locals as isize),
+        AddI | AddU | AddF | SubI | SubU | SubF | MulI | MulU | MulF | DivI | DivU | DivF => (2, 1),
+        AddIWith(_) | SubIWith(_) | MulIWith(_) | AddUWith(_) | SubUWith(_) | MulUWith(_) => (2, 1),
+        DivIWith(_) => (2, 1),
+        ModI | ModU | MinI | MaxI | MinU | MaxU => (2, 1),
+        BAnd | BOr | BXor | Shl | Shr | Sar => (2, 1),
+        NegI | AbsI | NegIWith(_) | AbsIWith(_) | BNot => (1, 1),
+        Instruction::Eq | Neq | Lt | Gt | Lte | Gte | And | Or => (2, 1),
+        Dup => (1, 2),
+        Swap => (2, 2),
+        Rot => (3, 3),
+        Print | Not | CastToI | CastToF | CastToU | CastToChar | CastToBool | CastToPtr => (1, 1),
+        GetStruct(_) | StrLen => (1, 1),
+        Read | ReadI | CreateStruct(_) | CreateString => (0, 1),
+        SetStruct(_) | WriteCharToString => (2, 0),
+        ReadCharFromString | StrConcat | StrFind => (2, 1),
+        StrSlice => (3, 1),
+        StrUpper | StrLower | ParseInt | ParseFloat | ToStr => (1, 1),
+        CreateVec(_) => (0, 1),
+        VecPush => (2, 0),
+        VecPop | VecLen => (1, 1),
+        VecGet | VecRemove => (2, 1),
+        VecSet | VecInsert => (3, 0),
+        CreateMap => (0, 1),
+        MapGet | MapHas | MapRemove | MapKeyAt => (2, 1),
+        MapSet => (3, 0),
+        MapLen => (1, 1),
+        NewClass(_) => (0, 1),
+        GetField(_, _) => (1, 1),
+        SetField(_, _) => (2, 0),
+        ExternCall(u) => (extern_args.get(*u).copied().unwrap_or(0) as isize, 1),
+        Jmp(_) | Call(_) | CallMethod(_) | Ret | HLT | Nop => (0, 0),
+        TryBegin(_) | TryEnd => (0, 0),
+        CoroCreate(_) => (0, 1),
+        CoroResume => (2, 1),
+        Yield | CoroStatus => (1, 1),
+        Send(_) => (1, 0),
+        Recv(_) => (0, 1),
+    }
+}
+
+/// Follow every path from `entry` and summarize the function starting there.
+/// Diagnostics are only reported when `report` is given.
+fn walk(
+    ins: &[Instruction],
+    extern_args: &[usize],
+    methods: &[(Intern<String>, usize)],
+    entry: usize,
+    functions: &BTreeMap<usize, Option<Summary>>,
+    mut report: Option<&mut dyn FnMut(DiagnosticKind, usize)>,
+) -> Option<Summary> {
+    let is_function = functions.contains_key(&entry);
+    // A function with a frame can't touch the stack below its locals
+    // & always returns a single value in place of its arguments
+    let frame = match ins.get(entry) {
+        Some(Instruction::Enter(args, locals)) => Some((*args, *locals as isize)),
+        _ => None,
+    };
+    let (args, locals) = frame.unwrap_or((0, 0));
+    let mut depths: Vec<Option<isize>> = vec![None; ins.len()];
+    let mut work = vec![(entry, 0)];
+    let mut lowest = 0;
+    let mut ret = None;
+    while let Some((pc, depth)) = work.pop() {
+        if pc >= ins.len() {
+            continue;
+        }
+        match depths[pc] {
+            None => depths[pc] = Some(depth),
+            Some(expected) if expected != depth => {
+                emit(
+                    &mut report,
+                    DiagnosticKind::StackMismatch {
+                        expected,
+                        found: depth,
+                    },
+                    pc,
+                );
+                continue;
+            }
+            Some(_) => continue,
+        }
+
+        let i = &ins[pc];
+        match i {
+            Instruction::Enter(_, _) if pc != entry => {
+                emit(&mut report, DiagnosticKind::MisplacedFrame, pc)
+            }
+            Instruction::LoadArg(u) if *u >= args => {
+                emit(&mut report, DiagnosticKind::ArgOutOfBounds(*u), pc)
+            }
+            Instruction::LoadLocal(u) | Instruction::StoreLocal(u) if *u as isize >= locals => {
+                emit(&mut report, DiagnosticKind::LocalOutOfBounds(*u), pc)
+            }
+            _ => {}
+        }
+        let floor = if frame.is_some() && pc != entry {
+            locals
+        } else {
+            0
+        };
+        let checked = frame.is_some() || !is_function;
+
+        let (pops, pushes) = stack_effect(i, extern_args);
+        let mut next = depth - pops;
+        lowest = lowest.min(next);
+        if next < floor && checked {
+            emit(&mut report, DiagnosticKind::StackUnderflow, pc);
+            // Keep going as if the stack was just empty, so the error isn't repeated
+            next = floor;
+        }
+        next += pushes;
+
+        match i {
+            Instruction::HLT | Instruction::Throw => {}
+            Instruction::Ret => {
+                if !is_function {
+                    emit(&mut report, DiagnosticKind::ReturnOutsideCall, pc);
+                }
+                if frame.is_some() {
+                    // The frame is dropped anyway, only the returned value matters
+                    if next <= floor {
+                        emit(&mut report, DiagnosticKind::StackUnderflow, pc);
+                    }
+                    ret = Some(1 - args as isize);
+                    continue;
+                }
+                match ret {
+                    Some(expected) if expected != next => {
+                        emit(
+                            &mut report,
+                            DiagnosticKind::StackMismatch {
+                                expected,
+                                found: next,
+                            },
+                            pc,
+                        );
+                    }
+                    Some(_) => {}
+                    None => ret = Some(next),
+                }
+            }
+            Instruction::Jmp(Address::Val(v)) => work.push((*v, next)),
+            Instruction::TryBegin(Address::Val(v)) => {
+                work.push((*v, next + 1));
+                work.push((pc + 1, next));
+            }
+            Instruction::JmpNZ(Address::Val(v)) | Instruction::JmpZ(Address::Val(v)) => {
+                work.push((*v, next));
+                work.push((pc + 1, next));
+            }
+            Instruction::Call(Address::Val(_)) | Instruction::CallMethod(_) => {
+                let callees: Vec<usize> = match i {
+                    Instruction::CallMethod(name) => methods
+                        .iter()
+                        .filter(|(n, _)| n == name)
+                        .map(|(_, address)| *address)
+                        .collect(),
+                    Instruction::Call(Address::Val(v)) => vec![*v],
+                    _ => unreachable!(),
+                };
+                let mut summary: Option<(isize, isize)> = None;
+                for callee in callees {
+                    // Either it never returns or it isn't known yet
+                    let Some(Some(Summary {
+                        lowest,
+                        ret: Some(ret),
+                    })) = functions.get(&callee)
+                    else {
+                        summary = None;
+                        break;
+                    };
+                    summary = match summary {
+                        Some((_, expected)) if expected != *ret => {
+                            emit(
+                                &mut report,
+                                DiagnosticKind::StackMismatch {
+                                    expected,
+                                    found: *ret,
+                                },
+                                pc,
+                            );
+                            None
+                        }
+                        Some((l, r)) => Some((l.min(*lowest), r)),
+                        None => Some((*lowest, *ret)),
+                    };
+                    if summary.is_none() {
+                        break;
+                    }
+                }
+                let Some((callee_lowest, callee_ret)) = summary else {
+                    continue;
+                };
+                lowest = lowest.min(next + callee_lowest);
+                if next + callee_lowest < floor && checked {
+                    emit(&mut report, DiagnosticKind::StackUnderflow, pc);
+                    next = floor - callee_lowest;
+                }
+                work.push((pc + 1, next + callee_ret));
+            }
+            // Unresolved addresses are already reported
+            Instruction::Jmp(_)
+            | Instruction::JmpNZ(_)
+            | Instruction::JmpZ(_)
+            | Instruction::Call(_)
+            | Instruction::TryBegin(_) => {}
+            _ => work.push((pc + 1, next)),
+        }
+    }
+    if frame.is_some() {
+        lowest = -(args as isize);
+    }
+    Some(Summary { lowest, ret })
+}
+
+fn emit(
+    report: &mut Option<&mut dyn FnMut(DiagnosticKind, usize)>,
+    kind: DiagnosticKind,
+    pc: usize,
+) {
+    if let Some(report) = report {
+        report(kind, pc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::{assemble, EXAMPLES};
+    use crate::memory::vm_data::VMData;
+    use crate::runtime::VM;
+    use Instruction::*;
+
+    /// (pc, kind) of every diagnostic, with 1 constant, global & class, an extern taking 2
+    /// arguments and the methods `methods`
+    fn check(ins: &[Instruction], methods: &[(&str, usize)]) -> Vec<(usize, DiagnosticKind)> {
+        let methods: Vec<_> = methods
+            .iter()
+            .map(|(name, address)| (Intern::new(name.to_string()), *address))
+            .collect();
+        verify(ins, 1, 1, 1, &[2], &methods)
+            .into_iter()
+            .map(|d| (d.pc, d.kind))
+            .collect()
+    }
+
+    fn mismatch(diagnostics: &[(usize, DiagnosticKind)], at: usize) -> bool {
+        diagnostics
+            .iter()
+            .any(|(pc, kind)| *pc == at && matches!(kind, DiagnosticKind::StackMismatch { .. }))
+    }
+
+    #[test]
+    fn examples_are_valid() {
+        for (name, source) in EXAMPLES.iter().filter(|(name, _)| *name != "brainfuck") {
+            let mut vm = VM::new(16, vec![]);
+            vm.add_extern_fn("fib", &[VMData::TAG_I64], |s| s.stack.pop());
+            let ins = vm.link(&assemble(source)).unwrap();
+            let diagnostics = vm.verify(&ins).err().unwrap_or_default();
+            assert!(diagnostics.is_empty(), "{}: {:?}", name, diagnostics);
+        }
+    }
+
+    #[test]
+    fn brainfuck_example_is_unbalanced() {
+        let mut vm = VM::new(16, vec![]);
+        let source = EXAMPLES
+            .iter()
+            .find(|(name, _)| *name == "brainfuck")
+            .unwrap()
+            .1;
+        let ins = vm.link(&assemble(source)).unwrap();
+        let at = |label: &str| vm.label_address(label).unwrap();
+        let diagnostics: Vec<_> = vm
+            .verify(&ins)
+            .unwrap_err()
+            .into_iter()
+            .map(|d| (d.pc, d.kind.to_string()))
+            .collect();
+        // The early `ret` of `l_brace` pops `i` but the one after its loop doesn't, and the cases
+        // of the main loop don't all reach `i_inc` with the same depth
+        assert_eq!(
+            diagnostics,
+            [
+                (
+                    at("l_exit_loop") + 1,
+                    "stack depth mismatch: expected -1, found 0".to_owned()
+                ),
+                (
+                    at("i_inc"),
+                    "stack depth mismatch: expected 1, found 3".to_owned()
+                ),
+                (
+                    at("i_inc"),
+                    "stack depth mismatch: expected 1, found 2".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_operands_are_reported() {
+        let label = Intern::new("nowhere".to_owned());
+        let diagnostics = check(
+            &[
+                LoadConst(1),
+                LoadGlobal(1),
+                NewClass(1),
+                ExternCall(1),
+                CallMethod(Intern::new("m".to_owned())),
+                Jmp(Address::ToDefine(label)),
+                Jmp(Address::Val(100)),
+                HLT,
+            ],
+            &[],
+        );
+        assert_eq!(
+            diagnostics[..7],
+            [
+                (0, DiagnosticKind::InvalidConstant(1)),
+                (1, DiagnosticKind::InvalidGlobal(1)),
+                (2, DiagnosticKind::InvalidClass(1)),
+                (3, DiagnosticKind::UnknownExternCall(1)),
+                (
+                    4,
+                    DiagnosticKind::UnknownMethod(Intern::new("m".to_owned()))
+                ),
+                (5, DiagnosticKind::UnresolvedAddress(label)),
+                (6, DiagnosticKind::AddressOutOfBounds(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_underflow_is_reported_once() {
+        let diagnostics = check(&[Pop, PushI(1), AddI, HLT], &[]);
+        assert_eq!(
+            diagnostics,
+            [
+                (0, DiagnosticKind::StackUnderflow),
+                (2, DiagnosticKind::StackUnderflow)
+            ]
+        );
+        // Extern calls pop their arguments
+        assert_eq!(
+            check(&[PushI(1), ExternCall(0), HLT], &[]),
+            [(1, DiagnosticKind::StackUnderflow)]
+        );
+    }
+
+    #[test]
+    fn branches_must_merge_with_the_same_depth() {
+        let diagnostics = check(&[PushI(1), JmpZ(Address::Val(3)), PushI(2), HLT], &[]);
+        assert!(mismatch(&diagnostics, 3), "{:?}", diagnostics);
+        // Both sides push a value
+        let ins = [
+            PushI(1),
+            JmpZ(Address::Val(4)),
+            PushI(2),
+            Jmp(Address::Val(5)),
+            PushI(3),
+            HLT,
+        ];
+        assert_eq!(check(&ins, &[]), []);
+    }
+
+    #[test]
+    fn recursive_function_reaches_a_fixpoint() {
+        // Count down from 3 to 0, calling itself until then
+        let mut ins = vec![
+            PushI(3),
+            Call(Address::Val(3)),
+            HLT,
+            Dup,
+            JmpZ(Address::Val(9)),
+            PushI(1),
+            SubI,
+            Call(Address::Val(3)),
+            Ret,
+            Ret,
+        ];
+        assert_eq!(check(&ins, &[]), []);
+        // The recursive path leaves one more value than the base case
+        ins.insert(8, Dup);
+        if let JmpZ(Address::Val(v)) = &mut ins[4] {
+            *v += 1;
+        }
+        let diagnostics = check(&ins, &[]);
+        assert!(
+            diagnostics
+                .iter()
+                .any(|(_, kind)| matches!(kind, DiagnosticKind::StackMismatch { .. })),
+            "{:?}",
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn frames_are_checked() {
+        let diagnostics = check(
+            &[
+                PushI(1),
+                Call(Address::Val(4)),
+                Ret,
+                HLT,
+                Enter(1, 1),
+                LoadArg(1),
+                LoadLocal(1),
+                Enter(0, 0),
+                Pop,
+                Pop,
+                Ret,
+            ],
+            &[],
+        );
+        assert_eq!(
+            diagnostics,
+            [
+                (5, DiagnosticKind::ArgOutOfBounds(1)),
+                (6, DiagnosticKind::LocalOutOfBounds(1)),
+                (7, DiagnosticKind::MisplacedFrame),
+                // Only the locals are left, there's nothing to return
+                (10, DiagnosticKind::StackUnderflow),
+                (2, DiagnosticKind::ReturnOutsideCall),
+            ]
+        );
+    }
+
+    #[test]
+    fn handler_gets_the_thrown_value() {
+        let ins = [TryBegin(Address::Val(3)), TryEnd, HLT, Pop, HLT];
+        assert_eq!(check(&ins, &[]), []);
+        let ins = [TryBegin(Address::Val(3)), TryEnd, HLT, Pop, Pop, HLT];
+        assert_eq!(check(&ins, &[]), [(4, DiagnosticKind::StackUnderflow)]);
+    }
+
+    #[test]
+    fn methods_with_the_same_name_must_agree() {
+        let mut ins = vec![
+            NewClass(0),
+            CallMethod(Intern::new("m".to_owned())),
+            HLT,
+            Enter(1, 0),
+            LoadArg(0),
+            Ret,
+            Enter(1, 0),
+            PushI(1),
+            Ret,
+        ];
+        assert_eq!(check(&ins, &[("m", 3), ("m", 6)]), []);
+        // Without a frame, it leaves its argument & a value on the stack
+        ins[6] = Nop;
+        assert!(mismatch(&check(&ins, &[("m", 3), ("m", 6)]), 1));
+    }
+}
@@ -1,9 +1,13 @@
+use std::any::Any;
+
 use crate::memory::{object_map::Memory, stack::Stack, vm_data::VMData};
+use crate::runtime::error::VMErrorKind;
 
 pub struct VMState<'state> {
     pub stack: &'state mut Stack,
     pub object_map: &'state mut Memory,
     pub consts: &'state [VMData],
+    host: Option<&'state mut (dyn Any + Send)>,
 }
 
 impl<'state> VMState<'state> {
@@ -11,11 +15,24 @@ impl<'state> VMState<'state> {
         stack: &'state mut Stack,
         object_map: &'state mut Memory,
         consts: &'state [VMData],
+        host: Option<&'state mut (dyn Any + Send)>,
     ) -> Self {
         Self {
             stack,
             object_map,
             consts,
+            host,
         }
     }
+
+    /// The host context set with [`crate::runtime::VM::set_host`], if it's a `T`
+    pub fn host<T: Any>(&mut self) -> Option<&mut T> {
+        self.host.as_deref_mut().and_then(|h| h.downcast_mut())
+    }
+
+    /// Same as [`VMState::host`] but with an error the extern can return as is
+    pub fn try_host<T: Any>(&mut self) -> Result<&mut T, VMErrorKind> {
+        self.host()
+            .ok_or(VMErrorKind::MissingHost(std::any::type_name::<T>()))
+    }
 }
@@ -0,0 +1,200 @@
+//! Run several VMs on a few threads, e.g. actors exchanging messages over channels.
+//!
+//! Each VM runs its loaded program for a slice of instructions at a time, then goes back to
+//! the queue so the others get a turn. A VM blocked by `recv` is skipped until its channel
+//! has a message.
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+use crate::runtime::{error::VMError, ExecutionOutcome, VM};
+
+/// Number of instructions a VM runs before giving its thread to another one
+const DEFAULT_SLICE: u64 = 10_000;
+
+#[derive(Debug)]
+pub struct Workers {
+    vms: Vec<VM>,
+    threads: usize,
+    slice: u64,
+}
+
+/// Shared by the threads while running
+struct Queue {
+    /// VMs waiting for their turn, with their position
+    waiting: VecDeque<(usize, VM)>,
+    /// Number of VMs currently running a slice
+    running: usize,
+    results: Vec<Option<(VM, Result<ExecutionOutcome, VMError>)>>,
+}
+
+impl Workers {
+    /// Run the VMs on `threads` threads, at least 1
+    pub fn new(threads: usize) -> Self {
+        Self {
+            vms: vec![],
+            threads: threads.max(1),
+            slice: DEFAULT_SLICE,
+        }
+    }
+    /// Number of instructions a VM runs before giving its thread to another one
+    pub fn set_slice(&mut self, steps: u64) -> &mut Self {
+        self.slice = steps.max(1);
+        self
+    }
+    /// Add a VM with a loaded program, see [`VM::load`], giving back its position in the results
+    pub fn add(&mut self, vm: VM) -> usize {
+        self.vms.push(vm);
+        self.vms.len() - 1
+    }
+    /// Run every VM until it halts, fails, runs out of fuel or is interrupted.
+    /// The VMs still blocked by `recv` once no other one can send them anything end with
+    /// [`ExecutionOutcome::Blocked`].
+    ///
+    /// Every VM is given back along with how it ended, in the order they were added.
+    pub fn run(self) -> Vec<(VM, Result<ExecutionOutcome, VMError>)> {
+        let count = self.vms.len();
+        let queue = Mutex::new(Queue {
+            waiting: self.vms.into_iter().enumerate().collect(),
+            running: 0,
+            results: (0..count).map(|_| None).collect(),
+        });
+        let turn = Condvar::new();
+        thread::scope(|s| {
+            for _ in 0..self.threads.min(count) {
+                s.spawn(|| work(&queue, &turn, self.slice));
+            }
+        });
+        queue
+            .into_inner()
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+}
+
+/// Run slices of the waiting VMs until they're all done
+fn work(queue: &Mutex<Queue>, turn: &Condvar, slice: u64) {
+    loop {
+        let mut q = queue.lock().unwrap();
+        let (i, mut vm) = loop {
+            if let Some(pos) = q.waiting.iter().position(|(_, vm)| !vm.is_blocked()) {
+                break q.waiting.remove(pos).unwrap();
+            }
+            if q.running == 0 {
+                // Nothing can send a message to the blocked ones anymore
+                while let Some((i, vm)) = q.waiting.pop_front() {
+                    q.results[i] = Some((vm, Ok(ExecutionOutcome::Blocked)));
+                }
+                turn.notify_all();
+                return;
+            }
+            q = turn.wait(q).unwrap();
+        };
+        q.running += 1;
+        drop(q);
+
+        let outcome = vm.run_for(slice);
+
+        let mut q = queue.lock().unwrap();
+        q.running -= 1;
+        match outcome {
+            Ok(ExecutionOutcome::Paused | ExecutionOutcome::Blocked) => {
+                q.waiting.push_back((i, vm))
+            }
+            outcome => q.results[i] = Some((vm, outcome)),
+        }
+        turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::compiler::{assemble, EXAMPLES};
+    use crate::memory::object_map::Memory;
+    use crate::runtime::channel::Channel;
+
+    /// A VM running `source`, given the `channels` in order
+    fn vm(source: &str, channels: &[&Channel]) -> VM {
+        let program = assemble(source);
+        let mut vm = VM::new(16, vec![]);
+        let ins = vm.link(&program).unwrap();
+        vm.load(ins);
+        for channel in channels {
+            vm.add_channel((*channel).clone());
+        }
+        vm
+    }
+
+    fn example(name: &str) -> &'static str {
+        EXAMPLES.iter().find(|(n, _)| *n == name).unwrap().1
+    }
+
+    #[test]
+    fn ping_pong() {
+        let to_pong = Channel::new();
+        let to_ping = Channel::new();
+        let ping = ".section\n.code\nmain:\n    push_i $5\n    send $0\n    recv $1\n    hlt\n";
+        let pong =
+            ".section\n.code\nmain:\n    recv $0\n    push_i $1\n    add_i\n    send $1\n    hlt\n";
+        let mut workers = Workers::new(2);
+        workers.set_slice(1);
+        // Pong is added first, so it's blocked before ping sent anything
+        workers.add(vm(pong, &[&to_pong, &to_ping]));
+        workers.add(vm(ping, &[&to_pong, &to_ping]));
+        let results = workers.run();
+        for (_, outcome) in &results {
+            assert_eq!(*outcome.as_ref().unwrap(), ExecutionOutcome::Halted);
+        }
+        assert_eq!(results[1].0.stack.last().unwrap().as_i64(), 6);
+    }
+
+    #[test]
+    fn deadlocked_vms_end_blocked() {
+        let a = Channel::new();
+        let b = Channel::new();
+        // Each one waits for the other
+        let wait = ".section\n.code\nmain:\n    recv $0\n    send $1\n    hlt\n";
+        let mut workers = Workers::new(3);
+        workers.add(vm(wait, &[&a, &b]));
+        workers.add(vm(wait, &[&b, &a]));
+        workers.add(vm(".section\n.code\nmain:\n    push_i $1\n    hlt\n", &[]));
+        let outcomes: Vec<_> = workers
+            .run()
+            .into_iter()
+            .map(|(_, outcome)| outcome.unwrap())
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ExecutionOutcome::Blocked,
+                ExecutionOutcome::Blocked,
+                ExecutionOutcome::Halted
+            ]
+        );
+    }
+
+    #[test]
+    fn producers_and_consumer() {
+        let numbers = Channel::new();
+        let total = Channel::new();
+        let mut workers = Workers::new(3);
+        workers.set_slice(100);
+        workers.add(vm(example("consumer"), &[&numbers, &total]));
+        for _ in 0..4 {
+            workers.add(vm(example("producer"), &[&numbers]));
+        }
+        for (_, outcome) in workers.run() {
+            assert_eq!(outcome.unwrap(), ExecutionOutcome::Halted);
+        }
+        let mut memory = Memory::new(1);
+        let sum = total.try_recv().unwrap().paste(&mut memory).unwrap();
+        assert_eq!(sum.as_i64(), 4 * (0..1000).sum::<i64>());
+    }
+}